@@ -0,0 +1,53 @@
+//! Consultas por faixa e posição num conjunto ordenado com 1M de membros, comparadas com
+//! reordenar o conjunto a cada consulta. Rode com `cargo bench`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::collections::HashMap;
+
+#[path = "../src/scan.rs"]
+#[allow(dead_code)]
+mod scan;
+#[path = "../src/zset.rs"]
+#[allow(dead_code)]
+mod zset;
+
+use zset::{ScoreBound, ZSet};
+
+const MEMBERS: usize = 1_000_000;
+
+fn bound(value: f64) -> ScoreBound {
+    ScoreBound { value, exclusive: false }
+}
+
+fn zset_queries(c: &mut Criterion) {
+    let mut zset = ZSet::default();
+    let mut scores = HashMap::new();
+    for i in 0..MEMBERS {
+        zset.insert(format!("m{i}"), i as f64);
+        scores.insert(format!("m{i}"), i as f64);
+    }
+    let mut group = c.benchmark_group("zset_1m");
+    group.sample_size(10);
+
+    group.bench_function("zrangebyscore_100", |b| {
+        b.iter(|| zset.range_by_score(bound(500_000.0), bound(500_099.0)).count())
+    });
+    group.bench_function("zrangebyscore_top_18", |b| {
+        b.iter(|| zset.range_by_score(bound(999_982.0), bound(f64::INFINITY)).count())
+    });
+    group.bench_function("zrank_near_end", |b| b.iter(|| zset.rank(black_box("m999990"))));
+    group.bench_function("zrank_middle", |b| b.iter(|| zset.rank(black_box("m500000"))));
+    // O que cada consulta custaria se o conjunto fosse só o mapa de scores.
+    group.bench_function("full_resort", |b| {
+        b.iter(|| {
+            let mut members: Vec<(f64, &str)> =
+                scores.iter().map(|(member, score)| (*score, member.as_str())).collect();
+            members.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(b.1)));
+            members.len()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, zset_queries);
+criterion_main!(benches);
@@ -0,0 +1,302 @@
+//! Campos inteiros do `BITFIELD`: inteiros com ou sem sinal, de largura arbitrária, guardados
+//! numa string a partir de qualquer posição de bit, com o bit mais significativo primeiro, como
+//! no `SETBIT`.
+
+/// Tipo e posição de um campo, como `i16` no bit 100 ou `u8` em `#2` (o terceiro `u8`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Field {
+    pub signed: bool,
+    /// Largura em bits: de 1 a 64 com sinal e de 1 a 63 sem sinal, como no Redis.
+    pub bits: u32,
+    /// Posição do primeiro bit do campo na string.
+    pub offset: u64,
+}
+
+/// O que fazer quando o resultado de um `SET` ou `INCRBY` não cabe no campo (`OVERFLOW`).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Overflow {
+    /// Fica só com os bits menos significativos, como na aritmética de inteiros de largura
+    /// fixa.
+    #[default]
+    Wrap,
+    /// Satura no maior ou no menor valor do campo.
+    Sat,
+    /// Não escreve nada, e a operação responde `nil`.
+    Fail,
+}
+
+/// Uma operação de um `BITFIELD`, com o `OVERFLOW` em vigor quando ela aparece no comando.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Get(Field),
+    Set { field: Field, value: i64, overflow: Overflow },
+    IncrBy { field: Field, increment: i64, overflow: Overflow },
+}
+
+impl Field {
+    /// Lê um tipo como `i16` ou `u8`; `None` se a largura não for aceita.
+    pub fn parse_type(name: &str) -> Option<(bool, u32)> {
+        let (signed, bits) = match name.split_at_checked(1)? {
+            ("i" | "I", bits) => (true, bits),
+            ("u" | "U", bits) => (false, bits),
+            _ => return None,
+        };
+        let bits = bits.parse::<u32>().ok()?;
+        let max = if signed { 64 } else { 63 };
+        (1..=max).contains(&bits).then_some((signed, bits))
+    }
+
+    /// Nome do tipo, como `i16`.
+    pub fn type_name(&self) -> String {
+        format!("{}{}", if self.signed { 'i' } else { 'u' }, self.bits)
+    }
+
+    /// Número de bytes que a string precisa ter para conter o campo.
+    pub fn len_needed(&self) -> usize {
+        (self.offset + self.bits as u64).div_ceil(8) as usize
+    }
+
+    /// Valor do campo; bits além do fim da string valem 0.
+    pub fn get(&self, bytes: &[u8]) -> i64 {
+        let mut value = 0u64;
+        for position in self.offset..self.offset + self.bits as u64 {
+            let byte = bytes.get((position / 8) as usize).copied().unwrap_or(0);
+            value = (value << 1) | ((byte >> (7 - position % 8)) & 1) as u64;
+        }
+        // Estende o sinal a partir do bit mais significativo do campo.
+        let shift = 64 - self.bits;
+        if self.signed {
+            ((value << shift) as i64) >> shift
+        } else {
+            value as i64
+        }
+    }
+
+    /// Grava os `bits` menos significativos de `value` no campo. A string já precisa ter o
+    /// tamanho de `len_needed`.
+    fn set(&self, bytes: &mut [u8], value: i64) {
+        let value = value as u64;
+        for i in 0..self.bits as u64 {
+            let position = self.offset + i;
+            let mask = 0x80 >> (position % 8);
+            let byte = &mut bytes[(position / 8) as usize];
+            if (value >> (self.bits as u64 - 1 - i)) & 1 == 1 {
+                *byte |= mask;
+            } else {
+                *byte &= !mask;
+            }
+        }
+    }
+
+    /// Ajusta ao campo um resultado que pode não caber nele, conforme `overflow`. `None` quando
+    /// não cabe e o modo é `Fail`.
+    fn fit(&self, value: i128, overflow: Overflow) -> Option<i64> {
+        let (min, max) = if self.signed {
+            (-(1i128 << (self.bits - 1)), (1i128 << (self.bits - 1)) - 1)
+        } else {
+            (0, (1i128 << self.bits) - 1)
+        };
+        if (min..=max).contains(&value) {
+            return Some(value as i64);
+        }
+        match overflow {
+            Overflow::Fail => None,
+            Overflow::Sat => Some(value.clamp(min, max) as i64),
+            Overflow::Wrap => {
+                // A conversão para `u64` já descarta os bits acima do 64º.
+                let shift = 64 - self.bits;
+                let low = (value as u64) << shift;
+                Some(if self.signed { (low as i64) >> shift } else { (low >> shift) as i64 })
+            }
+        }
+    }
+}
+
+impl Op {
+    pub fn field(&self) -> Field {
+        match self {
+            Op::Get(field) | Op::Set { field, .. } | Op::IncrBy { field, .. } => *field,
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        !matches!(self, Op::Get(_))
+    }
+
+    /// Executa a operação sobre a string, que nas escritas já precisa ter o tamanho de
+    /// `len_needed`. Retorna o valor lido no `GET`, o anterior no `SET` e o novo no `INCRBY`;
+    /// `None` se o `OVERFLOW FAIL` impediu a escrita.
+    pub fn apply(&self, bytes: &mut [u8]) -> Option<i64> {
+        match *self {
+            Op::Get(field) => Some(field.get(bytes)),
+            Op::Set { field, value, overflow } => {
+                // Como no Redis, um valor negativo num campo sem sinal é lido como o `u64` de
+                // mesmos bits: com `SAT`, satura no máximo.
+                let value = if field.signed { value as i128 } else { value as u64 as i128 };
+                let value = field.fit(value, overflow)?;
+                let previous = field.get(bytes);
+                field.set(bytes, value);
+                Some(previous)
+            }
+            Op::IncrBy { field, increment, overflow } => {
+                let value = field.fit(field.get(bytes) as i128 + increment as i128, overflow)?;
+                field.set(bytes, value);
+                Some(value)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(signed: bool, bits: u32, offset: u64) -> Field {
+        Field { signed, bits, offset }
+    }
+
+    /// Todos os tipos aceitos: `u1` a `u63` e `i1` a `i64`.
+    fn all_types() -> impl Iterator<Item = (bool, u32)> {
+        (1..=63).map(|bits| (false, bits)).chain((1..=64).map(|bits| (true, bits)))
+    }
+
+    fn limits(field: Field) -> (i128, i128) {
+        if field.signed {
+            (-(1i128 << (field.bits - 1)), (1i128 << (field.bits - 1)) - 1)
+        } else {
+            (0, (1i128 << field.bits) - 1)
+        }
+    }
+
+    /// Leitura de referência: os 16 primeiros bytes como um inteiro big-endian.
+    fn reference_get(bytes: &[u8], field: Field) -> i128 {
+        let window = u128::from_be_bytes(bytes[..16].try_into().unwrap());
+        let shift = 128 - field.offset - field.bits as u64;
+        let raw = (window >> shift) & ((1u128 << field.bits) - 1);
+        if field.signed && raw >> (field.bits - 1) == 1 {
+            raw as i128 - (1i128 << field.bits)
+        } else {
+            raw as i128
+        }
+    }
+
+    #[test]
+    fn parse_type_accepts_redis_widths() {
+        assert!(all_types().all(|(signed, bits)| {
+            let name = format!("{}{}", if signed { 'i' } else { 'u' }, bits);
+            Field::parse_type(&name) == Some((signed, bits))
+        }));
+        assert_eq!(Field::parse_type("U8"), Some((false, 8)));
+        for invalid in ["u64", "i65", "u0", "i0", "x8", "u", "", "u-1"] {
+            assert_eq!(Field::parse_type(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn set_and_get_every_type_at_unaligned_offsets() {
+        for (signed, bits) in all_types() {
+            // Alinhado, dentro de um byte e atravessando vários bytes.
+            for offset in [0, 1, 3, 7, 8, 13, 31, 64 - bits as u64] {
+                let field = field(signed, bits, offset);
+                let (min, max) = limits(field);
+                let values = [min, max, 0, 1, min / 3, max / 3];
+                for value in values.into_iter().filter(|value| (min..=max).contains(value)) {
+                    // Bits alternados em volta mostram se o campo invadiu os vizinhos.
+                    let mut bytes = vec![0b1010_0101; 16];
+                    let before = bytes.clone();
+                    field.set(&mut bytes, value as i64);
+                    let name = field.type_name();
+                    assert_eq!(field.get(&bytes) as i128, value, "{name} em {offset}");
+                    assert_eq!(reference_get(&bytes, field), value);
+                    let inside = offset..offset + bits as u64;
+                    for position in (0..128).filter(|position| !inside.contains(position)) {
+                        let (byte, shift) = ((position / 8) as usize, 7 - position % 8);
+                        let bit = |bytes: &[u8]| (bytes[byte] >> shift) & 1;
+                        assert_eq!(bit(&bytes), bit(&before), "bit {position} alterado");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn get_past_the_end_reads_zeros() {
+        let bytes = [0xff];
+        assert_eq!(field(false, 8, 4).get(&bytes), 0xf0);
+        assert_eq!(field(true, 8, 4).get(&bytes), -16);
+        assert_eq!(field(false, 16, 8).get(&bytes), 0);
+        assert_eq!(field(true, 64, 1000).get(&[]), 0);
+    }
+
+    #[test]
+    fn len_needed_covers_the_last_bit() {
+        assert_eq!(field(false, 8, 0).len_needed(), 1);
+        assert_eq!(field(false, 8, 1).len_needed(), 2);
+        assert_eq!(field(true, 1, 7).len_needed(), 1);
+        assert_eq!(field(true, 64, 100).len_needed(), 21);
+    }
+
+    #[test]
+    fn fit_at_the_limits() {
+        for (signed, bits) in all_types() {
+            let field = field(signed, bits, 0);
+            let (min, max) = limits(field);
+            for overflow in [Overflow::Wrap, Overflow::Sat, Overflow::Fail] {
+                assert_eq!(field.fit(min, overflow), Some(min as i64));
+                assert_eq!(field.fit(max, overflow), Some(max as i64));
+            }
+            assert_eq!(field.fit(max + 1, Overflow::Wrap), Some(min as i64));
+            assert_eq!(field.fit(min - 1, Overflow::Wrap), Some(max as i64));
+            assert_eq!(field.fit(max + 1, Overflow::Sat), Some(max as i64));
+            assert_eq!(field.fit(min - 1, Overflow::Sat), Some(min as i64));
+            assert_eq!(field.fit(max + 1, Overflow::Fail), None);
+            assert_eq!(field.fit(min - 1, Overflow::Fail), None);
+        }
+        // Com `WRAP`, só os bits do campo contam, mesmo bem longe dos limites.
+        assert_eq!(field(false, 8, 0).fit(0x1_23, Overflow::Wrap), Some(0x23));
+        assert_eq!(field(true, 8, 0).fit(-129 - 256, Overflow::Wrap), Some(127));
+        let tripled = field(true, 64, 0).fit(i64::MAX as i128 * 3, Overflow::Wrap);
+        assert_eq!(tripled, Some(i64::MAX - 2));
+    }
+
+    #[test]
+    fn apply_returns_what_redis_returns() {
+        let u8_at = |offset| field(false, 8, offset);
+        let mut bytes = vec![0; 2];
+
+        let set = Op::Set { field: u8_at(4), value: 200, overflow: Overflow::Wrap };
+        assert_eq!(set.apply(&mut bytes), Some(0), "o SET responde o valor anterior");
+        assert_eq!(bytes, [0x0c, 0x80]);
+        assert_eq!(Op::Get(u8_at(4)).apply(&mut bytes), Some(200));
+
+        let incr = |increment, overflow| Op::IncrBy { field: u8_at(4), increment, overflow };
+        // O `INCRBY` responde o valor novo.
+        assert_eq!(incr(55, Overflow::Wrap).apply(&mut bytes), Some(255));
+        assert_eq!(incr(1, Overflow::Wrap).apply(&mut bytes), Some(0));
+        assert_eq!(incr(-1, Overflow::Sat).apply(&mut bytes), Some(0));
+        assert_eq!(incr(300, Overflow::Sat).apply(&mut bytes), Some(255));
+        assert_eq!(incr(1, Overflow::Fail).apply(&mut bytes), None);
+        assert_eq!(bytes, [0x0f, 0xf0], "o FAIL não escreve nada");
+
+        let fail = Op::Set { field: u8_at(4), value: 256, overflow: Overflow::Fail };
+        assert_eq!(fail.apply(&mut bytes), None);
+        assert_eq!(bytes, [0x0f, 0xf0]);
+
+        // Um negativo num campo sem sinal é lido como o `u64` de mesmos bits.
+        let negative = |overflow| Op::Set { field: u8_at(0), value: -1, overflow };
+        assert_eq!(negative(Overflow::Sat).apply(&mut bytes), Some(0x0f));
+        assert_eq!(Op::Get(u8_at(0)).apply(&mut bytes), Some(255));
+        assert_eq!(negative(Overflow::Fail).apply(&mut bytes), None);
+        assert_eq!(negative(Overflow::Wrap).apply(&mut bytes), Some(255));
+
+        let signed = |increment, overflow| Op::IncrBy {
+            field: field(true, 4, 12),
+            increment,
+            overflow,
+        };
+        assert_eq!(signed(7, Overflow::Wrap).apply(&mut [0, 0]), Some(7));
+        assert_eq!(signed(8, Overflow::Wrap).apply(&mut [0, 0]), Some(-8));
+        assert_eq!(signed(-9, Overflow::Sat).apply(&mut [0, 0]), Some(-8));
+        assert_eq!(signed(8, Overflow::Fail).apply(&mut [0, 0]), None);
+    }
+}
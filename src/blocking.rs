@@ -0,0 +1,143 @@
+//! Registro dos clientes parados em comandos bloqueantes como o `BLPOP` e o `BZPOPMIN`.
+//!
+//! Cada banco tem uma fila por chave, na ordem em que os clientes chegaram. Quem atende os
+//! clientes é a task de escrita: depois de aplicar um comando que deixa elementos numa chave
+//! aguardada, ela executa ali mesmo, sob o lock de escrita, o comando de cada cliente da fila.
+//! Assim nenhum outro escritor pega o elemento no meio do caminho.
+
+use crate::data_types::{Command, ListEnd, Reply, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tokio::sync::oneshot;
+
+/// Chave que atendeu o cliente e o resultado do comando executado nela.
+pub type Served = (String, Result<Reply, &'static str>);
+
+/// O que fazer por um cliente bloqueado quando uma das chaves que ele aguarda recebe elementos.
+#[derive(Debug, Clone)]
+pub enum BlockedAction {
+    /// `BLPOP`/`BRPOP`: retira um elemento da ponta indicada.
+    Pop(ListEnd),
+    /// `BLMOVE`/`BRPOPLPUSH`: move um elemento para `destination`, como o `LMOVE`.
+    Move {
+        destination: String,
+        from: ListEnd,
+        to: ListEnd,
+    },
+    /// `BZPOPMIN`/`BZPOPMAX`: retira o membro de menor (ou, com `max`, maior) score.
+    ZPop { max: bool },
+}
+
+impl BlockedAction {
+    /// Indica se a ação consome valores do tipo de `value`: listas para o `BLPOP` e o
+    /// `BLMOVE`, conjuntos ordenados para o `BZPOPMIN`.
+    pub fn accepts(&self, value: &Value) -> bool {
+        match self {
+            BlockedAction::Pop(_) | BlockedAction::Move { .. } => matches!(value, Value::List(_)),
+            BlockedAction::ZPop { .. } => matches!(value, Value::ZSet(_)),
+        }
+    }
+
+    /// Comando que atende o cliente a partir de `key`.
+    pub fn command(&self, key: String) -> Command {
+        match self {
+            BlockedAction::Pop(end) => Command::Pop { key, count: 1, end: *end },
+            BlockedAction::Move { destination, from, to } => Command::LMove {
+                source: key,
+                destination: destination.clone(),
+                from: *from,
+                to: *to,
+            },
+            BlockedAction::ZPop { max } => Command::ZPop { key, count: 1, max: *max },
+        }
+    }
+}
+
+/// Um cliente bloqueado, registrado em cada uma das chaves que aguarda.
+pub struct BlockedClient {
+    pub action: BlockedAction,
+    /// Quem retira o canal é quem resolve o bloqueio: a task de escrita, ao atender o cliente,
+    /// ou o próprio cliente, ao desistir por timeout. Nunca os dois.
+    reply_tx: Mutex<Option<oneshot::Sender<Served>>>,
+}
+
+impl BlockedClient {
+    pub fn new(action: BlockedAction) -> (Arc<Self>, oneshot::Receiver<Served>) {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let client = Self {
+            action,
+            reply_tx: Mutex::new(Some(reply_tx)),
+        };
+        (Arc::new(client), reply_rx)
+    }
+
+    /// Retira o canal de resposta, ou `None` se o bloqueio já tiver sido resolvido.
+    pub fn take_reply_tx(&self) -> Option<oneshot::Sender<Served>> {
+        self.reply_tx.lock().unwrap_or_else(PoisonError::into_inner).take()
+    }
+}
+
+/// Filas de clientes bloqueados de um banco, por chave.
+#[derive(Default)]
+pub struct BlockedClients {
+    /// Travado sempre depois de `data` e `metadata`, e só por instantes.
+    queues: Mutex<HashMap<String, VecDeque<Arc<BlockedClient>>>>,
+}
+
+impl BlockedClients {
+    fn queues(&self) -> MutexGuard<'_, HashMap<String, VecDeque<Arc<BlockedClient>>>> {
+        self.queues.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Coloca o cliente no fim da fila de cada chave. Ele sai das filas quando o registro
+    /// retornado é descartado, seja qual for o motivo: atendimento, timeout ou desconexão.
+    pub fn register<'a>(
+        &'a self,
+        keys: &'a [String],
+        client: Arc<BlockedClient>,
+    ) -> Registration<'a> {
+        let mut queues = self.queues();
+        for key in keys {
+            queues.entry(key.clone()).or_default().push_back(client.clone());
+        }
+        Registration { clients: self, keys, client }
+    }
+
+    /// Retira o primeiro cliente da fila da chave cuja ação `accepts` aceitar. Os que vêm antes
+    /// continuam na fila, esperando por um valor do tipo que consomem.
+    pub fn next(
+        &self,
+        key: &str,
+        accepts: impl Fn(&BlockedAction) -> bool,
+    ) -> Option<Arc<BlockedClient>> {
+        let mut queues = self.queues();
+        let queue = queues.get_mut(key)?;
+        let position = queue.iter().position(|client| accepts(&client.action))?;
+        let client = queue.remove(position);
+        if queue.is_empty() {
+            queues.remove(key);
+        }
+        client
+    }
+}
+
+/// Mantém um cliente nas filas das chaves enquanto existir.
+pub struct Registration<'a> {
+    clients: &'a BlockedClients,
+    keys: &'a [String],
+    client: Arc<BlockedClient>,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        let mut queues = self.clients.queues();
+        for key in self.keys {
+            if let Some(queue) = queues.get_mut(key) {
+                queue.retain(|client| !Arc::ptr_eq(client, &self.client));
+                if queue.is_empty() {
+                    queues.remove(key);
+                }
+            }
+        }
+    }
+}
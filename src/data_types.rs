@@ -1,14 +1,115 @@
-use std::collections::{HashMap, HashSet, VecDeque};
-use std::time::{Duration, SystemTime};
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use serde::{Deserialize, Serialize};
+use crate::bitfield;
+use crate::scan::{ScanMap, ScanSet};
+use crate::zset::ZSet;
 
 /// Enum que representa os diferentes tipos de valores que podem ser armazenados.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Value {
     String(String),
+    /// String cujo conteúdo é um inteiro, guardada em forma numérica para que comandos como
+    /// `INCR` não precisem fazer parse e formatação a cada chamada. Para os clientes é
+    /// indistinguível de uma `String`.
+    Int(i64),
     List(VecDeque<String>),
-    Set(HashSet<String>),
-    Hash(HashMap<String, String>),
+    Set(ScanSet),
+    Hash(ScanMap<String>),
+    ZSet(ZSet),
+    /// String guardada como bytes, que podem não ser UTF-8 válido: é a representação dos
+    /// bitmaps do `SETBIT`. Para os clientes também é indistinguível de uma `String`. Fica por
+    /// último para não mudar os índices das variantes nos payloads de `DUMP` já gerados.
+    Bytes(Vec<u8>),
+}
+
+impl Value {
+    /// Indica se o valor é do tipo string, em qualquer representação interna.
+    pub fn is_string(&self) -> bool {
+        matches!(self, Value::String(_) | Value::Int(_) | Value::Bytes(_))
+    }
+
+    /// Nome do tipo como reportado pelo comando `TYPE`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) | Value::Int(_) | Value::Bytes(_) => "string",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::Hash(_) => "hash",
+            Value::ZSet(_) => "zset",
+        }
+    }
+
+    /// Representação interna como reportada pelo `OBJECT ENCODING`, usando os nomes do Redis.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            // O Redis converte no `SET` strings que são inteiros canônicos; aqui elas continuam
+            // em texto até o primeiro `INCR`, mas são reportadas como o Redis reportaria.
+            Value::String(s) if s.parse::<i64>().is_ok_and(|i| i.to_string() == *s) => "int",
+            // O Redis guarda strings de até 44 bytes junto com o cabeçalho do objeto.
+            Value::String(s) if s.len() <= 44 => "embstr",
+            Value::String(_) => "raw",
+            Value::Bytes(bytes) if bytes.len() <= 44 => "embstr",
+            Value::Bytes(_) => "raw",
+            Value::List(_) => "quicklist",
+            Value::Set(_) | Value::Hash(_) => "hashtable",
+            Value::ZSet(_) => "skiplist",
+        }
+    }
+
+    /// Conteúdo de um valor do tipo string; `None` para os demais tipos. Bytes que não são
+    /// UTF-8 válido viram `U+FFFD`: quem precisa do conteúdo exato usa `as_bytes`.
+    pub fn as_str(&self) -> Option<Cow<'_, str>> {
+        match self {
+            Value::String(s) => Some(Cow::Borrowed(s)),
+            Value::Int(i) => Some(Cow::Owned(i.to_string())),
+            Value::Bytes(bytes) => Some(String::from_utf8_lossy(bytes)),
+            _ => None,
+        }
+    }
+
+    /// Versão de `as_str` que consome o valor.
+    pub fn into_string(self) -> Option<String> {
+        match self {
+            Value::String(s) => Some(s),
+            Value::Int(i) => Some(i.to_string()),
+            Value::Bytes(bytes) => Some(String::from_utf8_lossy(&bytes).into_owned()),
+            _ => None,
+        }
+    }
+
+    /// Bytes de um valor do tipo string, exatamente como o cliente os veria; `None` para os
+    /// demais tipos.
+    pub fn as_bytes(&self) -> Option<Cow<'_, [u8]>> {
+        match self {
+            Value::String(s) => Some(Cow::Borrowed(s.as_bytes())),
+            Value::Int(i) => Some(Cow::Owned(i.to_string().into_bytes())),
+            Value::Bytes(bytes) => Some(Cow::Borrowed(bytes)),
+            _ => None,
+        }
+    }
+
+    /// Versão de `as_bytes` que consome o valor.
+    pub fn into_bytes(self) -> Option<Vec<u8>> {
+        match self {
+            Value::String(s) => Some(s.into_bytes()),
+            Value::Int(i) => Some(i.to_string().into_bytes()),
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// String com o conteúdo dado: em texto quando os bytes são UTF-8 válido, e como `Bytes`
+    /// caso contrário.
+    pub fn from_bytes(bytes: Vec<u8>) -> Value {
+        match String::from_utf8(bytes) {
+            Ok(s) => Value::String(s),
+            Err(e) => Value::Bytes(e.into_bytes()),
+        }
+    }
 }
 
 /// Enum que representa os comandos que modificam o estado. Usado no canal de comunicação.
@@ -17,21 +118,585 @@ pub enum Command {
     Set {
         key: String,
         value: Value,
-        expiry: Option<Duration>,
+        expiry: SetExpiry,
+        condition: SetCondition,
+        /// Opção `GET`: responde com o valor anterior da chave.
+        get: bool,
     },
+    /// Define um ou mais campos do hash, todos na mesma operação.
     HSet {
+        key: String,
+        pairs: Vec<(String, String)>,
+    },
+    /// Define o campo só se ele ainda não existir no hash.
+    HSetNx {
         key: String,
         field: String,
         value: String,
     },
+    HDel {
+        key: String,
+        fields: Vec<String>,
+    },
+    /// Define o instante de expiração de campos de um hash, já convertido em absoluto como no
+    /// `Expire`. Um prazo que já passou remove os campos na hora.
+    HExpire {
+        key: String,
+        fields: Vec<String>,
+        at: SystemTime,
+        condition: ExpireCondition,
+    },
+    /// Remove o TTL de campos de um hash.
+    HPersist {
+        key: String,
+        fields: Vec<String>,
+    },
+    /// `LPUSH`/`RPUSH`: insere os elementos, um de cada vez e na ordem dada, na ponta `end` da
+    /// lista, criando-a se necessário. Com `if_exists` (`LPUSHX`/`RPUSHX`), uma chave
+    /// inexistente não é criada.
+    Push {
+        key: String,
+        elements: Vec<String>,
+        end: ListEnd,
+        if_exists: bool,
+    },
+    /// `LPOP`/`RPOP`: remove até `count` elementos da ponta `end` da lista.
+    Pop {
+        key: String,
+        count: usize,
+        end: ListEnd,
+    },
+    /// `LMPOP`: retira até `count` elementos da ponta `end` da primeira das `keys` que guardar
+    /// uma lista.
+    MPop {
+        keys: Vec<String>,
+        end: ListEnd,
+        count: usize,
+    },
+    /// Substitui o elemento na posição `index`; negativos contam a partir do fim.
+    LSet {
+        key: String,
+        index: i64,
+        element: String,
+    },
+    /// Insere `element` antes (ou depois) da primeira ocorrência de `pivot`, a partir da cabeça.
+    LInsert {
+        key: String,
+        before: bool,
+        pivot: String,
+        element: String,
+    },
+    /// Remove até `count` ocorrências de `element`: a partir da cabeça se positivo, da cauda se
+    /// negativo e todas se zero.
+    LRem {
+        key: String,
+        count: i64,
+        element: String,
+    },
+    /// Mantém só os elementos entre `start` e `stop` (inclusivos), com os índices do `LRANGE`.
+    LTrim {
+        key: String,
+        start: i64,
+        stop: i64,
+    },
+    /// Retira um elemento da ponta `from` de `source` e o insere na ponta `to` de
+    /// `destination`, que pode ser a mesma lista.
+    LMove {
+        source: String,
+        destination: String,
+        from: ListEnd,
+        to: ListEnd,
+    },
+    /// Adiciona membros ao conjunto, criando-o se necessário.
+    SAdd {
+        key: String,
+        members: Vec<String>,
+    },
+    /// Remove membros do conjunto; o conjunto vazio deixa de existir.
+    SRem {
+        key: String,
+        members: Vec<String>,
+    },
+    /// Remove até `count` membros sorteados do conjunto.
+    SPop {
+        key: String,
+        count: usize,
+    },
+    /// Define o score de cada membro do conjunto ordenado, criando-o se necessário, conforme
+    /// as `options`.
+    ZAdd {
+        key: String,
+        pairs: Vec<(f64, String)>,
+        options: ZAddOptions,
+    },
+    /// Soma `increment` ao score do membro, criando o membro e a chave se necessário.
+    ZIncrBy {
+        key: String,
+        member: String,
+        increment: f64,
+    },
+    /// `ZPOPMIN`/`ZPOPMAX`: remove até `count` membros de menor (ou, com `max`, maior) score.
+    ZPop {
+        key: String,
+        count: usize,
+        max: bool,
+    },
+    /// Remove membros do conjunto ordenado; o conjunto vazio deixa de existir.
+    ZRem {
+        key: String,
+        members: Vec<String>,
+    },
+    /// Tira `member` de `source` e o coloca em `destination`.
+    SMove {
+        source: String,
+        destination: String,
+        member: String,
+    },
+    HIncrBy {
+        key: String,
+        field: String,
+        delta: i64,
+    },
+    HIncrByFloat {
+        key: String,
+        field: String,
+        increment: f64,
+    },
     Delete {
         key: String,
     },
-   
+    /// Remove a chave se o seu TTL já tiver passado, ou os campos do hash cujo TTL já passou.
+    /// Enviado pelas leituras que encontram algo expirado; se a chave tiver sido escrita de novo
+    /// nesse meio-tempo, nada acontece.
+    DeleteExpired {
+        key: String,
+    },
+    /// Como o `Delete`, mas valores grandes são liberados fora da task de escrita.
+    Unlink {
+        key: String,
+    },
+    IncrBy {
+        key: String,
+        delta: i64,
+    },
+    IncrByFloat {
+        key: String,
+        increment: f64,
+    },
+    Append {
+        key: String,
+        value: String,
+    },
+    SetRange {
+        key: String,
+        offset: usize,
+        value: String,
+    },
+    /// `BITFIELD` com ao menos uma escrita: executa as operações em ordem sobre a string.
+    /// Comandos só com `GET` são leituras e não passam por aqui.
+    BitField {
+        key: String,
+        ops: Vec<bitfield::Op>,
+    },
+    /// Registra os elementos no HyperLogLog da chave, criando-o se necessário (`PFADD`).
+    PfAdd {
+        key: String,
+        elements: Vec<String>,
+    },
+    /// Grava em `destination` a união dos HyperLogLogs de `keys` com o do próprio destino
+    /// (`PFMERGE`).
+    PfMerge {
+        destination: String,
+        keys: Vec<String>,
+    },
+    /// Define o bit na posição `offset` da string, contando a partir do bit mais significativo
+    /// do primeiro byte, como o `SETBIT`.
+    SetBit {
+        key: String,
+        offset: u64,
+        bit: bool,
+    },
+    GetSet {
+        key: String,
+        value: String,
+    },
+    MSet {
+        pairs: Vec<(String, String)>,
+    },
+    MSetNx {
+        pairs: Vec<(String, String)>,
+    },
+    GetDel {
+        key: String,
+    },
+    GetEx {
+        key: String,
+        ttl: TtlUpdate,
+    },
+    /// Define o instante de expiração de uma chave existente. Prazos relativos (`EXPIRE`) já
+    /// chegam convertidos em absolutos, para que a reprodução seja determinística.
+    Expire {
+        key: String,
+        at: SystemTime,
+        condition: ExpireCondition,
+    },
+    Persist {
+        key: String,
+    },
+    /// Move o valor e o TTL de `key` para `new_key`. Com `nx`, só se `new_key` não existir.
+    Rename {
+        key: String,
+        new_key: String,
+        nx: bool,
+    },
+    /// Move a chave, com o TTL, para o banco `db`, se ela não existir lá.
+    Move {
+        key: String,
+        db: usize,
+    },
+    /// Troca o conteúdo de dois bancos inteiros.
+    SwapDb {
+        first: usize,
+        second: usize,
+    },
+    /// `SORT ... STORE`: ordena a lista ou o conjunto em `key` e grava o resultado como uma
+    /// lista em `destination`.
+    SortStore {
+        key: String,
+        destination: String,
+        options: SortOptions,
+    },
+    /// `SINTERSTORE`/`SUNIONSTORE`/`SDIFFSTORE`: combina os conjuntos de `keys` e grava o
+    /// resultado em `destination`, que pode ser uma delas.
+    SetStore {
+        op: SetOp,
+        destination: String,
+        keys: Vec<String>,
+    },
+    /// `ZUNIONSTORE`/`ZINTERSTORE`: combina os conjuntos (ordenados ou não) de `keys`, com os
+    /// scores multiplicados pelos `weights` e juntados por `aggregate`, e grava o resultado em
+    /// `destination`, que pode ser uma delas.
+    ZSetStore {
+        op: SetOp,
+        destination: String,
+        keys: Vec<String>,
+        weights: Vec<f64>,
+        aggregate: Aggregate,
+    },
+    /// `BITOP`: combina byte a byte as strings de `keys` e grava o resultado em `destination`,
+    /// que pode ser uma delas.
+    BitOp {
+        op: BitOp,
+        destination: String,
+        keys: Vec<String>,
+    },
+    /// Recria uma chave a partir de um valor vindo de um `DUMP`. Sem `replace`, falha se a
+    /// chave já existir.
+    Restore {
+        key: String,
+        value: Value,
+        expiry: Option<SystemTime>,
+        replace: bool,
+    },
 }
 
-/// Metadados associados a uma chave, como o tempo de expiração.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Command {
+    /// Chaves que o comando lê ou escreve no banco em que é aplicado.
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::Set { key, .. }
+            | Command::HSet { key, .. }
+            | Command::HSetNx { key, .. }
+            | Command::HDel { key, .. }
+            | Command::HExpire { key, .. }
+            | Command::HPersist { key, .. }
+            | Command::Push { key, .. }
+            | Command::Pop { key, .. }
+            | Command::LSet { key, .. }
+            | Command::LInsert { key, .. }
+            | Command::LRem { key, .. }
+            | Command::LTrim { key, .. }
+            | Command::SAdd { key, .. }
+            | Command::SRem { key, .. }
+            | Command::SPop { key, .. }
+            | Command::ZAdd { key, .. }
+            | Command::ZRem { key, .. }
+            | Command::ZIncrBy { key, .. }
+            | Command::ZPop { key, .. }
+            | Command::HIncrBy { key, .. }
+            | Command::HIncrByFloat { key, .. }
+            | Command::Delete { key }
+            | Command::DeleteExpired { key }
+            | Command::Unlink { key }
+            | Command::IncrBy { key, .. }
+            | Command::IncrByFloat { key, .. }
+            | Command::Append { key, .. }
+            | Command::SetRange { key, .. }
+            | Command::SetBit { key, .. }
+            | Command::BitField { key, .. }
+            | Command::PfAdd { key, .. }
+            | Command::GetSet { key, .. }
+            | Command::GetDel { key }
+            | Command::GetEx { key, .. }
+            | Command::Expire { key, .. }
+            | Command::Persist { key }
+            | Command::Move { key, .. }
+            | Command::Restore { key, .. } => vec![key],
+            Command::Rename { key, new_key, .. } => vec![key, new_key],
+            Command::SortStore { key, destination, .. } => vec![key, destination],
+            Command::LMove { source, destination, .. }
+            | Command::SMove { source, destination, .. } => vec![source, destination],
+            Command::MPop { keys, .. } => keys.iter().map(String::as_str).collect(),
+            Command::SetStore { destination, keys, .. }
+            | Command::ZSetStore { destination, keys, .. }
+            | Command::BitOp { destination, keys, .. }
+            | Command::PfMerge { destination, keys } => std::iter::once(destination)
+                .chain(keys)
+                .map(String::as_str)
+                .collect(),
+            Command::MSet { pairs } | Command::MSetNx { pairs } => {
+                pairs.iter().map(|(key, _)| key.as_str()).collect()
+            }
+            Command::SwapDb { .. } => Vec::new(),
+        }
+    }
+}
+
+/// Opções do `SORT`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SortOptions {
+    /// Ordem decrescente (`DESC`).
+    pub desc: bool,
+    /// Compara os elementos como texto em vez de números (`ALPHA`).
+    pub alpha: bool,
+    /// Deslocamento e quantidade (`LIMIT offset count`). Uma quantidade negativa pega todo o
+    /// restante.
+    pub limit: Option<(i64, i64)>,
+}
+
+/// Opções do `ZADD`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ZAddOptions {
+    /// Só adiciona membros novos (`NX`).
+    pub nx: bool,
+    /// Só atualiza membros existentes (`XX`).
+    pub xx: bool,
+    /// Só atualiza um membro se o novo score for maior (`GT`) ou menor (`LT`) que o atual.
+    /// Membros novos são adicionados normalmente.
+    pub gt: bool,
+    pub lt: bool,
+    /// Conta também os membros cujo score mudou (`CH`).
+    pub ch: bool,
+    /// Soma o score ao atual, como o `ZINCRBY` (`INCR`); só com um par.
+    pub incr: bool,
+}
+
+/// Condições para um `EXPIRE` ser aplicado, comparadas com o TTL atual da chave. Uma chave sem
+/// TTL é tratada como tendo TTL infinito para `gt` e `lt`, como no Redis.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ExpireCondition {
+    /// Só se a chave não tiver TTL.
+    pub nx: bool,
+    /// Só se a chave já tiver TTL.
+    pub xx: bool,
+    /// Só se o novo prazo for maior que o atual.
+    pub gt: bool,
+    /// Só se o novo prazo for menor que o atual.
+    pub lt: bool,
+}
+
+impl ExpireCondition {
+    /// Indica se a condição impede trocar o prazo `current` por `at`.
+    pub fn blocks(&self, current: Option<SystemTime>, at: SystemTime) -> bool {
+        (self.nx && current.is_some())
+            || (self.xx && current.is_none())
+            || (self.gt && current.is_none_or(|current| at <= current))
+            || (self.lt && current.is_some_and(|current| at >= current))
+    }
+}
+
+/// Ponta de uma lista em que um comando atua: `Left` é a cabeça e `Right`, a cauda.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+/// Operação entre conjuntos de `SINTER`, `SUNION` e `SDIFF`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetOp {
+    Inter,
+    Union,
+    /// Membros do primeiro conjunto que não estão em nenhum dos seguintes.
+    Diff,
+}
+
+/// Operação do `BITOP`. `Not` só aceita uma origem.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+/// Como `ZUNIONSTORE` e `ZINTERSTORE` juntam os scores de um membro presente em mais de uma
+/// chave (`AGGREGATE`).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Aggregate {
+    #[default]
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    /// Junta o score acumulado com o de mais uma chave. Como no Redis, `inf + -inf` vira 0.
+    pub fn apply(self, current: f64, score: f64) -> f64 {
+        match self {
+            Aggregate::Sum => {
+                let sum = current + score;
+                if sum.is_nan() {
+                    0.0
+                } else {
+                    sum
+                }
+            }
+            Aggregate::Min => current.min(score),
+            Aggregate::Max => current.max(score),
+        }
+    }
+}
+
+/// Alteração do TTL de uma chave sem mexer no seu valor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TtlUpdate {
+    ExpireAt(SystemTime),
+    Persist,
+}
+
+/// O que um `SET` faz com o TTL da chave.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetExpiry {
+    /// Remove o TTL anterior (comportamento padrão).
+    Clear,
+    /// Mantém o TTL anterior (opção `KEEPTTL`).
+    Keep,
+    /// Expira após a duração informada (opções `EX` e `PX`).
+    After(Duration),
+    /// Expira no instante informado (opções `EXAT` e `PXAT`).
+    At(SystemTime),
+}
+
+/// Condição para um `SET` ser aplicado (opções `NX` e `XX`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetCondition {
+    Always,
+    IfNotExists,
+    IfExists,
+}
+
+/// Resultado devolvido pela task de escrita após aplicar um `Command`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Ok,
+    Integer(i64),
+    Bulk(String),
+    /// Valor de uma string, byte a byte, como no `GETSET`: um bitmap não passa por UTF-8.
+    Value(Vec<u8>),
+    Nil,
+    /// Um código por item, como no `HEXPIRE`.
+    Integers(Vec<i64>),
+    /// Um resultado por operação do `BITFIELD`; `None` onde o `OVERFLOW FAIL` impediu a escrita.
+    Fields(Vec<Option<i64>>),
+    /// Elementos retirados de uma coleção, como no `LPOP` com contagem.
+    Elements(Vec<String>),
+    /// Membros retirados de um conjunto ordenado com os seus scores, como no `ZPOPMIN`.
+    Scored(Vec<(String, f64)>),
+    /// Elementos retirados de uma entre várias chaves, como no `LMPOP`.
+    Popped {
+        key: String,
+        elements: Vec<String>,
+    },
+    /// Resposta do `SET ... GET`: o valor anterior e se a escrita de fato aconteceu.
+    Previous {
+        value: Option<Vec<u8>>,
+        written: bool,
+    },
+}
+
+/// Metadados associados a uma chave, como o tempo de expiração e as estatísticas de acesso.
+/// Toda chave existente tem uma entrada, criada pela escrita que a criou.
+#[derive(Debug, Serialize, Deserialize)]
 pub struct KeyMetadata {
+    /// No snapshot o serde grava segundos e nanossegundos desde a época, então o instante
+    /// definido por um `PEXPIREAT` volta idêntico depois de um restart.
     pub expiry: Option<SystemTime>,
-}
\ No newline at end of file
+    /// Último acesso, em milissegundos desde a época Unix. Atômico para que as leituras possam
+    /// atualizá-lo com o lock de leitura, sem passar pela task de escrita.
+    #[serde(default = "now_millis")]
+    pub last_access: AtomicU64,
+    /// Quantas vezes a chave foi lida ou escrita.
+    #[serde(default)]
+    pub access_count: AtomicU64,
+    /// Prazos dos campos de um hash que têm TTL próprio (`HEXPIRE`). Só contém campos que
+    /// existem no hash; gravados como instantes absolutos, como o `expiry`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub field_expiries: HashMap<String, SystemTime>,
+}
+
+impl KeyMetadata {
+    /// Registra um acesso à chave.
+    pub fn touch(&self) {
+        self.last_access
+            .store(to_unix_millis(SystemTime::now()) as u64, Ordering::Relaxed);
+        self.access_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// O prazo mais próximo entre o da chave e os dos seus campos: quando a limpeza periódica
+    /// precisa olhar para ela de novo.
+    pub fn next_deadline(&self) -> Option<SystemTime> {
+        self.expiry.into_iter().chain(self.field_expiries.values().copied()).min()
+    }
+}
+
+impl Default for KeyMetadata {
+    fn default() -> Self {
+        Self {
+            expiry: None,
+            last_access: now_millis(),
+            access_count: AtomicU64::new(0),
+            field_expiries: HashMap::new(),
+        }
+    }
+}
+
+impl Clone for KeyMetadata {
+    fn clone(&self) -> Self {
+        Self {
+            expiry: self.expiry,
+            last_access: AtomicU64::new(self.last_access.load(Ordering::Relaxed)),
+            access_count: AtomicU64::new(self.access_count.load(Ordering::Relaxed)),
+            field_expiries: self.field_expiries.clone(),
+        }
+    }
+}
+
+fn now_millis() -> AtomicU64 {
+    AtomicU64::new(to_unix_millis(SystemTime::now()) as u64)
+}
+
+/// Converte um instante em milissegundos desde a época Unix. Junto com `from_unix_millis`, é a
+/// única conversão entre `SystemTime` e timestamps: usada tanto pelos comandos (`EXPIREAT`,
+/// `PXAT`...) quanto pela forma canônica `PEXPIREAT` gravada no AOF.
+pub fn to_unix_millis(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_millis() as i64,
+        Err(e) => -(e.duration().as_millis() as i64),
+    }
+}
+
+/// Converte milissegundos desde a época Unix em um instante. Valores negativos ficam na época.
+pub fn from_unix_millis(millis: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64)
+}
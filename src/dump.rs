@@ -0,0 +1,39 @@
+//! Formato do payload usado por `DUMP` e `RESTORE`: o `Value` serializado com bincode, seguido
+//! de um byte de versão e do CRC32 (little-endian) de tudo o que vem antes dele.
+//!
+//! O formato é opaco para os clientes e só precisa ser entendido por outra instância do
+//! Altilium, que é quem recebe o payload num `RESTORE`.
+
+use crate::data_types::Value;
+
+/// Versão atual do formato. Deve ser incrementada sempre que a serialização de `Value` mudar
+/// de forma incompatível.
+pub const DUMP_VERSION: u8 = 1;
+
+/// Tamanho do rodapé: byte de versão mais os quatro bytes do CRC.
+const FOOTER_LEN: usize = 1 + 4;
+
+/// Serializa um valor no formato do `DUMP`.
+pub fn serialize(value: &Value) -> Vec<u8> {
+    let mut payload = bincode::serialize(value).expect("Value sempre é serializável");
+    payload.push(DUMP_VERSION);
+    let crc = crc32fast::hash(&payload);
+    payload.extend_from_slice(&crc.to_le_bytes());
+    payload
+}
+
+/// Reconstrói o valor de um payload gerado por `serialize`, validando a versão e o CRC antes
+/// de tentar interpretar o conteúdo.
+pub fn deserialize(payload: &[u8]) -> Result<Value, &'static str> {
+    const BAD_PAYLOAD: &str = "ERR DUMP payload version or checksum are wrong";
+
+    if payload.len() < FOOTER_LEN {
+        return Err(BAD_PAYLOAD);
+    }
+    let (body, crc) = payload.split_at(payload.len() - 4);
+    let expected = u32::from_le_bytes(crc.try_into().expect("o CRC tem quatro bytes"));
+    if crc32fast::hash(body) != expected || body[body.len() - 1] != DUMP_VERSION {
+        return Err(BAD_PAYLOAD);
+    }
+    bincode::deserialize(&body[..body.len() - 1]).map_err(|_| "ERR Bad data format")
+}
@@ -0,0 +1,269 @@
+//! HyperLogLog dos comandos `PF*`, guardado numa string no formato denso do Redis: um cabeçalho
+//! de 16 bytes (`HYLL`, a codificação, três bytes livres e a cardinalidade em cache) seguido de
+//! 16384 registradores de 6 bits. Como o formato e o hash são os do Redis, o valor de um `GET`
+//! é um HyperLogLog válido também lá, e as estimativas coincidem.
+//!
+//! Só a representação densa é gerada e aceita; a esparsa do Redis é opcional e economiza memória
+//! apenas em conjuntos pequenos.
+
+/// Erro para strings que não são um HyperLogLog, com o texto do Redis.
+pub const INVALID: &str = "WRONGTYPE Key is not a valid HyperLogLog string value.";
+
+/// Bits do hash usados para escolher o registrador.
+const P: u32 = 14;
+const REGISTERS: usize = 1 << P;
+/// Bits restantes do hash, cuja sequência de zeros define o valor do registrador.
+const Q: u32 = 64 - P;
+const REGISTER_BITS: usize = 6;
+const REGISTER_MAX: u8 = (1 << REGISTER_BITS) - 1;
+const HEADER_LEN: usize = 16;
+const DENSE_LEN: usize = HEADER_LEN + REGISTERS * REGISTER_BITS / 8;
+const MAGIC: &[u8] = b"HYLL";
+const DENSE: u8 = 0;
+/// Bit do último byte da cardinalidade que marca o cache como desatualizado.
+const STALE: u8 = 1 << 7;
+
+/// Indica se a string é um HyperLogLog denso.
+pub fn is_valid(bytes: &[u8]) -> bool {
+    bytes.len() == DENSE_LEN && bytes.starts_with(MAGIC) && bytes[4] == DENSE
+}
+
+/// HyperLogLog vazio, com a cardinalidade 0 já em cache.
+pub fn new() -> Vec<u8> {
+    let mut bytes = vec![0; DENSE_LEN];
+    bytes[..4].copy_from_slice(MAGIC);
+    bytes
+}
+
+/// Registra o elemento e indica se algum registrador mudou, ou seja, se a estimativa pode ter
+/// mudado. A string já precisa ser um HyperLogLog válido.
+pub fn add(hll: &mut [u8], element: &[u8]) -> bool {
+    let hash = murmur_hash64a(element, 0xadc83b19);
+    let index = (hash & (REGISTERS as u64 - 1)) as usize;
+    // O bit extra em `Q` garante que a contagem termine em no máximo `Q + 1`.
+    let count = ((hash >> P) | (1 << Q)).trailing_zeros() as u8 + 1;
+    let registers = &mut hll[HEADER_LEN..];
+    if get_register(registers, index) >= count {
+        return false;
+    }
+    set_register(registers, index, count);
+    invalidate_cache(hll);
+    true
+}
+
+/// Registradores da união dos HyperLogLogs, um por byte: o maior de cada posição.
+pub fn merge<'a>(hlls: impl IntoIterator<Item = &'a [u8]>) -> Vec<u8> {
+    let mut registers = vec![0; REGISTERS];
+    for hll in hlls {
+        for (index, register) in registers.iter_mut().enumerate() {
+            *register = (*register).max(get_register(&hll[HEADER_LEN..], index));
+        }
+    }
+    registers
+}
+
+/// HyperLogLog denso com os registradores dados, sem cardinalidade em cache.
+pub fn from_registers(registers: &[u8]) -> Vec<u8> {
+    let mut hll = new();
+    for (index, register) in registers.iter().enumerate() {
+        set_register(&mut hll[HEADER_LEN..], index, *register);
+    }
+    invalidate_cache(&mut hll);
+    hll
+}
+
+/// Cardinalidade estimada da string. O cache do cabeçalho só é usado se estiver em dia, o que
+/// aqui acontece apenas num HyperLogLog vazio ou num valor gravado pelo Redis: nenhum comando
+/// daqui atualiza o cache, então depois de qualquer `PFADD` a estimativa é recalculada.
+pub fn cardinality(hll: &[u8]) -> u64 {
+    let cache = &hll[8..HEADER_LEN];
+    if cache[7] & STALE == 0 {
+        return u64::from_le_bytes(cache.try_into().expect("o cache tem oito bytes"));
+    }
+    count(&merge([hll]))
+}
+
+/// Estimativa da cardinalidade a partir dos registradores, com o estimador do Redis (Ertl,
+/// "New cardinality estimation algorithms for HyperLogLog sketches"), que dispensa as correções
+/// de faixa do algoritmo original.
+pub fn count(registers: &[u8]) -> u64 {
+    let m = REGISTERS as f64;
+    let mut histogram = [0u32; 64];
+    for register in registers {
+        histogram[*register as usize] += 1;
+    }
+    let mut z = m * tau((m - histogram[Q as usize + 1] as f64) / m);
+    for j in (1..=Q as usize).rev() {
+        z += histogram[j] as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    // 0.5 / ln(2): o `alfa` do estimador para `m` infinito.
+    const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+    (ALPHA_INF * m * m / z).round() as u64
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let (mut y, mut z) = (1.0, x);
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if z == previous {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let (mut y, mut z) = (1.0, 1.0 - x);
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z == previous {
+            return z / 3.0;
+        }
+    }
+}
+
+fn invalidate_cache(hll: &mut [u8]) {
+    hll[HEADER_LEN - 1] |= STALE;
+}
+
+/// Os registradores ficam em sequência, do bit menos significativo de cada byte para o mais
+/// significativo, como no Redis; um registrador pode ocupar dois bytes.
+fn get_register(registers: &[u8], index: usize) -> u8 {
+    let (byte, shift) = (index * REGISTER_BITS / 8, index * REGISTER_BITS % 8);
+    let low = registers[byte] as u16;
+    let high = registers.get(byte + 1).copied().unwrap_or(0) as u16;
+    (((low | (high << 8)) >> shift) as u8) & REGISTER_MAX
+}
+
+fn set_register(registers: &mut [u8], index: usize, value: u8) {
+    let (byte, shift) = (index * REGISTER_BITS / 8, index * REGISTER_BITS % 8);
+    let mask = (REGISTER_MAX as u16) << shift;
+    let value = (value as u16) << shift;
+    registers[byte] = (registers[byte] & !(mask as u8)) | value as u8;
+    // O último registrador cabe inteiro no último byte.
+    if let Some(next) = registers.get_mut(byte + 1) {
+        *next = (*next & !((mask >> 8) as u8)) | (value >> 8) as u8;
+    }
+}
+
+/// MurmurHash64A, o hash usado pelo HyperLogLog do Redis, lendo os blocos em little-endian.
+fn murmur_hash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().expect("blocos de oito bytes"));
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        for (i, byte) in rest.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Valor montado à mão no layout denso do `hyperloglog.c` do Redis: registrador 0 = 1,
+    /// registrador 1 = 2 e o último = 63, com o cache marcado como desatualizado.
+    fn redis_payload() -> Vec<u8> {
+        let mut payload = b"HYLL\0\0\0\0".to_vec();
+        payload.extend([0, 0, 0, 0, 0, 0, 0, 0x80]);
+        let mut registers = vec![0; 12288];
+        // 6 bits do registrador 0 e os 2 menos significativos do registrador 1.
+        registers[0] = 0b10_000001;
+        // Os 4 bits restantes do registrador 1.
+        registers[1] = 0b0000;
+        // O registrador 16383 ocupa os 6 bits mais altos do último byte.
+        registers[12287] = 63 << 2;
+        payload.extend(registers);
+        payload
+    }
+
+    #[test]
+    fn reads_and_writes_the_redis_dense_layout() {
+        let payload = redis_payload();
+        assert!(is_valid(&payload));
+        let registers = merge([&payload[..]]);
+        assert_eq!((registers[0], registers[1], registers[16383]), (1, 2, 63));
+        assert_eq!(registers.iter().filter(|register| **register != 0).count(), 3);
+        assert_eq!(from_registers(&registers), payload);
+    }
+
+    #[test]
+    fn merge_keeps_the_largest_register() {
+        let mut other = new();
+        set_register(&mut other[HEADER_LEN..], 0, 5);
+        set_register(&mut other[HEADER_LEN..], 1, 1);
+        set_register(&mut other[HEADER_LEN..], 2, 7);
+        let registers = merge([&redis_payload()[..], &other[..]]);
+        assert_eq!(&registers[..3], [5, 2, 7]);
+        assert_eq!(registers[16383], 63);
+        assert!(merge([]).iter().all(|register| *register == 0));
+    }
+
+    #[test]
+    fn validation_and_cache() {
+        assert!(is_valid(&new()));
+        assert_eq!(cardinality(&new()), 0);
+        assert!(!is_valid(b"HYLL"));
+        let mut sparse = new();
+        sparse[4] = 1;
+        assert!(!is_valid(&sparse));
+
+        // Um cache em dia, como o que o Redis grava, é usado sem recalcular.
+        let mut cached = new();
+        cached[8] = 42;
+        assert_eq!(cardinality(&cached), 42);
+        add(&mut cached, b"a");
+        assert_eq!(cardinality(&cached), 1);
+    }
+
+    #[test]
+    fn add_reports_register_changes() {
+        let mut hll = new();
+        assert!(add(&mut hll, b"a"));
+        assert!(!add(&mut hll, b"a"));
+        assert_eq!(cardinality(&hll), 1);
+        assert!(is_valid(&hll));
+    }
+
+    #[test]
+    fn estimate_stays_within_the_standard_error() {
+        let mut hll = new();
+        for n in 1..=100_000u64 {
+            add(&mut hll, format!("element:{n}").as_bytes());
+            if [10, 100, 1000, 10_000, 100_000].contains(&n) {
+                // Três vezes o erro padrão de 1.04 / sqrt(16384), cerca de 0.81%.
+                let error = (cardinality(&hll) as f64 - n as f64).abs() / n as f64;
+                assert!(error < 3.0 * 0.0081, "{n} elementos estimados com erro de {error}");
+            }
+        }
+    }
+}
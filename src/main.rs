@@ -1,19 +1,30 @@
+mod bitfield;
+mod blocking;
 mod data_types;
+mod dump;
+mod hyperloglog;
 mod persistence;
 mod resp;
+mod scan;
 mod store;
+mod zset;
 
-use crate::data_types::Value;
+use crate::bitfield::Overflow;
+use crate::data_types::{
+    from_unix_millis, to_unix_millis, Aggregate, BitOp, ExpireCondition, ListEnd, SetCondition,
+    SetExpiry, SetOp, SortOptions, TtlUpdate, Value, ZAddOptions,
+};
 use crate::persistence::PersistenceManager;
 use crate::resp::{parse_resp, serialize_resp, RespValue};
 use crate::store::Store;
+use crate::zset::{format_score, LexBound, ScoreBound};
 
 use bytes::{Buf, BytesMut};
 use config::Config;
 use serde::Deserialize;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, TcpStream};
 
@@ -22,6 +33,20 @@ struct Settings {
     host: String,
     port: u16,
     requirepass: Option<String>,
+    #[serde(default = "default_proto_max_bulk_len")]
+    proto_max_bulk_len: usize,
+    #[serde(default = "default_databases")]
+    databases: usize,
+}
+
+/// Quantidade padrão de bancos lógicos, a mesma do Redis.
+fn default_databases() -> usize {
+    16
+}
+
+/// Limite padrão de 512MB para strings, o mesmo do Redis.
+fn default_proto_max_bulk_len() -> usize {
+    512 * 1024 * 1024
 }
 
 #[tokio::main]
@@ -35,7 +60,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let password = settings.requirepass.clone();
 
     // 1. Inicializa o Store
-    let (store, store_bg_task) = Store::new();
+    let (store, store_bg_task) = Store::new(settings.databases, settings.proto_max_bulk_len);
     let store = Arc::new(store);
     tokio::spawn(store_bg_task);
 
@@ -65,7 +90,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // 5. Inicia a task de limpeza
     let store_clone_for_cleaning = store.clone();
     tokio::spawn(async move {
-        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        // Cada rodada só visita as chaves vencidas, então pode ser frequente.
+        let mut interval = tokio::time::interval(Duration::from_millis(100));
         loop {
             interval.tick().await;
             store_clone_for_cleaning.clean_expired().await;
@@ -101,6 +127,8 @@ async fn handle_connection(
     let mut reader = BufReader::new(socket);
     let mut buffer = BytesMut::with_capacity(4096);
     let mut authenticated = password.is_none();
+    // Banco selecionado pela conexão com `SELECT`.
+    let mut db = 0;
 
       loop {
         
@@ -125,9 +153,27 @@ async fn handle_connection(
             match parse_resp(&buffer) {
                 Ok((remaining, frame)) => {
                     let consumed = buffer.len() - remaining.len();
-                    //buffer.advance(consumed);
+                    buffer.advance(consumed);
 
-                    let response = process_command(frame, &store, &mut authenticated, &password).await;
+                    // Um comando bloqueante pode demorar: enquanto isso, o que o cliente enviar
+                    // continua sendo lido para o buffer, e uma desconexão cancela a espera. Os
+                    // demais terminam mesmo que o cliente já tenha fechado o seu lado do socket.
+                    let blocking = is_blocking(&frame);
+                    let command =
+                        process_command(frame, &store, &mut db, &mut authenticated, &password);
+                    tokio::pin!(command);
+                    let response = loop {
+                        tokio::select! {
+                            biased;
+                            response = &mut command => break response,
+                            read = reader.read_buf(&mut buffer), if blocking => {
+                                if read? == 0 {
+                                    println!("Conexão encerrada durante um comando bloqueante.");
+                                    return Ok(());
+                                }
+                            }
+                        }
+                    };
                     let response_bytes = serialize_resp(response);
                     
                     // Escreve a resposta de volta
@@ -136,8 +182,6 @@ async fn handle_connection(
                         return Err(e.into());
                     }
 
-                    buffer.advance(consumed);
-
                     if buffer.is_empty() {
                         break;
                     }
@@ -162,9 +206,27 @@ async fn handle_connection(
     //Ok(())
 }
 
+/// Comandos que podem deixar a conexão esperando por escritas de outros clientes.
+const BLOCKING_COMMANDS: &[&str] =
+    &["BLPOP", "BRPOP", "BLMOVE", "BRPOPLPUSH", "BZPOPMIN", "BZPOPMAX"];
+
+/// Indica se o frame é um comando bloqueante.
+fn is_blocking(frame: &RespValue) -> bool {
+    let RespValue::Array(args) = frame else {
+        return false;
+    };
+    match args.first() {
+        Some(RespValue::BulkString(name)) => BLOCKING_COMMANDS
+            .iter()
+            .any(|blocking| name.eq_ignore_ascii_case(blocking.as_bytes())),
+        _ => false,
+    }
+}
+
 async fn process_command(
     cmd: RespValue,
     store: &Store,
+    db: &mut usize,
     authenticated: &mut bool,
     password: &Option<String>,
 ) -> RespValue {
@@ -224,15 +286,34 @@ async fn process_command(
             let Ok(key) = args.remove(0).to_string() else {
                 return RespValue::Error("ERR invalid key".into());
             };
-            match store.get(&key).await {
-                Some(Value::String(s)) => RespValue::BulkString(s.into_bytes()),
-                Some(_) => RespValue::Error(
+            match store.get(*db, &key).await.map(Value::into_bytes) {
+                Some(Some(bytes)) => RespValue::BulkString(bytes),
+                Some(None) => RespValue::Error(
                     "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
                 ),
                 None => RespValue::Null,
             }
         }
 
+        "GETRANGE" => {
+            if args.len() != 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'GETRANGE'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let mut bounds = args
+                .into_iter()
+                .map(|arg| arg.to_string().ok().and_then(|s| s.parse::<i64>().ok()));
+            let (Some(Some(start)), Some(Some(end))) = (bounds.next(), bounds.next()) else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            match store.getrange(*db, &key, start, end).await {
+                Ok(bytes) => RespValue::BulkString(bytes),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
         "SET" => {
             if args.len() < 2 {
                 return RespValue::Error("ERR wrong number of arguments for 'SET'".into());
@@ -244,75 +325,3105 @@ async fn process_command(
                 return RespValue::Error("ERR invalid value".into());
             };
 
-            let mut expiry = None;
-            if !args.is_empty() {
-                if let Ok(opt) = args.remove(0).to_string() {
-                    if opt.to_uppercase() == "PX" && !args.is_empty() {
-                        if let Ok(millis_str) = args.remove(0).to_string() {
-                            if let Ok(millis) = millis_str.parse::<u64>() {
-                                expiry = Some(Duration::from_millis(millis));
-                            }
-                        }
-                    } else if opt.to_uppercase() == "EX" && !args.is_empty() {
-                        if let Ok(secs_str) = args.remove(0).to_string() {
-                            if let Ok(secs) = secs_str.parse::<u64>() {
-                                expiry = Some(Duration::from_secs(secs));
-                            }
+            let mut expiry = SetExpiry::Clear;
+            let mut condition = SetCondition::Always;
+            let mut get = false;
+            while !args.is_empty() {
+                let Ok(opt) = args.remove(0).to_string() else {
+                    return RespValue::Error("ERR syntax error".into());
+                };
+                match opt.to_uppercase().as_str() {
+                    unit @ ("EX" | "PX" | "EXAT" | "PXAT")
+                        if expiry == SetExpiry::Clear && !args.is_empty() =>
+                    {
+                        let Some(amount) = args
+                            .remove(0)
+                            .to_string()
+                            .ok()
+                            .and_then(|s| s.parse::<i64>().ok())
+                        else {
+                            return RespValue::Error(
+                                "ERR value is not an integer or out of range".into(),
+                            );
+                        };
+                        if amount <= 0 {
+                            return RespValue::Error(
+                                "ERR invalid expire time in 'set' command".into(),
+                            );
                         }
+                        let Some(at) = expiry_instant(unit, amount) else {
+                            return RespValue::Error(
+                                "ERR invalid expire time in 'set' command".into(),
+                            );
+                        };
+                        expiry = match unit {
+                            "EX" => SetExpiry::After(Duration::from_secs(amount as u64)),
+                            "PX" => SetExpiry::After(Duration::from_millis(amount as u64)),
+                            _ => SetExpiry::At(at),
+                        };
                     }
+                    "KEEPTTL" if expiry == SetExpiry::Clear => {
+                        expiry = SetExpiry::Keep;
+                    }
+                    "NX" if condition == SetCondition::Always => {
+                        condition = SetCondition::IfNotExists;
+                    }
+                    "XX" if condition == SetCondition::Always => {
+                        condition = SetCondition::IfExists;
+                    }
+                    "GET" if !get => get = true,
+                    _ => return RespValue::Error("ERR syntax error".into()),
                 }
             }
-            store.set(key, Value::String(value), expiry).await;
+            if get {
+                return match store.set_get(*db, key, Value::String(value), expiry, condition).await {
+                    Ok(Some(old)) => RespValue::BulkString(old),
+                    Ok(None) => RespValue::Null,
+                    Err(e) => RespValue::Error(e.to_string()),
+                };
+            }
+            if store.set(*db, key, Value::String(value), expiry, condition).await {
+                RespValue::SimpleString("OK".into())
+            } else {
+                RespValue::Null
+            }
+        }
+
+        "SETNX" => {
+            if args.len() != 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'SETNX'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(value) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid value".into());
+            };
+            // Mesmo caminho do `SET ... NX`, para que a semântica de lock seja idêntica.
+            let created = store
+                .set(*db, key, Value::String(value), SetExpiry::Clear, SetCondition::IfNotExists)
+                .await;
+            RespValue::Integer(created as i64)
+        }
+
+        "MGET" => {
+            if args.is_empty() {
+                return RespValue::Error("ERR wrong number of arguments for 'MGET'".into());
+            }
+            let keys: Vec<String> = args
+                .into_iter()
+                .map(|arg| arg.to_string().unwrap_or_default())
+                .collect();
+            let values = store
+                .mget(*db, &keys)
+                .await
+                .into_iter()
+                .map(|value| match value {
+                    Some(bytes) => RespValue::BulkString(bytes),
+                    None => RespValue::Null,
+                })
+                .collect();
+            RespValue::Array(values)
+        }
+
+        "MSET" | "MSETNX" => {
+            if args.is_empty() || args.len() % 2 != 0 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let mut pairs = Vec::with_capacity(args.len() / 2);
+            let mut args = args.into_iter();
+            while let (Some(key), Some(value)) = (args.next(), args.next()) {
+                let (Ok(key), Ok(value)) = (key.to_string(), value.to_string()) else {
+                    return RespValue::Error("ERR invalid key or value".into());
+                };
+                pairs.push((key, value));
+            }
+            if command_name == "MSET" {
+                store.mset(*db, pairs).await;
+                RespValue::SimpleString("OK".into())
+            } else {
+                RespValue::Integer(store.msetnx(*db, pairs).await as i64)
+            }
+        }
+
+        "SETEX" | "PSETEX" => {
+            if args.len() != 3 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Some(amount) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+            else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            let Ok(value) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid value".into());
+            };
+            let duration = if command_name == "SETEX" {
+                Duration::from_secs(amount.max(0) as u64)
+            } else {
+                Duration::from_millis(amount.max(0) as u64)
+            };
+            if amount <= 0 || SystemTime::now().checked_add(duration).is_none() {
+                return RespValue::Error(format!(
+                    "ERR invalid expire time in '{}' command",
+                    command_name.to_lowercase()
+                ));
+            }
+            store
+                .set(*db, key, Value::String(value), SetExpiry::After(duration), SetCondition::Always)
+                .await;
             RespValue::SimpleString("OK".into())
         }
 
+        "GETSET" => {
+            if args.len() != 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'GETSET'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(value) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid value".into());
+            };
+            match store.getset(*db, key, value).await {
+                Ok(Some(old)) => RespValue::BulkString(old),
+                Ok(None) => RespValue::Null,
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "GETDEL" => {
+            if args.len() != 1 {
+                return RespValue::Error("ERR wrong number of arguments for 'GETDEL'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            match store.getdel(*db, key).await {
+                Ok(Some(old)) => RespValue::BulkString(old),
+                Ok(None) => RespValue::Null,
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "GETEX" => {
+            if args.is_empty() {
+                return RespValue::Error("ERR wrong number of arguments for 'GETEX'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let option = match args.len() {
+                0 => None,
+                1 | 2 => args.remove(0).to_string().ok().map(|s| s.to_uppercase()),
+                _ => return RespValue::Error("ERR syntax error".into()),
+            };
+
+            let ttl = match option.as_deref() {
+                // Sem opções, `GETEX` é apenas um `GET` e não toca no TTL.
+                None => {
+                    return match store.get(*db, &key).await.map(Value::into_bytes) {
+                        Some(Some(bytes)) => RespValue::BulkString(bytes),
+                        Some(None) => RespValue::Error(
+                            "WRONGTYPE Operation against a key holding the wrong kind of value"
+                                .into(),
+                        ),
+                        None => RespValue::Null,
+                    };
+                }
+                Some("PERSIST") if args.is_empty() => TtlUpdate::Persist,
+                Some(unit @ ("EX" | "PX" | "EXAT" | "PXAT")) if args.len() == 1 => {
+                    let Some(amount) = args
+                        .remove(0)
+                        .to_string()
+                        .ok()
+                        .and_then(|s| s.parse::<i64>().ok())
+                    else {
+                        return RespValue::Error(
+                            "ERR value is not an integer or out of range".into(),
+                        );
+                    };
+                    if amount <= 0 {
+                        return RespValue::Error(
+                            "ERR invalid expire time in 'getex' command".into(),
+                        );
+                    }
+                    let Some(at) = expiry_instant(unit, amount) else {
+                        return RespValue::Error(
+                            "ERR invalid expire time in 'getex' command".into(),
+                        );
+                    };
+                    TtlUpdate::ExpireAt(at)
+                }
+                _ => return RespValue::Error("ERR syntax error".into()),
+            };
+            match store.getex(*db, key, ttl).await {
+                Ok(Some(value)) => RespValue::BulkString(value),
+                Ok(None) => RespValue::Null,
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
         "KEYS" => {
             if args.len() != 1 || args[0].clone().to_string().unwrap_or_default() != "*" {
                 return RespValue::Error("ERR a sintaxe suportada é 'KEYS *'".into());
             }
-            let data_lock = store.data.read().await;
-            let keys: Vec<RespValue> = data_lock
-                .keys()
-                .map(|k| RespValue::BulkString(k.clone().into_bytes()))
+            let keys: Vec<RespValue> = store
+                .keys(*db)
+                .await
+                .into_iter()
+                .map(|k| RespValue::BulkString(k.into_bytes()))
                 .collect();
             RespValue::Array(keys)
         }
 
+        "RANDOMKEY" => {
+            if !args.is_empty() {
+                return RespValue::Error("ERR wrong number of arguments for 'RANDOMKEY'".into());
+            }
+            match store.random_key(*db).await {
+                Some(key) => RespValue::BulkString(key.into_bytes()),
+                None => RespValue::Null,
+            }
+        }
+
+        "SCAN" => {
+            if args.is_empty() {
+                return RespValue::Error("ERR wrong number of arguments for 'SCAN'".into());
+            }
+            let Some(cursor) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<u64>().ok())
+            else {
+                return RespValue::Error("ERR invalid cursor".into());
+            };
+            let mut count = 10;
+            let mut pattern = None;
+            let mut type_name = None;
+            let mut args = args.into_iter().map(|arg| arg.to_string().unwrap_or_default());
+            while let Some(opt) = args.next() {
+                let Some(value) = args.next() else {
+                    return RespValue::Error("ERR syntax error".into());
+                };
+                match opt.to_uppercase().as_str() {
+                    "MATCH" => pattern = Some(value),
+                    "TYPE" => type_name = Some(value),
+                    "COUNT" => match value.parse::<usize>() {
+                        Ok(n) if n >= 1 => count = n,
+                        _ => return RespValue::Error("ERR syntax error".into()),
+                    },
+                    _ => return RespValue::Error("ERR syntax error".into()),
+                }
+            }
+            let (next, keys) = store
+                .scan(*db, cursor, count, pattern.as_deref(), type_name.as_deref())
+                .await;
+            RespValue::Array(vec![
+                RespValue::BulkString(next.to_string().into_bytes()),
+                RespValue::Array(
+                    keys.into_iter()
+                        .map(|k| RespValue::BulkString(k.into_bytes()))
+                        .collect(),
+                ),
+            ])
+        }
+
         "HSET" => {
-            if args.len() != 3 {
+            if args.len() < 3 {
                 return RespValue::Error("ERR wrong number of arguments for 'HSET'".into());
             }
-            let Ok(key) = args.remove(0).to_string() else { 
-                return RespValue::Error("ERR invalid key".into()); 
+            // Depois da chave, os argumentos precisam formar pares campo/valor.
+            if args.len() % 2 == 0 {
+                return RespValue::Error("ERR syntax error".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let mut pairs = Vec::with_capacity(args.len() / 2);
+            let mut args = args.into_iter();
+            while let (Some(field), Some(value)) = (args.next(), args.next()) {
+                let (Ok(field), Ok(value)) = (field.to_string(), value.to_string()) else {
+                    return RespValue::Error("ERR invalid field or value".into());
+                };
+                pairs.push((field, value));
+            }
+
+            match store.hset(*db, key, pairs).await {
+                Ok(i) => RespValue::Integer(i),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "HINCRBY" => {
+            if args.len() != 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'HINCRBY'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
             };
-            let Ok(field) = args.remove(0).to_string() else { 
-                return RespValue::Error("ERR invalid field".into()); 
+            let Ok(field) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid field".into());
             };
-            let Ok(value) = args.remove(0).to_string() else { 
-                return RespValue::Error("ERR invalid value".into()); 
+            let Some(delta) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+            else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
             };
+            match store.hincr_by(*db, key, field, delta).await {
+                Ok(value) => RespValue::Integer(value),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
 
-            match store.hset(key, field, value).await {
-                Ok(i) => RespValue::Integer(i),
+        "HINCRBYFLOAT" => {
+            if args.len() != 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'HINCRBYFLOAT'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(field) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid field".into());
+            };
+            let Some(increment) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<f64>().ok())
+                .filter(|f| !f.is_nan())
+            else {
+                return RespValue::Error("ERR value is not a valid float".into());
+            };
+            match store.hincr_by_float(*db, key, field, increment).await {
+                Ok(value) => RespValue::BulkString(value.into_bytes()),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "HSETNX" => {
+            if args.len() != 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'HSETNX'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(field) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid field".into());
+            };
+            let Ok(value) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid value".into());
+            };
+            match store.hsetnx(*db, key, field, value).await {
+                Ok(set) => RespValue::Integer(set as i64),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "HGET" => {
+            if args.len() != 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'HGET'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(field) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid field".into());
+            };
+            match store.hget(*db, &key, &field).await {
+                Ok(Some(value)) => RespValue::BulkString(value.into_bytes()),
+                Ok(None) => RespValue::Null,
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "HDEL" => {
+            if args.len() < 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'HDEL'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(fields) = args
+                .into_iter()
+                .map(RespValue::to_string)
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                return RespValue::Error("ERR invalid field".into());
+            };
+            match store.hdel(*db, key, fields).await {
+                Ok(removed) => RespValue::Integer(removed),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "HMGET" => {
+            if args.len() < 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'HMGET'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let fields: Vec<String> = args
+                .into_iter()
+                .map(|arg| arg.to_string().unwrap_or_default())
+                .collect();
+            match store.hmget(*db, &key, &fields).await {
+                Ok(values) => RespValue::Array(
+                    values
+                        .into_iter()
+                        .map(|value| match value {
+                            Some(v) => RespValue::BulkString(v.into_bytes()),
+                            None => RespValue::Null,
+                        })
+                        .collect(),
+                ),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "HEXISTS" => {
+            if args.len() != 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'HEXISTS'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(field) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid field".into());
+            };
+            match store.hexists(*db, &key, &field).await {
+                Ok(exists) => RespValue::Integer(exists as i64),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "HLEN" => {
+            if args.len() != 1 {
+                return RespValue::Error("ERR wrong number of arguments for 'HLEN'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            match store.hlen(*db, &key).await {
+                Ok(len) => RespValue::Integer(len),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "HSTRLEN" => {
+            if args.len() != 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'HSTRLEN'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(field) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid field".into());
+            };
+            match store.hstrlen(*db, &key, &field).await {
+                Ok(len) => RespValue::Integer(len),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "HKEYS" | "HVALS" => {
+            if args.len() != 1 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let result = if command_name == "HKEYS" {
+                store.hkeys(*db, &key).await
+            } else {
+                store.hvals(*db, &key).await
+            };
+            match result {
+                Ok(items) => RespValue::Array(
+                    items
+                        .into_iter()
+                        .map(|item| RespValue::BulkString(item.into_bytes()))
+                        .collect(),
+                ),
                 Err(e) => RespValue::Error(e.to_string()),
             }
         }
 
-        "DEL" => {
+        "HRANDFIELD" => {
+            if args.is_empty() || args.len() > 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'HRANDFIELD'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            // Sem `count`, a resposta é um único campo, e não um array.
             if args.is_empty() {
-                return RespValue::Error("ERR wrong number of arguments for 'DEL'".into());
+                return match store.hrandfield(*db, &key, 1).await {
+                    Ok(mut picked) => match picked.pop() {
+                        Some((field, _)) => RespValue::BulkString(field.into_bytes()),
+                        None => RespValue::Null,
+                    },
+                    Err(e) => RespValue::Error(e.to_string()),
+                };
             }
-            let mut deleted_count = 0;
-            for arg in args {
-                if let Ok(key) = arg.to_string() {
-                    if store.delete(&key).await {
-                        deleted_count += 1;
-                    }
+            let Some(count) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+            else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            if count < -(i64::MAX / 2) {
+                return RespValue::Error("ERR value is out of range".into());
+            }
+            let option = args.pop().map(|arg| arg.to_string().unwrap_or_default().to_uppercase());
+            let with_values = match option.as_deref() {
+                None => false,
+                Some("WITHVALUES") => true,
+                Some(_) => return RespValue::Error("ERR syntax error".into()),
+            };
+            match store.hrandfield(*db, &key, count).await {
+                Ok(picked) if with_values => pairs_reply(picked),
+                Ok(picked) => RespValue::Array(
+                    picked
+                        .into_iter()
+                        .map(|(field, _)| RespValue::BulkString(field.into_bytes()))
+                        .collect(),
+                ),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "HSCAN" => {
+            if args.len() < 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'HSCAN'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Some(cursor) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<u64>().ok())
+            else {
+                return RespValue::Error("ERR invalid cursor".into());
+            };
+            let mut count = 10;
+            let mut pattern = None;
+            let mut no_values = false;
+            let mut args = args.into_iter().map(|arg| arg.to_string().unwrap_or_default());
+            while let Some(opt) = args.next() {
+                match opt.to_uppercase().as_str() {
+                    "NOVALUES" => no_values = true,
+                    "MATCH" => match args.next() {
+                        Some(value) => pattern = Some(value),
+                        None => return RespValue::Error("ERR syntax error".into()),
+                    },
+                    "COUNT" => match args.next().and_then(|value| value.parse::<usize>().ok()) {
+                        Some(n) if n >= 1 => count = n,
+                        _ => return RespValue::Error("ERR syntax error".into()),
+                    },
+                    _ => return RespValue::Error("ERR syntax error".into()),
                 }
             }
-            RespValue::Integer(deleted_count)
+            let scanned = store.hscan(*db, &key, cursor, count, pattern.as_deref()).await;
+            let (next, pairs) = match scanned {
+                Ok(result) => result,
+                Err(e) => return RespValue::Error(e.to_string()),
+            };
+            let elements = if no_values {
+                RespValue::Array(
+                    pairs
+                        .into_iter()
+                        .map(|(field, _)| RespValue::BulkString(field.into_bytes()))
+                        .collect(),
+                )
+            } else {
+                pairs_reply(pairs)
+            };
+            RespValue::Array(vec![
+                RespValue::BulkString(next.to_string().into_bytes()),
+                elements,
+            ])
         }
 
-        _ => RespValue::Error(format!("ERR unknown command '{}'", command_name)),
-    }
-}
\ No newline at end of file
+        "HEXPIRE" | "HPEXPIRE" | "HEXPIREAT" | "HPEXPIREAT" => {
+            if args.len() < 5 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Some(amount) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+            else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            // Ao contrário do `EXPIRE`, só uma condição é aceita, antes do `FIELDS`.
+            let mut condition = ExpireCondition::default();
+            let flag = args[0].clone().to_string().unwrap_or_default().to_uppercase();
+            match flag.as_str() {
+                "NX" => condition.nx = true,
+                "XX" => condition.xx = true,
+                "GT" => condition.gt = true,
+                "LT" => condition.lt = true,
+                _ => {}
+            }
+            if condition != ExpireCondition::default() {
+                args.remove(0);
+            }
+            let fields = match parse_fields(args) {
+                Ok(fields) => fields,
+                Err(e) => return e,
+            };
+            let unit = match command_name.as_str() {
+                "HEXPIRE" => "EX",
+                "HPEXPIRE" => "PX",
+                "HEXPIREAT" => "EXAT",
+                _ => "PXAT",
+            };
+            let Some(at) = expiry_instant(unit, amount) else {
+                return RespValue::Error(format!(
+                    "ERR invalid expire time in '{}' command",
+                    command_name.to_lowercase()
+                ));
+            };
+            match store.hexpire(*db, key, fields, at, condition).await {
+                Ok(statuses) => integers_reply(statuses),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "HPERSIST" => {
+            if args.len() < 4 {
+                return RespValue::Error("ERR wrong number of arguments for 'HPERSIST'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let fields = match parse_fields(args) {
+                Ok(fields) => fields,
+                Err(e) => return e,
+            };
+            match store.hpersist(*db, key, fields).await {
+                Ok(statuses) => integers_reply(statuses),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "HTTL" | "HPTTL" => {
+            if args.len() < 4 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let fields = match parse_fields(args) {
+                Ok(fields) => fields,
+                Err(e) => return e,
+            };
+            let ttls = match store.field_ttls(*db, &key, &fields).await {
+                Ok(ttls) => ttls,
+                Err(e) => return RespValue::Error(e.to_string()),
+            };
+            // Mesmos códigos e arredondamento do `TTL`/`PTTL`, um por campo.
+            let statuses = ttls
+                .into_iter()
+                .map(|ttl| match ttl {
+                    None => -2,
+                    Some(None) => -1,
+                    Some(Some(remaining)) if command_name == "HPTTL" => {
+                        remaining.as_millis() as i64
+                    }
+                    Some(Some(remaining)) => {
+                        (remaining.as_secs() + (remaining.subsec_nanos() > 0) as u64) as i64
+                    }
+                })
+                .collect();
+            integers_reply(statuses)
+        }
+
+        "HGETALL" => {
+            if args.len() != 1 {
+                return RespValue::Error("ERR wrong number of arguments for 'HGETALL'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            match store.hgetall(*db, &key).await {
+                Ok(pairs) => pairs_reply(pairs),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "LPUSH" | "RPUSH" | "LPUSHX" | "RPUSHX" => {
+            if args.len() < 2 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(elements) = args
+                .into_iter()
+                .map(RespValue::to_string)
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                return RespValue::Error("ERR invalid element".into());
+            };
+            let end = if command_name.starts_with('L') { ListEnd::Left } else { ListEnd::Right };
+            let if_exists = command_name.ends_with('X');
+            match store.push(*db, key, elements, end, if_exists).await {
+                Ok(len) => RespValue::Integer(len),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "LLEN" => {
+            if args.len() != 1 {
+                return RespValue::Error("ERR wrong number of arguments for 'LLEN'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            match store.llen(*db, &key).await {
+                Ok(len) => RespValue::Integer(len),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "LINDEX" => {
+            if args.len() != 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'LINDEX'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Some(index) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+            else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            match store.lindex(*db, &key, index).await {
+                Ok(Some(element)) => RespValue::BulkString(element.into_bytes()),
+                Ok(None) => RespValue::Null,
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "LSET" => {
+            if args.len() != 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'LSET'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Some(index) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+            else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            let Ok(element) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid element".into());
+            };
+            match store.lset(*db, key, index, element).await {
+                Ok(()) => RespValue::SimpleString("OK".into()),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "LINSERT" => {
+            if args.len() != 4 {
+                return RespValue::Error("ERR wrong number of arguments for 'LINSERT'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let position = args.remove(0).to_string().unwrap_or_default().to_uppercase();
+            let before = match position.as_str() {
+                "BEFORE" => true,
+                "AFTER" => false,
+                _ => return RespValue::Error("ERR syntax error".into()),
+            };
+            let (Ok(pivot), Ok(element)) = (args.remove(0).to_string(), args.remove(0).to_string())
+            else {
+                return RespValue::Error("ERR invalid pivot or element".into());
+            };
+            match store.linsert(*db, key, before, pivot, element).await {
+                Ok(len) => RespValue::Integer(len),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "LREM" => {
+            if args.len() != 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'LREM'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Some(count) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+            else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            let Ok(element) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid element".into());
+            };
+            match store.lrem(*db, key, count, element).await {
+                Ok(removed) => RespValue::Integer(removed),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "LTRIM" => {
+            if args.len() != 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'LTRIM'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let (Some(start), Some(stop)) = (
+                args[0].clone().to_string().ok().and_then(|s| s.parse::<i64>().ok()),
+                args[1].clone().to_string().ok().and_then(|s| s.parse::<i64>().ok()),
+            ) else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            match store.ltrim(*db, key, start, stop).await {
+                Ok(()) => RespValue::SimpleString("OK".into()),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "LMOVE" | "RPOPLPUSH" => {
+            let arity = if command_name == "LMOVE" { 4 } else { 2 };
+            if args.len() != arity {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let (Ok(source), Ok(destination)) =
+                (args.remove(0).to_string(), args.remove(0).to_string())
+            else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            // `RPOPLPUSH` é o `LMOVE` da cauda da origem para a cabeça do destino.
+            let (from, to) = if args.is_empty() {
+                (Some(ListEnd::Right), Some(ListEnd::Left))
+            } else {
+                (list_end(args.remove(0)), list_end(args.remove(0)))
+            };
+            let (Some(from), Some(to)) = (from, to) else {
+                return RespValue::Error("ERR syntax error".into());
+            };
+            match store.lmove(*db, source, destination, from, to).await {
+                Ok(Some(element)) => RespValue::BulkString(element.into_bytes()),
+                Ok(None) => RespValue::Null,
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "BLPOP" | "BRPOP" => {
+            if args.len() < 2 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let timeout = match parse_timeout(args.pop().expect("há pelo menos dois argumentos")) {
+                Ok(timeout) => timeout,
+                Err(e) => return e,
+            };
+            let Ok(keys) = args
+                .into_iter()
+                .map(RespValue::to_string)
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let end = if command_name == "BLPOP" { ListEnd::Left } else { ListEnd::Right };
+            match store.blocking_pop(*db, &keys, end, timeout).await {
+                Ok(Some((key, element))) => RespValue::Array(vec![
+                    RespValue::BulkString(key.into_bytes()),
+                    RespValue::BulkString(element.into_bytes()),
+                ]),
+                Ok(None) => RespValue::Null,
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "BLMOVE" | "BRPOPLPUSH" => {
+            let arity = if command_name == "BLMOVE" { 5 } else { 3 };
+            if args.len() != arity {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let timeout = match parse_timeout(args.pop().expect("a aridade foi verificada")) {
+                Ok(timeout) => timeout,
+                Err(e) => return e,
+            };
+            let (Ok(source), Ok(destination)) =
+                (args.remove(0).to_string(), args.remove(0).to_string())
+            else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let (from, to) = if args.is_empty() {
+                (Some(ListEnd::Right), Some(ListEnd::Left))
+            } else {
+                (list_end(args.remove(0)), list_end(args.remove(0)))
+            };
+            let (Some(from), Some(to)) = (from, to) else {
+                return RespValue::Error("ERR syntax error".into());
+            };
+            match store.blocking_move(*db, source, destination, from, to, timeout).await {
+                Ok(Some(element)) => RespValue::BulkString(element.into_bytes()),
+                Ok(None) => RespValue::Null,
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "LPOS" => {
+            if args.len() < 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'LPOS'".into());
+            }
+            let (Ok(key), Ok(element)) = (args.remove(0).to_string(), args.remove(0).to_string())
+            else {
+                return RespValue::Error("ERR invalid key or element".into());
+            };
+            let mut rank = 1;
+            let mut count = None;
+            let mut maxlen = 0;
+            let mut args = args.into_iter().map(|arg| arg.to_string().unwrap_or_default());
+            while let Some(opt) = args.next() {
+                let Some(value) = args.next() else {
+                    return RespValue::Error("ERR syntax error".into());
+                };
+                let Ok(value) = value.parse::<i64>() else {
+                    return RespValue::Error("ERR value is not an integer or out of range".into());
+                };
+                match opt.to_uppercase().as_str() {
+                    "RANK" if value == 0 => {
+                        return RespValue::Error(
+                            "ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list".into(),
+                        )
+                    }
+                    "RANK" => rank = value,
+                    "COUNT" if value < 0 => {
+                        return RespValue::Error("ERR COUNT can't be negative".into())
+                    }
+                    "COUNT" => count = Some(value as usize),
+                    "MAXLEN" if value < 0 => {
+                        return RespValue::Error("ERR MAXLEN can't be negative".into())
+                    }
+                    "MAXLEN" => maxlen = value as usize,
+                    _ => return RespValue::Error("ERR syntax error".into()),
+                }
+            }
+            // Sem `COUNT` a resposta é uma única posição (ou Null); com ele, sempre um array.
+            let positions = store
+                .lpos(*db, &key, &element, rank, count.unwrap_or(1), maxlen)
+                .await;
+            match positions {
+                Ok(positions) if count.is_some() => {
+                    RespValue::Array(positions.into_iter().map(RespValue::Integer).collect())
+                }
+                Ok(positions) => positions
+                    .first()
+                    .map_or(RespValue::Null, |position| RespValue::Integer(*position)),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "LMPOP" => {
+            if args.len() < 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'LMPOP'".into());
+            }
+            let (keys, rest) = match parse_numkeys(args) {
+                Ok(parsed) => parsed,
+                Err(e) => return e,
+            };
+            let mut rest = rest.into_iter();
+            let Some(end) = rest.next().and_then(list_end) else {
+                return RespValue::Error("ERR syntax error".into());
+            };
+            let count = match (rest.next(), rest.next(), rest.next()) {
+                (None, ..) => 1,
+                (Some(opt), Some(n), None)
+                    if opt.clone().to_string().is_ok_and(|o| o.eq_ignore_ascii_case("COUNT")) =>
+                {
+                    match n.to_string().ok().and_then(|n| n.parse::<i64>().ok()) {
+                        Some(n) if n > 0 => n as usize,
+                        _ => return RespValue::Error("ERR count should be greater than 0".into()),
+                    }
+                }
+                _ => return RespValue::Error("ERR syntax error".into()),
+            };
+            match store.mpop(*db, keys, end, count).await {
+                Ok(Some((key, popped))) => RespValue::Array(vec![
+                    RespValue::BulkString(key.into_bytes()),
+                    RespValue::Array(
+                        popped
+                            .into_iter()
+                            .map(|element| RespValue::BulkString(element.into_bytes()))
+                            .collect(),
+                    ),
+                ]),
+                Ok(None) => RespValue::Null,
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "LPOP" | "RPOP" => {
+            if args.is_empty() || args.len() > 2 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            // Sem a contagem a resposta é um único elemento; com ela, sempre um array.
+            let count = match args.pop() {
+                Some(arg) => match arg.to_string().ok().and_then(|s| s.parse::<i64>().ok()) {
+                    Some(n) if n >= 0 => Some(n as usize),
+                    _ => {
+                        return RespValue::Error(
+                            "ERR value is out of range, must be positive".into(),
+                        )
+                    }
+                },
+                None => None,
+            };
+            let end = if command_name == "LPOP" { ListEnd::Left } else { ListEnd::Right };
+            match store.pop(*db, key, count.unwrap_or(1), end).await {
+                Ok(Some(popped)) if count.is_some() => RespValue::Array(
+                    popped
+                        .into_iter()
+                        .map(|element| RespValue::BulkString(element.into_bytes()))
+                        .collect(),
+                ),
+                Ok(Some(popped)) => match popped.into_iter().next() {
+                    Some(element) => RespValue::BulkString(element.into_bytes()),
+                    None => RespValue::Null,
+                },
+                Ok(None) => RespValue::Null,
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "SADD" | "SREM" => {
+            if args.len() < 2 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(members) = args
+                .into_iter()
+                .map(RespValue::to_string)
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                return RespValue::Error("ERR invalid member".into());
+            };
+            let result = if command_name == "SADD" {
+                store.sadd(*db, key, members).await
+            } else {
+                store.srem(*db, key, members).await
+            };
+            match result {
+                Ok(count) => RespValue::Integer(count),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "SMEMBERS" => {
+            if args.len() != 1 {
+                return RespValue::Error("ERR wrong number of arguments for 'SMEMBERS'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            match store.smembers(*db, &key).await {
+                Ok(members) => members_reply(members),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "SPOP" => {
+            if args.is_empty() || args.len() > 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'SPOP'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            // Sem a contagem a resposta é um único membro; com ela, sempre um array.
+            let count = match args.pop() {
+                Some(arg) => match arg.to_string().ok().and_then(|s| s.parse::<i64>().ok()) {
+                    Some(n) if n >= 0 => Some(n as usize),
+                    _ => {
+                        return RespValue::Error(
+                            "ERR value is out of range, must be positive".into(),
+                        )
+                    }
+                },
+                None => None,
+            };
+            match store.spop(*db, key, count.unwrap_or(1)).await {
+                Ok(popped) if count.is_some() => members_reply(popped),
+                Ok(popped) => match popped.into_iter().next() {
+                    Some(member) => RespValue::BulkString(member.into_bytes()),
+                    None => RespValue::Null,
+                },
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "SRANDMEMBER" => {
+            if args.is_empty() || args.len() > 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'SRANDMEMBER'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            // Sem `count`, a resposta é um único membro, e não um array.
+            if args.is_empty() {
+                return match store.srandmember(*db, &key, 1).await {
+                    Ok(mut picked) => match picked.pop() {
+                        Some(member) => RespValue::BulkString(member.into_bytes()),
+                        None => RespValue::Null,
+                    },
+                    Err(e) => RespValue::Error(e.to_string()),
+                };
+            }
+            let Some(count) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+            else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            if count < -(i64::MAX / 2) {
+                return RespValue::Error("ERR value is out of range".into());
+            }
+            match store.srandmember(*db, &key, count).await {
+                Ok(picked) => members_reply(picked),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "SINTER" | "SUNION" | "SDIFF" => {
+            if args.is_empty() {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(keys) = args
+                .into_iter()
+                .map(RespValue::to_string)
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let op = match command_name.as_str() {
+                "SINTER" => SetOp::Inter,
+                "SUNION" => SetOp::Union,
+                _ => SetOp::Diff,
+            };
+            match store.set_op(*db, op, &keys).await {
+                Ok(members) => members_reply(members),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "SINTERSTORE" | "SUNIONSTORE" | "SDIFFSTORE" => {
+            if args.len() < 2 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(destination) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(keys) = args
+                .into_iter()
+                .map(RespValue::to_string)
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let op = match command_name.as_str() {
+                "SINTERSTORE" => SetOp::Inter,
+                "SUNIONSTORE" => SetOp::Union,
+                _ => SetOp::Diff,
+            };
+            match store.set_store(*db, op, destination, keys).await {
+                Ok(len) => RespValue::Integer(len),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "SINTERCARD" => {
+            if args.len() < 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'SINTERCARD'".into());
+            }
+            let (keys, rest) = match parse_numkeys(args) {
+                Ok(parsed) => parsed,
+                Err(e) => return e,
+            };
+            // Zero em `LIMIT` é sem limite.
+            let mut rest = rest.into_iter();
+            let limit = match (rest.next(), rest.next(), rest.next()) {
+                (None, ..) => 0,
+                (Some(opt), Some(n), None)
+                    if opt.clone().to_string().is_ok_and(|o| o.eq_ignore_ascii_case("LIMIT")) =>
+                {
+                    match n.to_string().ok().and_then(|n| n.parse::<i64>().ok()) {
+                        Some(n) if n >= 0 => n as usize,
+                        Some(_) => return RespValue::Error("ERR LIMIT can't be negative".into()),
+                        None => {
+                            return RespValue::Error(
+                                "ERR value is not an integer or out of range".into(),
+                            )
+                        }
+                    }
+                }
+                _ => return RespValue::Error("ERR syntax error".into()),
+            };
+            match store.sintercard(*db, &keys, limit).await {
+                Ok(len) => RespValue::Integer(len),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "SMOVE" => {
+            if args.len() != 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'SMOVE'".into());
+            }
+            let (Ok(source), Ok(destination)) =
+                (args.remove(0).to_string(), args.remove(0).to_string())
+            else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(member) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid member".into());
+            };
+            match store.smove(*db, source, destination, member).await {
+                Ok(moved) => RespValue::Integer(moved as i64),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "SSCAN" => {
+            if args.len() < 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'SSCAN'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Some(cursor) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<u64>().ok())
+            else {
+                return RespValue::Error("ERR invalid cursor".into());
+            };
+            let mut count = 10;
+            let mut pattern = None;
+            let mut args = args.into_iter().map(|arg| arg.to_string().unwrap_or_default());
+            while let Some(opt) = args.next() {
+                match opt.to_uppercase().as_str() {
+                    "MATCH" => match args.next() {
+                        Some(value) => pattern = Some(value),
+                        None => return RespValue::Error("ERR syntax error".into()),
+                    },
+                    "COUNT" => match args.next().and_then(|value| value.parse::<usize>().ok()) {
+                        Some(n) if n >= 1 => count = n,
+                        _ => return RespValue::Error("ERR syntax error".into()),
+                    },
+                    _ => return RespValue::Error("ERR syntax error".into()),
+                }
+            }
+            match store.sscan(*db, &key, cursor, count, pattern.as_deref()).await {
+                Ok((next, members)) => RespValue::Array(vec![
+                    RespValue::BulkString(next.to_string().into_bytes()),
+                    members_reply(members),
+                ]),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "SCARD" => {
+            if args.len() != 1 {
+                return RespValue::Error("ERR wrong number of arguments for 'SCARD'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            match store.scard(*db, &key).await {
+                Ok(len) => RespValue::Integer(len),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "SISMEMBER" => {
+            if args.len() != 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'SISMEMBER'".into());
+            }
+            let (Ok(key), Ok(member)) = (args.remove(0).to_string(), args.remove(0).to_string())
+            else {
+                return RespValue::Error("ERR invalid key or member".into());
+            };
+            match store.sismember(*db, &key, &member).await {
+                Ok(found) => RespValue::Integer(found as i64),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "SMISMEMBER" => {
+            if args.len() < 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'SMISMEMBER'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(members) = args
+                .into_iter()
+                .map(RespValue::to_string)
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                return RespValue::Error("ERR invalid member".into());
+            };
+            match store.smismember(*db, &key, &members).await {
+                Ok(found) => integers_reply(found.into_iter().map(i64::from).collect()),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "ZADD" => {
+            if args.len() < 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'ZADD'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            // As opções vêm antes dos pares; o primeiro argumento que não é opção é um score.
+            let mut options = ZAddOptions::default();
+            while let Some(option) = args.first().and_then(|arg| arg.clone().to_string().ok()) {
+                match option.to_uppercase().as_str() {
+                    "NX" => options.nx = true,
+                    "XX" => options.xx = true,
+                    "GT" => options.gt = true,
+                    "LT" => options.lt = true,
+                    "CH" => options.ch = true,
+                    "INCR" => options.incr = true,
+                    _ => break,
+                }
+                args.remove(0);
+            }
+            if options.nx && options.xx {
+                return RespValue::Error(
+                    "ERR XX and NX options at the same time are not compatible".into(),
+                );
+            }
+            if [options.gt, options.lt, options.nx].iter().filter(|set| **set).count() > 1 {
+                return RespValue::Error(
+                    "ERR GT, LT, and/or NX options at the same time are not compatible".into(),
+                );
+            }
+            if args.is_empty() || args.len() % 2 != 0 {
+                return RespValue::Error("ERR syntax error".into());
+            }
+            if options.incr && args.len() > 2 {
+                return RespValue::Error(
+                    "ERR INCR option supports a single increment-element pair".into(),
+                );
+            }
+            let mut pairs = Vec::with_capacity(args.len() / 2);
+            let mut args = args.into_iter();
+            while let (Some(score), Some(member)) = (args.next(), args.next()) {
+                let Some(score) = parse_score(score) else {
+                    return RespValue::Error("ERR value is not a valid float".into());
+                };
+                let Ok(member) = member.to_string() else {
+                    return RespValue::Error("ERR invalid member".into());
+                };
+                pairs.push((score, member));
+            }
+            if options.incr {
+                let (increment, member) = pairs.remove(0);
+                return match store.zadd_incr(*db, key, member, increment, options).await {
+                    Ok(Some(score)) => RespValue::BulkString(score.into_bytes()),
+                    Ok(None) => RespValue::Null,
+                    Err(e) => RespValue::Error(e.to_string()),
+                };
+            }
+            match store.zadd(*db, key, pairs, options).await {
+                Ok(count) => RespValue::Integer(count),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "ZSCORE" => {
+            if args.len() != 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'ZSCORE'".into());
+            }
+            let (Ok(key), Ok(member)) = (args.remove(0).to_string(), args.remove(0).to_string())
+            else {
+                return RespValue::Error("ERR invalid key or member".into());
+            };
+            match store.zscore(*db, &key, &member).await {
+                Ok(Some(score)) => RespValue::BulkString(format_score(score).into_bytes()),
+                Ok(None) => RespValue::Null,
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "ZINCRBY" => {
+            if args.len() != 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'ZINCRBY'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Some(increment) = parse_score(args.remove(0)) else {
+                return RespValue::Error("ERR value is not a valid float".into());
+            };
+            let Ok(member) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid member".into());
+            };
+            match store.zincr_by(*db, key, member, increment).await {
+                Ok(score) => RespValue::BulkString(score.into_bytes()),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "ZMSCORE" => {
+            if args.len() < 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'ZMSCORE'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(members) = args
+                .into_iter()
+                .map(RespValue::to_string)
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                return RespValue::Error("ERR invalid member".into());
+            };
+            match store.zmscore(*db, &key, &members).await {
+                Ok(scores) => RespValue::Array(
+                    scores
+                        .into_iter()
+                        .map(|score| match score {
+                            Some(score) => RespValue::BulkString(format_score(score).into_bytes()),
+                            None => RespValue::Null,
+                        })
+                        .collect(),
+                ),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "ZRANK" | "ZREVRANK" => {
+            if args.len() != 2 && args.len() != 3 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let (Ok(key), Ok(member)) = (args.remove(0).to_string(), args.remove(0).to_string())
+            else {
+                return RespValue::Error("ERR invalid key or member".into());
+            };
+            let with_score = match args.pop().map(RespValue::to_string) {
+                None => false,
+                Some(Ok(opt)) if opt.eq_ignore_ascii_case("WITHSCORE") => true,
+                Some(_) => return RespValue::Error("ERR syntax error".into()),
+            };
+            let rev = command_name == "ZREVRANK";
+            match store.zrank(*db, &key, &member, rev).await {
+                Ok(Some((rank, score))) if with_score => RespValue::Array(vec![
+                    RespValue::Integer(rank as i64),
+                    RespValue::BulkString(format_score(score).into_bytes()),
+                ]),
+                Ok(Some((rank, _))) => RespValue::Integer(rank as i64),
+                Ok(None) => RespValue::Null,
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "ZPOPMIN" | "ZPOPMAX" => {
+            if args.is_empty() || args.len() > 2 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let count = match args.pop() {
+                Some(arg) => match arg.to_string().ok().and_then(|s| s.parse::<i64>().ok()) {
+                    Some(n) if n >= 0 => n as usize,
+                    _ => {
+                        return RespValue::Error(
+                            "ERR value is out of range, must be positive".into(),
+                        )
+                    }
+                },
+                None => 1,
+            };
+            match store.zpop(*db, key, count, command_name == "ZPOPMAX").await {
+                Ok(popped) => scored_reply(popped, true),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "BZPOPMIN" | "BZPOPMAX" => {
+            if args.len() < 2 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let timeout = match parse_timeout(args.pop().expect("há pelo menos dois argumentos")) {
+                Ok(timeout) => timeout,
+                Err(e) => return e,
+            };
+            let Ok(keys) = args
+                .into_iter()
+                .map(RespValue::to_string)
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let max = command_name == "BZPOPMAX";
+            match store.blocking_zpop(*db, &keys, max, timeout).await {
+                Ok(Some((key, member, score))) => RespValue::Array(vec![
+                    RespValue::BulkString(key.into_bytes()),
+                    RespValue::BulkString(member.into_bytes()),
+                    RespValue::BulkString(format_score(score).into_bytes()),
+                ]),
+                Ok(None) => RespValue::Null,
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "ZREM" => {
+            if args.len() < 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'ZREM'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(members) = args
+                .into_iter()
+                .map(RespValue::to_string)
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                return RespValue::Error("ERR invalid member".into());
+            };
+            match store.zrem(*db, key, members).await {
+                Ok(removed) => RespValue::Integer(removed),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "ZRANDMEMBER" => {
+            if args.is_empty() || args.len() > 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'ZRANDMEMBER'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            // Sem `count`, a resposta é um único membro, e não um array.
+            if args.is_empty() {
+                return match store.zrandmember(*db, &key, 1).await {
+                    Ok(mut picked) => match picked.pop() {
+                        Some((member, _)) => RespValue::BulkString(member.into_bytes()),
+                        None => RespValue::Null,
+                    },
+                    Err(e) => RespValue::Error(e.to_string()),
+                };
+            }
+            let Some(count) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+            else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            if count < -(i64::MAX / 2) {
+                return RespValue::Error("ERR value is out of range".into());
+            }
+            let option = args.pop().map(|arg| arg.to_string().unwrap_or_default().to_uppercase());
+            let with_scores = match option.as_deref() {
+                None => false,
+                Some("WITHSCORES") => true,
+                Some(_) => return RespValue::Error("ERR syntax error".into()),
+            };
+            match store.zrandmember(*db, &key, count).await {
+                Ok(picked) => scored_reply(picked, with_scores),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "ZSCAN" => {
+            if args.len() < 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'ZSCAN'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Some(cursor) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<u64>().ok())
+            else {
+                return RespValue::Error("ERR invalid cursor".into());
+            };
+            let mut count = 10;
+            let mut pattern = None;
+            let mut args = args.into_iter().map(|arg| arg.to_string().unwrap_or_default());
+            while let Some(opt) = args.next() {
+                match opt.to_uppercase().as_str() {
+                    "MATCH" => match args.next() {
+                        Some(value) => pattern = Some(value),
+                        None => return RespValue::Error("ERR syntax error".into()),
+                    },
+                    "COUNT" => match args.next().and_then(|value| value.parse::<usize>().ok()) {
+                        Some(n) if n >= 1 => count = n,
+                        _ => return RespValue::Error("ERR syntax error".into()),
+                    },
+                    _ => return RespValue::Error("ERR syntax error".into()),
+                }
+            }
+            // Os membros vêm intercalados com os scores, como no `ZRANGE ... WITHSCORES`.
+            match store.zscan(*db, &key, cursor, count, pattern.as_deref()).await {
+                Ok((next, members)) => RespValue::Array(vec![
+                    RespValue::BulkString(next.to_string().into_bytes()),
+                    scored_reply(members, true),
+                ]),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "ZCARD" => {
+            if args.len() != 1 {
+                return RespValue::Error("ERR wrong number of arguments for 'ZCARD'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            match store.zcard(*db, &key).await {
+                Ok(len) => RespValue::Integer(len),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "ZCOUNT" => {
+            if args.len() != 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'ZCOUNT'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let (min, max) = (args.remove(0), args.remove(0));
+            let (Some(min), Some(max)) = (parse_score_bound(min), parse_score_bound(max)) else {
+                return RespValue::Error("ERR min or max is not a float".into());
+            };
+            match store.zcount(*db, &key, min, max).await {
+                Ok(count) => RespValue::Integer(count),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "ZRANGEBYSCORE" | "ZREVRANGEBYSCORE" => {
+            if args.len() < 3 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let rev = command_name == "ZREVRANGEBYSCORE";
+            let (first, second) = (args.remove(0), args.remove(0));
+            // O `ZREVRANGEBYSCORE` recebe o máximo antes do mínimo.
+            let (min, max) = if rev { (second, first) } else { (first, second) };
+            let (Some(min), Some(max)) = (parse_score_bound(min), parse_score_bound(max)) else {
+                return RespValue::Error("ERR min or max is not a float".into());
+            };
+            let mut with_scores = false;
+            let mut limit = None;
+            let mut args = args.into_iter().map(|arg| arg.to_string().unwrap_or_default());
+            while let Some(opt) = args.next() {
+                match opt.to_uppercase().as_str() {
+                    "WITHSCORES" => with_scores = true,
+                    "LIMIT" => {
+                        let (Some(offset), Some(count)) = (args.next(), args.next()) else {
+                            return RespValue::Error("ERR syntax error".into());
+                        };
+                        let (Ok(offset), Ok(count)) = (offset.parse(), count.parse()) else {
+                            return RespValue::Error(
+                                "ERR value is not an integer or out of range".into(),
+                            );
+                        };
+                        limit = Some((offset, count));
+                    }
+                    _ => return RespValue::Error("ERR syntax error".into()),
+                }
+            }
+            match store.zrange_by_score(*db, &key, min, max, rev, limit).await {
+                Ok(scored) => scored_reply(scored, with_scores),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "ZRANGEBYLEX" | "ZREVRANGEBYLEX" => {
+            if args.len() < 3 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let rev = command_name == "ZREVRANGEBYLEX";
+            let (first, second) = (args.remove(0), args.remove(0));
+            // Como no `ZREVRANGEBYSCORE`, o máximo vem antes do mínimo.
+            let (min, max) = if rev { (second, first) } else { (first, second) };
+            let (Some(min), Some(max)) = (parse_lex_bound(min), parse_lex_bound(max)) else {
+                return RespValue::Error("ERR min or max not valid string range item".into());
+            };
+            let mut limit = None;
+            let mut args = args.into_iter().map(|arg| arg.to_string().unwrap_or_default());
+            while let Some(opt) = args.next() {
+                if !opt.eq_ignore_ascii_case("LIMIT") {
+                    return RespValue::Error("ERR syntax error".into());
+                }
+                let (Some(offset), Some(count)) = (args.next(), args.next()) else {
+                    return RespValue::Error("ERR syntax error".into());
+                };
+                let (Ok(offset), Ok(count)) = (offset.parse(), count.parse()) else {
+                    return RespValue::Error("ERR value is not an integer or out of range".into());
+                };
+                limit = Some((offset, count));
+            }
+            match store.zrange_by_lex(*db, &key, min, max, rev, limit).await {
+                Ok(members) => members_reply(members),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "ZUNIONSTORE" | "ZINTERSTORE" => {
+            if args.len() < 3 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(destination) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let (keys, rest) = match parse_numkeys(args) {
+                Ok(parsed) => parsed,
+                Err(e) => return e,
+            };
+            let mut weights = vec![1.0; keys.len()];
+            let mut aggregate = Aggregate::Sum;
+            let mut rest = rest.into_iter();
+            while let Some(opt) = rest.next() {
+                let opt = opt.to_string().unwrap_or_default().to_uppercase();
+                match opt.as_str() {
+                    // `WEIGHTS` traz exatamente um peso por chave.
+                    "WEIGHTS" if rest.len() >= keys.len() => {
+                        for weight in weights.iter_mut() {
+                            let Some(value) = rest.next().and_then(parse_score) else {
+                                return RespValue::Error(
+                                    "ERR weight value is not a float".into(),
+                                );
+                            };
+                            *weight = value;
+                        }
+                    }
+                    "AGGREGATE" => {
+                        let name = rest.next().and_then(|arg| arg.to_string().ok());
+                        aggregate = match name.unwrap_or_default().to_uppercase().as_str() {
+                            "SUM" => Aggregate::Sum,
+                            "MIN" => Aggregate::Min,
+                            "MAX" => Aggregate::Max,
+                            _ => return RespValue::Error("ERR syntax error".into()),
+                        };
+                    }
+                    _ => return RespValue::Error("ERR syntax error".into()),
+                }
+            }
+            let op = if command_name == "ZINTERSTORE" { SetOp::Inter } else { SetOp::Union };
+            match store.zset_store(*db, op, destination, keys, weights, aggregate).await {
+                Ok(len) => RespValue::Integer(len),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "INCR" | "DECR" => {
+            if args.len() != 1 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let delta = if command_name == "INCR" { 1 } else { -1 };
+            match store.incr_by(*db, key, delta).await {
+                Ok(value) => RespValue::Integer(value),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "INCRBY" | "DECRBY" => {
+            if args.len() != 2 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Some(increment) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+            else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            let delta = if command_name == "INCRBY" {
+                increment
+            } else {
+                // `DECRBY k -9223372036854775808` não tem representação positiva.
+                let Some(negated) = increment.checked_neg() else {
+                    return RespValue::Error("ERR decrement would overflow".into());
+                };
+                negated
+            };
+            match store.incr_by(*db, key, delta).await {
+                Ok(value) => RespValue::Integer(value),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "INCRBYFLOAT" => {
+            if args.len() != 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'INCRBYFLOAT'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Some(increment) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<f64>().ok())
+                .filter(|f| !f.is_nan())
+            else {
+                return RespValue::Error("ERR value is not a valid float".into());
+            };
+            match store.incr_by_float(*db, key, increment).await {
+                Ok(value) => RespValue::BulkString(value.into_bytes()),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "APPEND" => {
+            if args.len() != 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'APPEND'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(value) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid value".into());
+            };
+            match store.append(*db, key, value).await {
+                Ok(len) => RespValue::Integer(len),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "SETRANGE" => {
+            if args.len() != 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'SETRANGE'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Some(offset) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+            else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            if offset < 0 {
+                return RespValue::Error("ERR offset is out of range".into());
+            }
+            let Ok(value) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid value".into());
+            };
+            match store.setrange(*db, key, offset as usize, value).await {
+                Ok(len) => RespValue::Integer(len),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "BITOP" => {
+            if args.len() < 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'BITOP'".into());
+            }
+            let op = match args.remove(0).to_string().unwrap_or_default().to_uppercase().as_str() {
+                "AND" => BitOp::And,
+                "OR" => BitOp::Or,
+                "XOR" => BitOp::Xor,
+                "NOT" => BitOp::Not,
+                _ => return RespValue::Error("ERR syntax error".into()),
+            };
+            let Ok(destination) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            if op == BitOp::Not && args.len() != 1 {
+                return RespValue::Error(
+                    "ERR BITOP NOT must be called with a single source key.".into(),
+                );
+            }
+            let Ok(keys) = args
+                .into_iter()
+                .map(RespValue::to_string)
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            match store.bitop(*db, op, destination, keys).await {
+                Ok(len) => RespValue::Integer(len),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "SETBIT" => {
+            if args.len() != 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'SETBIT'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Some(offset) = args.remove(0).to_string().ok().and_then(|s| s.parse::<u64>().ok())
+            else {
+                return RespValue::Error("ERR bit offset is not an integer or out of range".into());
+            };
+            let bit = match args.remove(0).to_string().ok().as_deref() {
+                Some("0") => false,
+                Some("1") => true,
+                _ => return RespValue::Error("ERR bit is not an integer or out of range".into()),
+            };
+            match store.setbit(*db, key, offset, bit).await {
+                Ok(previous) => RespValue::Integer(previous),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "GETBIT" => {
+            if args.len() != 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'GETBIT'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Some(offset) = args.remove(0).to_string().ok().and_then(|s| s.parse::<u64>().ok())
+            else {
+                return RespValue::Error("ERR bit offset is not an integer or out of range".into());
+            };
+            match store.getbit(*db, &key, offset).await {
+                Ok(bit) => RespValue::Integer(bit),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "BITCOUNT" => {
+            if args.is_empty() {
+                return RespValue::Error("ERR wrong number of arguments for 'BITCOUNT'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            // A faixa é opcional, mas `start` e `end` vêm sempre juntos.
+            if args.len() == 1 || args.len() > 3 {
+                return RespValue::Error("ERR syntax error".into());
+            }
+            let mut args = args.into_iter().map(|arg| arg.to_string().unwrap_or_default());
+            let range = match (args.next(), args.next()) {
+                (Some(start), Some(end)) => match (start.parse(), end.parse()) {
+                    (Ok(start), Ok(end)) => Some((start, end)),
+                    _ => {
+                        return RespValue::Error(
+                            "ERR value is not an integer or out of range".into(),
+                        )
+                    }
+                },
+                _ => None,
+            };
+            let Some(in_bits) = args.next().map_or(Some(false), |unit| bit_unit(&unit)) else {
+                return RespValue::Error("ERR syntax error".into());
+            };
+            match store.bitcount(*db, &key, range, in_bits).await {
+                Ok(count) => RespValue::Integer(count),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "BITPOS" => {
+            if args.len() < 2 || args.len() > 5 {
+                return RespValue::Error("ERR wrong number of arguments for 'BITPOS'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let mut args = args.into_iter().map(|arg| arg.to_string().unwrap_or_default());
+            let bit = match args.next().as_deref() {
+                Some("0") => false,
+                Some("1") => true,
+                _ => return RespValue::Error("ERR The bit argument must be 1 or 0.".into()),
+            };
+            let (start, end) = (args.next(), args.next());
+            let Ok(start) = start.map_or(Ok(0), |start| start.parse::<i64>()) else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            let Ok(end) = end.map(|end| end.parse::<i64>()).transpose() else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            let Some(in_bits) = args.next().map_or(Some(false), |unit| bit_unit(&unit)) else {
+                return RespValue::Error("ERR syntax error".into());
+            };
+            match store.bitpos(*db, &key, bit, start, end, in_bits).await {
+                Ok(position) => RespValue::Integer(position),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "BITFIELD" => {
+            if args.is_empty() {
+                return RespValue::Error("ERR wrong number of arguments for 'BITFIELD'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            // O `OVERFLOW` vale para os `SET` e `INCRBY` que vêm depois dele.
+            let mut ops = Vec::new();
+            let mut overflow = Overflow::Wrap;
+            let mut args = args.into_iter().map(|arg| arg.to_string().unwrap_or_default());
+            while let Some(subcommand) = args.next() {
+                let subcommand = subcommand.to_uppercase();
+                if subcommand == "OVERFLOW" {
+                    overflow = match args.next().unwrap_or_default().to_uppercase().as_str() {
+                        "WRAP" => Overflow::Wrap,
+                        "SAT" => Overflow::Sat,
+                        "FAIL" => Overflow::Fail,
+                        _ => return RespValue::Error("ERR Invalid OVERFLOW type specified".into()),
+                    };
+                    continue;
+                }
+                if !matches!(subcommand.as_str(), "GET" | "SET" | "INCRBY") {
+                    return RespValue::Error("ERR syntax error".into());
+                }
+                let (Some(field_type), Some(offset)) = (args.next(), args.next()) else {
+                    return RespValue::Error("ERR syntax error".into());
+                };
+                let field = match parse_bitfield(&field_type, &offset) {
+                    Ok(field) => field,
+                    Err(e) => return e,
+                };
+                if subcommand == "GET" {
+                    ops.push(bitfield::Op::Get(field));
+                    continue;
+                }
+                let Some(argument) = args.next() else {
+                    return RespValue::Error("ERR syntax error".into());
+                };
+                let Ok(argument) = argument.parse::<i64>() else {
+                    return RespValue::Error("ERR value is not an integer or out of range".into());
+                };
+                ops.push(if subcommand == "SET" {
+                    bitfield::Op::Set { field, value: argument, overflow }
+                } else {
+                    bitfield::Op::IncrBy { field, increment: argument, overflow }
+                });
+            }
+            match store.bitfield(*db, key, ops).await {
+                Ok(results) => RespValue::Array(
+                    results
+                        .into_iter()
+                        .map(|result| result.map_or(RespValue::Null, RespValue::Integer))
+                        .collect(),
+                ),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "PFADD" => {
+            if args.is_empty() {
+                return RespValue::Error("ERR wrong number of arguments for 'PFADD'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(elements) = args
+                .into_iter()
+                .map(RespValue::to_string)
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                return RespValue::Error("ERR invalid element".into());
+            };
+            match store.pfadd(*db, key, elements).await {
+                Ok(changed) => RespValue::Integer(changed),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "PFCOUNT" => {
+            if args.is_empty() {
+                return RespValue::Error("ERR wrong number of arguments for 'PFCOUNT'".into());
+            }
+            let Ok(keys) = args
+                .into_iter()
+                .map(RespValue::to_string)
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            match store.pfcount(*db, &keys).await {
+                Ok(count) => RespValue::Integer(count),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "PFMERGE" => {
+            if args.is_empty() {
+                return RespValue::Error("ERR wrong number of arguments for 'PFMERGE'".into());
+            }
+            let Ok(destination) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(keys) = args
+                .into_iter()
+                .map(RespValue::to_string)
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            match store.pfmerge(*db, destination, keys).await {
+                Ok(()) => RespValue::SimpleString("OK".into()),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "EXISTS" => {
+            if args.is_empty() {
+                return RespValue::Error("ERR wrong number of arguments for 'EXISTS'".into());
+            }
+            let keys: Vec<String> = args
+                .into_iter()
+                .map(|arg| arg.to_string().unwrap_or_default())
+                .collect();
+            RespValue::Integer(store.exists(*db, &keys).await)
+        }
+
+        "TOUCH" => {
+            if args.is_empty() {
+                return RespValue::Error("ERR wrong number of arguments for 'TOUCH'".into());
+            }
+            let keys: Vec<String> = args
+                .into_iter()
+                .map(|arg| arg.to_string().unwrap_or_default())
+                .collect();
+            RespValue::Integer(store.touch(*db, &keys).await)
+        }
+
+        "RENAME" | "RENAMENX" => {
+            if args.len() != 2 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Ok(new_key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let nx = command_name == "RENAMENX";
+            match store.rename(*db, key, new_key, nx).await {
+                Ok(renamed) if nx => RespValue::Integer(renamed as i64),
+                Ok(_) => RespValue::SimpleString("OK".into()),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "OBJECT" => {
+            if args.is_empty() {
+                return RespValue::Error("ERR wrong number of arguments for 'OBJECT'".into());
+            }
+            let name = args.remove(0).to_string().unwrap_or_default();
+            let subcommand = name.to_uppercase();
+            if subcommand == "HELP" && args.is_empty() {
+                let lines = [
+                    "OBJECT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                    "ENCODING <key>",
+                    "    Return the kind of internal representation used in order to store the value",
+                    "    associated with a <key>.",
+                    "IDLETIME <key>",
+                    "    Return the idle time of the <key>, that is the approximated number of",
+                    "    seconds elapsed since the last access to the key.",
+                    "REFCOUNT <key>",
+                    "    Return the number of references of the value associated with the specified",
+                    "    <key>.",
+                    "HELP",
+                    "    Print this help.",
+                ];
+                return RespValue::Array(
+                    lines
+                        .iter()
+                        .map(|line| RespValue::SimpleString(line.to_string()))
+                        .collect(),
+                );
+            }
+            if !matches!(subcommand.as_str(), "ENCODING" | "IDLETIME" | "REFCOUNT")
+                || args.len() != 1
+            {
+                return RespValue::Error(format!(
+                    "ERR unknown subcommand or wrong number of arguments for '{}'. Try OBJECT HELP.",
+                    name
+                ));
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let reply = match subcommand.as_str() {
+                "ENCODING" => store
+                    .encoding(*db, &key)
+                    .await
+                    .map(|encoding| RespValue::BulkString(encoding.as_bytes().to_vec())),
+                "IDLETIME" => store
+                    .idle_time(*db, &key)
+                    .await
+                    .map(|idle| RespValue::Integer(idle.as_secs() as i64)),
+                // Valores não são compartilhados entre chaves, então sempre há uma referência.
+                _ => (store.exists(*db, std::slice::from_ref(&key)).await == 1)
+                    .then_some(RespValue::Integer(1)),
+            };
+            reply.unwrap_or(RespValue::Null)
+        }
+
+        "DUMP" => {
+            if args.len() != 1 {
+                return RespValue::Error("ERR wrong number of arguments for 'DUMP'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            match store.dump(*db, &key).await {
+                Some(payload) => RespValue::BulkString(payload),
+                None => RespValue::Null,
+            }
+        }
+
+        "RESTORE" => {
+            if args.len() < 3 {
+                return RespValue::Error("ERR wrong number of arguments for 'RESTORE'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Some(ttl) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+            else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            // O payload é binário, então não passa por `to_string`.
+            let RespValue::BulkString(payload) = args.remove(0) else {
+                return RespValue::Error("ERR DUMP payload version or checksum are wrong".into());
+            };
+            let (mut replace, mut absolute) = (false, false);
+            for arg in args {
+                match arg.to_string().unwrap_or_default().to_uppercase().as_str() {
+                    "REPLACE" => replace = true,
+                    "ABSTTL" => absolute = true,
+                    _ => return RespValue::Error("ERR syntax error".into()),
+                }
+            }
+            if ttl < 0 {
+                return RespValue::Error("ERR Invalid TTL value, must be >= 0".into());
+            }
+            // TTL 0 significa sem expiração; com `ABSTTL`, é um timestamp em milissegundos.
+            let expiry = match ttl {
+                0 => None,
+                _ if absolute => Some(from_unix_millis(ttl)),
+                _ => match expiry_instant("PX", ttl) {
+                    Some(at) => Some(at),
+                    None => {
+                        return RespValue::Error(
+                            "ERR invalid expire time in 'restore' command".into(),
+                        )
+                    }
+                },
+            };
+            let value = match dump::deserialize(&payload) {
+                Ok(value) => value,
+                Err(e) => return RespValue::Error(e.into()),
+            };
+            match store.restore(*db, key, value, expiry, replace).await {
+                Ok(()) => RespValue::SimpleString("OK".into()),
+                Err(e) => RespValue::Error(e.to_string()),
+            }
+        }
+
+        "SORT" => {
+            if args.is_empty() {
+                return RespValue::Error("ERR wrong number of arguments for 'SORT'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let mut options = SortOptions::default();
+            let mut destination = None;
+            let mut args = args.into_iter().map(|arg| arg.to_string().unwrap_or_default());
+            while let Some(opt) = args.next() {
+                match opt.to_uppercase().as_str() {
+                    "ASC" => options.desc = false,
+                    "DESC" => options.desc = true,
+                    "ALPHA" => options.alpha = true,
+                    "LIMIT" => {
+                        let offset = args.next().and_then(|s| s.parse::<i64>().ok());
+                        let count = args.next().and_then(|s| s.parse::<i64>().ok());
+                        let (Some(offset), Some(count)) = (offset, count) else {
+                            return RespValue::Error("ERR syntax error".into());
+                        };
+                        options.limit = Some((offset, count));
+                    }
+                    "STORE" => match args.next() {
+                        Some(dest) => destination = Some(dest),
+                        None => return RespValue::Error("ERR syntax error".into()),
+                    },
+                    // Ainda não há suporte a padrões; melhor recusar do que ignorar a opção.
+                    "BY" | "GET" => {
+                        return RespValue::Error(format!(
+                            "ERR SORT {} patterns are not supported",
+                            opt.to_uppercase()
+                        ))
+                    }
+                    _ => return RespValue::Error("ERR syntax error".into()),
+                }
+            }
+            match destination {
+                Some(destination) => match store.sort_store(*db, key, destination, options).await {
+                    Ok(len) => RespValue::Integer(len),
+                    Err(e) => RespValue::Error(e.to_string()),
+                },
+                None => match store.sort(*db, &key, options).await {
+                    Ok(elements) => RespValue::Array(
+                        elements
+                            .into_iter()
+                            .map(|e| RespValue::BulkString(e.into_bytes()))
+                            .collect(),
+                    ),
+                    Err(e) => RespValue::Error(e.to_string()),
+                },
+            }
+        }
+
+        "SELECT" => {
+            if args.len() != 1 {
+                return RespValue::Error("ERR wrong number of arguments for 'SELECT'".into());
+            }
+            let Some(index) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+            else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            let Some(index) = db_index(store, index) else {
+                return RespValue::Error("ERR DB index is out of range".into());
+            };
+            *db = index;
+            RespValue::SimpleString("OK".into())
+        }
+
+        "SWAPDB" => {
+            if args.len() != 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'SWAPDB'".into());
+            }
+            let mut indexes = args.into_iter().map(|arg| {
+                arg.to_string()
+                    .ok()
+                    .and_then(|s| s.parse::<i64>().ok())
+                    .and_then(|index| db_index(store, index))
+            });
+            let Some(first) = indexes.next().flatten() else {
+                return RespValue::Error("ERR invalid first DB index".into());
+            };
+            let Some(second) = indexes.next().flatten() else {
+                return RespValue::Error("ERR invalid second DB index".into());
+            };
+            store.swapdb(first, second).await;
+            RespValue::SimpleString("OK".into())
+        }
+
+        "MOVE" => {
+            if args.len() != 2 {
+                return RespValue::Error("ERR wrong number of arguments for 'MOVE'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Some(target) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+            else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            let Some(target) = db_index(store, target) else {
+                return RespValue::Error("ERR DB index is out of range".into());
+            };
+            if target == *db {
+                return RespValue::Error("ERR source and destination objects are the same".into());
+            }
+            RespValue::Integer(store.move_to(*db, key, target).await as i64)
+        }
+
+        "DBSIZE" => {
+            if !args.is_empty() {
+                return RespValue::Error("ERR wrong number of arguments for 'DBSIZE'".into());
+            }
+            RespValue::Integer(store.dbsize(*db).await)
+        }
+
+        "TYPE" => {
+            if args.len() != 1 {
+                return RespValue::Error("ERR wrong number of arguments for 'TYPE'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            RespValue::SimpleString(store.type_of(*db, &key).await.into())
+        }
+
+        "EXPIRE" | "PEXPIRE" | "EXPIREAT" | "PEXPIREAT" => {
+            if args.len() < 2 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            let Some(amount) = args
+                .remove(0)
+                .to_string()
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+            else {
+                return RespValue::Error("ERR value is not an integer or out of range".into());
+            };
+            let mut condition = ExpireCondition::default();
+            for arg in args {
+                let flag = arg.to_string().unwrap_or_default().to_uppercase();
+                match flag.as_str() {
+                    "NX" => condition.nx = true,
+                    "XX" => condition.xx = true,
+                    "GT" => condition.gt = true,
+                    "LT" => condition.lt = true,
+                    _ => {
+                        return RespValue::Error(format!("ERR Unsupported option {}", flag));
+                    }
+                }
+            }
+            if condition.nx && (condition.xx || condition.gt || condition.lt) {
+                return RespValue::Error(
+                    "ERR NX and XX, GT or LT options at the same time are not compatible".into(),
+                );
+            }
+            if condition.gt && condition.lt {
+                return RespValue::Error(
+                    "ERR GT and LT options at the same time are not compatible".into(),
+                );
+            }
+
+            // Um TTL zero ou negativo, ou um instante no passado, resulta num prazo já
+            // vencido, que remove a chave.
+            let unit = match command_name.as_str() {
+                "EXPIRE" => "EX",
+                "PEXPIRE" => "PX",
+                "EXPIREAT" => "EXAT",
+                _ => "PXAT",
+            };
+            let Some(at) = expiry_instant(unit, amount) else {
+                return RespValue::Error(format!(
+                    "ERR invalid expire time in '{}' command",
+                    command_name.to_lowercase()
+                ));
+            };
+            RespValue::Integer(store.expire(*db, key, at, condition).await as i64)
+        }
+
+        "EXPIRETIME" | "PEXPIRETIME" => {
+            if args.len() != 1 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            match store.expire_time(*db, &key).await {
+                None => RespValue::Integer(-2),
+                Some(None) => RespValue::Integer(-1),
+                Some(Some(at)) if command_name == "PEXPIRETIME" => {
+                    RespValue::Integer(to_unix_millis(at))
+                }
+                Some(Some(at)) => RespValue::Integer(to_unix_millis(at) / 1000),
+            }
+        }
+
+        "PERSIST" => {
+            if args.len() != 1 {
+                return RespValue::Error("ERR wrong number of arguments for 'PERSIST'".into());
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            RespValue::Integer(store.persist(*db, key).await as i64)
+        }
+
+        "TTL" | "PTTL" => {
+            if args.len() != 1 {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let Ok(key) = args.remove(0).to_string() else {
+                return RespValue::Error("ERR invalid key".into());
+            };
+            match store.ttl(*db, &key).await {
+                None => RespValue::Integer(-2),
+                Some(None) => RespValue::Integer(-1),
+                Some(Some(remaining)) if command_name == "PTTL" => {
+                    RespValue::Integer(remaining.as_millis() as i64)
+                }
+                // Frações de segundo são arredondadas para cima: 1.2s restantes viram 2.
+                Some(Some(remaining)) => RespValue::Integer(
+                    (remaining.as_secs() + (remaining.subsec_nanos() > 0) as u64) as i64,
+                ),
+            }
+        }
+
+        // `UNLINK` responde como o `DEL`; a diferença é que valores grandes são liberados em
+        // background, sem segurar as demais escritas.
+        "DEL" | "UNLINK" => {
+            if args.is_empty() {
+                return RespValue::Error(format!(
+                    "ERR wrong number of arguments for '{}'",
+                    command_name
+                ));
+            }
+            let mut deleted_count = 0;
+            for arg in args {
+                if let Ok(key) = arg.to_string() {
+                    let deleted = if command_name == "UNLINK" {
+                        store.unlink(*db, &key).await
+                    } else {
+                        store.delete(*db, &key).await
+                    };
+                    if deleted {
+                        deleted_count += 1;
+                    }
+                }
+            }
+            RespValue::Integer(deleted_count)
+        }
+
+        _ => RespValue::Error(format!("ERR unknown command '{}'", command_name)),
+    }
+}
+
+/// Lê o timeout de um comando bloqueante, em segundos e com frações. Zero é `None`: esperar
+/// para sempre.
+fn parse_timeout(arg: RespValue) -> Result<Option<Duration>, RespValue> {
+    let Some(seconds) = arg.to_string().ok().and_then(|s| s.parse::<f64>().ok()) else {
+        return Err(RespValue::Error("ERR timeout is not a float or out of range".into()));
+    };
+    if seconds < 0.0 {
+        return Err(RespValue::Error("ERR timeout is negative".into()));
+    }
+    if seconds == 0.0 {
+        return Ok(None);
+    }
+    Duration::try_from_secs_f64(seconds)
+        .map(Some)
+        .map_err(|_| RespValue::Error("ERR timeout is out of range".into()))
+}
+
+/// Lê o score de um membro de conjunto ordenado. Aceita `inf`, `+inf` e `-inf`, mas não NaN.
+fn parse_score(arg: RespValue) -> Option<f64> {
+    arg.to_string().ok()?.parse::<f64>().ok().filter(|score| !score.is_nan())
+}
+
+/// Lê um limite de faixa de scores: um score, opcionalmente precedido de `(` para excluí-lo.
+fn parse_score_bound(arg: RespValue) -> Option<ScoreBound> {
+    let arg = arg.to_string().ok()?;
+    let (value, exclusive) = match arg.strip_prefix('(') {
+        Some(value) => (value, true),
+        None => (arg.as_str(), false),
+    };
+    let value = value.parse::<f64>().ok().filter(|value| !value.is_nan())?;
+    Some(ScoreBound { value, exclusive })
+}
+
+/// Lê um limite do `ZRANGEBYLEX`: `-`, `+`, `[membro` ou `(membro`.
+fn parse_lex_bound(arg: RespValue) -> Option<LexBound> {
+    let arg = arg.to_string().ok()?;
+    match arg.as_str() {
+        "-" => Some(LexBound::Min),
+        "+" => Some(LexBound::Max),
+        _ => match arg.split_at_checked(1)? {
+            ("[", member) => Some(LexBound::Included(member.to_string())),
+            ("(", member) => Some(LexBound::Excluded(member.to_string())),
+            _ => None,
+        },
+    }
+}
+
+/// Resposta com membros de um conjunto ordenado; com `with_scores`, cada membro vem seguido
+/// do seu score, no mesmo array.
+fn scored_reply(scored: Vec<(String, f64)>, with_scores: bool) -> RespValue {
+    let mut items = Vec::with_capacity(if with_scores { scored.len() * 2 } else { scored.len() });
+    for (member, score) in scored {
+        items.push(RespValue::BulkString(member.into_bytes()));
+        if with_scores {
+            items.push(RespValue::BulkString(format_score(score).into_bytes()));
+        }
+    }
+    RespValue::Array(items)
+}
+
+/// Lê o tipo e a posição de um campo do `BITFIELD`. A posição pode vir como `#N`, que é o
+/// N-ésimo campo daquele tipo: `N` vezes a largura.
+fn parse_bitfield(field_type: &str, offset: &str) -> Result<bitfield::Field, RespValue> {
+    let Some((signed, bits)) = bitfield::Field::parse_type(field_type) else {
+        return Err(RespValue::Error(
+            "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is."
+                .into(),
+        ));
+    };
+    let offset = match offset.strip_prefix('#') {
+        Some(index) => index.parse::<u64>().ok().and_then(|index| index.checked_mul(bits as u64)),
+        None => offset.parse::<u64>().ok(),
+    };
+    let Some(offset) = offset else {
+        return Err(RespValue::Error("ERR bit offset is not an integer or out of range".into()));
+    };
+    Ok(bitfield::Field { signed, bits, offset })
+}
+
+/// Lê a unidade das faixas de `BITCOUNT` e `BITPOS`: `true` para `BIT` e `false` para `BYTE`.
+fn bit_unit(arg: &str) -> Option<bool> {
+    match arg.to_uppercase().as_str() {
+        "BYTE" => Some(false),
+        "BIT" => Some(true),
+        _ => None,
+    }
+}
+
+/// Lê a ponta de uma lista dada como `LEFT` ou `RIGHT`.
+fn list_end(arg: RespValue) -> Option<ListEnd> {
+    match arg.to_string().ok()?.to_uppercase().as_str() {
+        "LEFT" => Some(ListEnd::Left),
+        "RIGHT" => Some(ListEnd::Right),
+        _ => None,
+    }
+}
+
+/// Lê o trecho `numkeys key [key ...]` que abre comandos como o `LMPOP`. Retorna as chaves e
+/// os argumentos que vêm depois delas.
+fn parse_numkeys(
+    mut args: Vec<RespValue>,
+) -> Result<(Vec<String>, Vec<RespValue>), RespValue> {
+    let Some(numkeys) = args.remove(0).to_string().ok().and_then(|n| n.parse::<i64>().ok())
+    else {
+        return Err(RespValue::Error("ERR value is not an integer or out of range".into()));
+    };
+    if numkeys <= 0 {
+        return Err(RespValue::Error("ERR numkeys should be greater than 0".into()));
+    }
+    if numkeys as u64 > args.len() as u64 {
+        return Err(RespValue::Error(
+            "ERR Number of keys can't be greater than number of args".into(),
+        ));
+    }
+    let rest = args.split_off(numkeys as usize);
+    let keys = args
+        .into_iter()
+        .map(RespValue::to_string)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| RespValue::Error("ERR invalid key".into()))?;
+    Ok((keys, rest))
+}
+
+/// Lê o trecho `FIELDS numfields field [field ...]` dos comandos de TTL de campos, que
+/// precisa ocupar todos os argumentos restantes.
+fn parse_fields(args: Vec<RespValue>) -> Result<Vec<String>, RespValue> {
+    let mut args = args.into_iter().map(|arg| arg.to_string().unwrap_or_default());
+    if !args.next().is_some_and(|arg| arg.eq_ignore_ascii_case("FIELDS")) {
+        return Err(RespValue::Error(
+            "ERR Mandatory argument FIELDS is missing or not at the right position".into(),
+        ));
+    }
+    let Some(count) = args.next().and_then(|n| n.parse::<i64>().ok()) else {
+        return Err(RespValue::Error("ERR value is not an integer or out of range".into()));
+    };
+    if count <= 0 {
+        return Err(RespValue::Error(
+            "ERR Parameter `numFields` should be greater than 0".into(),
+        ));
+    }
+    let fields: Vec<String> = args.collect();
+    if fields.len() as i64 != count {
+        return Err(RespValue::Error(
+            "ERR The `numfields` parameter must match the number of arguments".into(),
+        ));
+    }
+    Ok(fields)
+}
+
+/// Resposta com os membros de um conjunto, como a do `SMEMBERS`. No RESP2 é um array comum;
+/// um futuro RESP3 responderia aqui com o tipo conjunto.
+fn members_reply(members: Vec<String>) -> RespValue {
+    RespValue::Array(
+        members
+            .into_iter()
+            .map(|member| RespValue::BulkString(member.into_bytes()))
+            .collect(),
+    )
+}
+
+/// Resposta de um código inteiro por item, como a do `HEXPIRE`.
+fn integers_reply(values: Vec<i64>) -> RespValue {
+    RespValue::Array(values.into_iter().map(RespValue::Integer).collect())
+}
+
+/// Resposta com pares chave/valor, como a do `HGETALL`. No RESP2 os pares vão achatados num
+/// único array (`campo1, valor1, campo2, valor2...`).
+fn pairs_reply(pairs: Vec<(String, String)>) -> RespValue {
+    RespValue::Array(
+        pairs
+            .into_iter()
+            .flat_map(|(k, v)| {
+                [
+                    RespValue::BulkString(k.into_bytes()),
+                    RespValue::BulkString(v.into_bytes()),
+                ]
+            })
+            .collect(),
+    )
+}
+
+/// Valida o índice de um banco vindo de um comando como `SELECT` ou `MOVE`.
+fn db_index(store: &Store, index: i64) -> Option<usize> {
+    usize::try_from(index).ok().filter(|&i| i < store.databases.len())
+}
+
+/// Converte o argumento de uma opção de expiração (`EX`, `PX`, `EXAT` ou `PXAT`) no instante
+/// absoluto em que a chave expira. Retorna `None` se o instante não for representável.
+fn expiry_instant(unit: &str, amount: i64) -> Option<SystemTime> {
+    match unit {
+        "EX" => SystemTime::now().checked_add(Duration::from_secs(amount.max(0) as u64)),
+        "PX" => SystemTime::now().checked_add(Duration::from_millis(amount.max(0) as u64)),
+        "EXAT" => amount.checked_mul(1000).map(from_unix_millis),
+        _ => Some(from_unix_millis(amount)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> Store {
+        let (store, background_task) = Store::new(16, 512 * 1024 * 1024);
+        tokio::spawn(background_task);
+        store
+    }
+
+    /// Executa um comando como se viesse de um cliente já autenticado, no banco 0.
+    async fn run(store: &Store, args: &[&str]) -> RespValue {
+        let frame = RespValue::Array(
+            args.iter().map(|arg| RespValue::BulkString(arg.as_bytes().to_vec())).collect(),
+        );
+        process_command(frame, store, &mut 0, &mut true, &None).await
+    }
+
+    fn bulk(value: &[u8]) -> RespValue {
+        RespValue::BulkString(value.to_vec())
+    }
+
+    fn command(args: &[&str]) -> Vec<u8> {
+        let args = args.iter().map(|arg| bulk(arg.as_bytes())).collect();
+        serialize_resp(RespValue::Array(args))
+    }
+
+    /// Sobe um servidor numa porta livre, atendendo uma conexão, e retorna o cliente.
+    async fn connect(store: &Store) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let store = Arc::new(store.clone());
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let _ = handle_connection(socket, store, None).await;
+        });
+        TcpStream::connect(address).await.unwrap()
+    }
+
+    /// Lê `count` respostas completas do servidor.
+    async fn replies(client: &mut TcpStream, count: usize) -> Vec<RespValue> {
+        let mut buffer = Vec::new();
+        loop {
+            let mut parsed = Vec::new();
+            let mut rest = &buffer[..];
+            while let Ok((remaining, reply)) = parse_resp(rest) {
+                parsed.push(reply);
+                rest = remaining;
+            }
+            if parsed.len() >= count {
+                return parsed;
+            }
+            let mut chunk = [0; 4096];
+            let read = client.read(&mut chunk).await.unwrap();
+            assert!(read > 0, "conexão encerrada antes das respostas");
+            buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    #[tokio::test]
+    async fn persist_removes_the_ttl() {
+        let store = store();
+        let integer = RespValue::Integer;
+        assert_eq!(run(&store, &["PERSIST", "k"]).await, integer(0));
+        run(&store, &["SET", "k", "v"]).await;
+        assert_eq!(run(&store, &["PERSIST", "k"]).await, integer(0));
+        assert_eq!(run(&store, &["EXPIRE", "k", "100"]).await, integer(1));
+        assert_eq!(run(&store, &["TTL", "k"]).await, integer(100));
+        assert_eq!(run(&store, &["PERSIST", "k"]).await, integer(1));
+        assert_eq!(run(&store, &["TTL", "k"]).await, integer(-1));
+        assert_eq!(run(&store, &["PERSIST", "k"]).await, integer(0));
+        assert_eq!(run(&store, &["GET", "k"]).await, bulk(b"v"));
+    }
+
+    #[tokio::test]
+    async fn lmpop_argument_errors() {
+        let store = store();
+        run(&store, &["RPUSH", "b", "1", "2", "3"]).await;
+        let error = |message: &str| RespValue::Error(message.into());
+        let syntax = error("ERR syntax error");
+        let count = error("ERR count should be greater than 0");
+        let cases: &[(&[&str], RespValue)] = &[
+            (&["LMPOP", "0", "a", "LEFT"], error("ERR numkeys should be greater than 0")),
+            (&["LMPOP", "-1", "a", "LEFT"], error("ERR numkeys should be greater than 0")),
+            (&["LMPOP", "x", "a", "LEFT"], error("ERR value is not an integer or out of range")),
+            (
+                &["LMPOP", "3", "a", "b"],
+                error("ERR Number of keys can't be greater than number of args"),
+            ),
+            (&["LMPOP", "1", "b"], error("ERR wrong number of arguments for 'LMPOP'")),
+            // Com `numkeys` 2, o `LEFT` é lido como chave e a ponta fica faltando.
+            (&["LMPOP", "2", "b", "LEFT"], syntax.clone()),
+            (&["LMPOP", "1", "b", "UP"], syntax.clone()),
+            (&["LMPOP", "1", "b", "COUNT", "1"], syntax.clone()),
+            (&["LMPOP", "1", "b", "LEFT", "COUNT", "0"], count.clone()),
+            (&["LMPOP", "1", "b", "LEFT", "COUNT", "-1"], count),
+            (&["LMPOP", "1", "b", "LEFT", "COUNT"], syntax.clone()),
+            (&["LMPOP", "1", "b", "LEFT", "COUNT", "1", "COUNT", "2"], syntax.clone()),
+            (&["LMPOP", "1", "b", "LEFT", "LIMIT", "1"], syntax),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(&run(&store, args).await, expected, "{args:?}");
+        }
+        // Nenhuma das chamadas inválidas tocou na lista.
+        assert_eq!(run(&store, &["LLEN", "b"]).await, RespValue::Integer(3));
+        let popped = run(&store, &["LMPOP", "2", "a", "b", "right", "count", "2"]).await;
+        let expected = RespValue::Array(vec![
+            bulk(b"b"),
+            RespValue::Array(vec![bulk(b"3"), bulk(b"2")]),
+        ]);
+        assert_eq!(popped, expected);
+    }
+
+    #[tokio::test]
+    async fn pipelined_rpush_and_ltrim() {
+        let store = store();
+        let mut client = connect(&store).await;
+        // Os três comandos chegam no mesmo pacote.
+        let mut pipeline = command(&["RPUSH", "log", "a", "b", "c", "d", "e"]);
+        pipeline.extend(command(&["LTRIM", "log", "-3", "-1"]));
+        pipeline.extend(command(&["LLEN", "log"]));
+        client.write_all(&pipeline).await.unwrap();
+        let expected = [
+            RespValue::Integer(5),
+            RespValue::SimpleString("OK".into()),
+            RespValue::Integer(3),
+        ];
+        assert_eq!(replies(&mut client, 3).await, expected);
+        assert_eq!(run(&store, &["LINDEX", "log", "0"]).await, bulk(b"c"));
+
+        // Um `LTRIM` que esvazia a lista remove a chave.
+        let mut pipeline = command(&["RPUSH", "log", "f"]);
+        pipeline.extend(command(&["LTRIM", "log", "5", "10"]));
+        client.write_all(&pipeline).await.unwrap();
+        let expected = [RespValue::Integer(4), RespValue::SimpleString("OK".into())];
+        assert_eq!(replies(&mut client, 2).await, expected);
+        assert_eq!(run(&store, &["EXISTS", "log"]).await, RespValue::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn pipelined_capped_log() {
+        let store = store();
+        let mut client = connect(&store).await;
+        // Milhares de pares numa única escrita: os frames chegam partidos entre as leituras.
+        let mut pipeline = Vec::new();
+        for i in 0..3000 {
+            pipeline.extend(command(&["RPUSH", "log", &i.to_string()]));
+            pipeline.extend(command(&["LTRIM", "log", "-1000", "-1"]));
+        }
+        client.write_all(&pipeline).await.unwrap();
+        let replies = replies(&mut client, 6000).await;
+        assert_eq!(replies[0], RespValue::Integer(1));
+        assert_eq!(replies[5998], RespValue::Integer(1001));
+        let ok = RespValue::SimpleString("OK".into());
+        assert!(replies.iter().skip(1).step_by(2).all(|reply| *reply == ok));
+        assert_eq!(run(&store, &["LLEN", "log"]).await, RespValue::Integer(1000));
+        assert_eq!(run(&store, &["LINDEX", "log", "0"]).await, bulk(b"2000"));
+        assert_eq!(run(&store, &["LINDEX", "log", "-1"]).await, bulk(b"2999"));
+    }
+
+    #[tokio::test]
+    async fn zadd_rejects_conflicting_flags() {
+        let store = store();
+        let error = |reply| matches!(reply, RespValue::Error(_));
+        assert!(error(run(&store, &["ZADD", "z", "NX", "XX", "1", "a"]).await));
+        assert!(error(run(&store, &["ZADD", "z", "GT", "LT", "1", "a"]).await));
+        assert!(error(run(&store, &["ZADD", "z", "NX", "GT", "1", "a"]).await));
+        assert!(error(run(&store, &["ZADD", "z", "INCR", "1", "a", "2", "b"]).await));
+        assert_eq!(run(&store, &["EXISTS", "z"]).await, RespValue::Integer(0));
+        let reply = run(&store, &["ZADD", "z", "XX", "GT", "CH", "INCR", "1", "a"]).await;
+        assert_eq!(reply, RespValue::Null);
+    }
+
+    #[test]
+    fn parse_bitfield_offsets() {
+        let field = |field_type, offset| parse_bitfield(field_type, offset).unwrap();
+        assert_eq!(field("u8", "13").offset, 13);
+        // `#N` é o N-ésimo campo do tipo, contando de 0.
+        assert_eq!(field("u8", "#2").offset, 16);
+        assert_eq!(field("i5", "#3").offset, 15);
+        assert_eq!(field("i64", "#0").offset, 0);
+        assert!(parse_bitfield("u8", "#-1").is_err());
+        assert!(parse_bitfield("i64", &format!("#{}", u64::MAX / 32)).is_err());
+        assert!(parse_bitfield("u64", "0").is_err());
+    }
+
+    #[tokio::test]
+    async fn bitfield_grows_the_string() {
+        let store = store();
+        let reply = run(&store, &["BITFIELD", "k", "SET", "u8", "#2", "255"]).await;
+        assert_eq!(reply, RespValue::Array(vec![RespValue::Integer(0)]));
+        assert_eq!(run(&store, &["GET", "k"]).await, bulk(&[0, 0, 255]));
+
+        // A string cresce até o maior campo escrito mesmo que o `FAIL` impeça a escrita.
+        let reply = run(&store, &["BITFIELD", "k", "OVERFLOW", "FAIL", "SET", "u8", "36", "256"]);
+        assert_eq!(reply.await, RespValue::Array(vec![RespValue::Null]));
+        assert_eq!(run(&store, &["GET", "k"]).await, bulk(&[0, 0, 255, 0, 0, 0]));
+
+        // Só leituras não criam nem alteram a chave.
+        let reply = run(&store, &["BITFIELD", "other", "GET", "i64", "1000"]).await;
+        assert_eq!(reply, RespValue::Array(vec![RespValue::Integer(0)]));
+        assert_eq!(run(&store, &["EXISTS", "other"]).await, RespValue::Integer(0));
+    }
+}
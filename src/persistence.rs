@@ -1,6 +1,12 @@
-use crate::data_types::{Command, KeyMetadata, Value};
+use crate::data_types::{
+    to_unix_millis, Aggregate, BitOp, Command, KeyMetadata, ListEnd, SetCondition, SetExpiry,
+    SetOp, TtlUpdate, Value,
+};
+use crate::bitfield::{self, Overflow};
+use crate::dump;
 use crate::resp::{serialize_resp, RespValue};
-use crate::store::Store;
+use crate::store::{format_float, Store};
+use crate::zset::format_score;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
@@ -13,10 +19,24 @@ use tokio::time::interval;
 
 #[derive(Serialize, Deserialize)]
 struct Snapshot {
+    databases: Vec<DatabaseSnapshot>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DatabaseSnapshot {
     data: HashMap<String, Value>,
     metadata: HashMap<String, KeyMetadata>,
 }
 
+/// Formatos de snapshot aceitos na carga. Snapshots anteriores aos múltiplos bancos guardavam
+/// só o banco 0, direto na raiz do arquivo.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SnapshotFile {
+    Databases(Snapshot),
+    Legacy(DatabaseSnapshot),
+}
+
 // Adicionamos Clone
 #[derive(Clone)]
 pub struct PersistenceManager {
@@ -77,9 +97,19 @@ impl PersistenceManager {
         };
 
         let mut cmd_rx = self.store.cmd_tx.subscribe();
-        while let Ok(cmd) = cmd_rx.recv().await {
-            let resp_cmd = self.command_to_resp(cmd);
-            let bytes = serialize_resp(resp_cmd);
+        // O arquivo pode ter terminado em outro banco numa execução anterior, então o primeiro
+        // comando sempre é precedido de um `SELECT`.
+        let mut selected = None;
+        while let Ok((db, cmd)) = cmd_rx.recv().await {
+            let mut bytes = Vec::new();
+            if selected != Some(db) {
+                bytes.extend(serialize_resp(RespValue::Array(vec![
+                    RespValue::BulkString(b"SELECT".to_vec()),
+                    RespValue::BulkString(db.to_string().into_bytes()),
+                ])));
+                selected = Some(db);
+            }
+            bytes.extend(serialize_resp(self.command_to_resp(cmd)));
             if let Err(e) = file.write_all(&bytes) {
                 eprintln!("[Persistence] Erro ao escrever no arquivo AOF: {}", e);
             }
@@ -89,14 +119,18 @@ impl PersistenceManager {
     async fn create_snapshot(&self) -> io::Result<()> {
         let temp_path = self.snapshot_path.with_extension("tmp");
 
-        let snapshot = Snapshot {
-            data: self.store.data.read().await.clone(),
-            metadata: self.store.metadata.read().await.clone(),
-        };
+        let mut databases = Vec::with_capacity(self.store.databases.len());
+        for database in self.store.databases.iter() {
+            databases.push(DatabaseSnapshot {
+                data: (**database.data.read().await).clone(),
+                metadata: database.metadata.read().await.clone(),
+            });
+        }
+        let snapshot = Snapshot { databases };
 
         let file = File::create(&temp_path)?;
         serde_json::to_writer(BufWriter::new(file), &snapshot)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            .map_err(io::Error::other)?;
 
         fs::rename(temp_path, &self.snapshot_path).await?;
         println!("[Persistence] Snapshot salvo em {}", self.snapshot_path.display());
@@ -105,42 +139,573 @@ impl PersistenceManager {
 
     async fn load_snapshot(&self) -> io::Result<()> {
         let content = fs::read(&self.snapshot_path).await?;
-        let snapshot: Snapshot = serde_json::from_slice(&content)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let snapshot: SnapshotFile = serde_json::from_slice(&content)
+            .map_err(io::Error::other)?;
+        let databases = match snapshot {
+            SnapshotFile::Databases(snapshot) => snapshot.databases,
+            SnapshotFile::Legacy(database) => vec![database],
+        };
 
-        *self.store.data.write().await = snapshot.data;
-        *self.store.metadata.write().await = snapshot.metadata;
+        if databases.len() > self.store.databases.len() {
+            eprintln!(
+                "[Persistence] Snapshot tem {} bancos, mas só {} estão configurados; os demais foram ignorados",
+                databases.len(),
+                self.store.databases.len()
+            );
+        }
+        for (snapshot, database) in databases.into_iter().zip(self.store.databases.iter()) {
+            // Snapshots antigos só tinham metadados para chaves com TTL.
+            let mut metadata = snapshot.metadata;
+            for key in snapshot.data.keys() {
+                metadata.entry(key.clone()).or_default();
+            }
+            database.replace(snapshot.data, metadata).await;
+        }
         Ok(())
     }
 
     fn command_to_resp(&self, cmd: Command) -> RespValue {
         match cmd {
-            Command::Set { key, value, expiry } => {
+            Command::Set { key, value, expiry, condition, .. } => {
                 let mut args = vec![
                     RespValue::BulkString(b"SET".to_vec()),
                     RespValue::BulkString(key.into_bytes()),
                 ];
-                if let Value::String(s) = value {
+                if let Some(s) = value.into_string() {
                     args.push(RespValue::BulkString(s.into_bytes()));
                 }
-                if let Some(d) = expiry {
-                    args.push(RespValue::BulkString(b"PX".to_vec()));
-                    args.push(RespValue::BulkString(
-                        d.as_millis().to_string().into_bytes(),
-                    ));
+                match expiry {
+                    SetExpiry::Clear => {}
+                    // Sem o `KEEPTTL`, a reprodução do AOF apagaria o TTL da chave.
+                    SetExpiry::Keep => args.push(RespValue::BulkString(b"KEEPTTL".to_vec())),
+                    // Um `PX` recomeçaria a contar na reprodução; a task de escrita só publica
+                    // prazos absolutos.
+                    SetExpiry::After(_) => unreachable!("SET publicado com prazo relativo"),
+                    // Prazo absoluto, para que a reprodução respeite o mesmo horário.
+                    SetExpiry::At(at) => {
+                        args.push(RespValue::BulkString(b"PXAT".to_vec()));
+                        args.push(RespValue::BulkString(
+                            to_unix_millis(at).to_string().into_bytes(),
+                        ));
+                    }
+                }
+                match condition {
+                    SetCondition::Always => {}
+                    SetCondition::IfNotExists => args.push(RespValue::BulkString(b"NX".to_vec())),
+                    SetCondition::IfExists => args.push(RespValue::BulkString(b"XX".to_vec())),
                 }
                 RespValue::Array(args)
             }
-            Command::HSet { key, field, value } => RespValue::Array(vec![
-                RespValue::BulkString(b"HSET".to_vec()),
+            Command::HSet { key, pairs } => {
+                let mut args = vec![
+                    RespValue::BulkString(b"HSET".to_vec()),
+                    RespValue::BulkString(key.into_bytes()),
+                ];
+                for (field, value) in pairs {
+                    args.push(RespValue::BulkString(field.into_bytes()));
+                    args.push(RespValue::BulkString(value.into_bytes()));
+                }
+                RespValue::Array(args)
+            }
+            Command::HIncrBy { key, field, delta } => RespValue::Array(vec![
+                RespValue::BulkString(b"HINCRBY".to_vec()),
+                RespValue::BulkString(key.into_bytes()),
+                RespValue::BulkString(field.into_bytes()),
+                RespValue::BulkString(delta.to_string().into_bytes()),
+            ]),
+            Command::HIncrByFloat { key, field, increment } => RespValue::Array(vec![
+                RespValue::BulkString(b"HINCRBYFLOAT".to_vec()),
                 RespValue::BulkString(key.into_bytes()),
                 RespValue::BulkString(field.into_bytes()),
+                RespValue::BulkString(format_float(increment).into_bytes()),
+            ]),
+            Command::HSetNx { key, field, value } => RespValue::Array(vec![
+                RespValue::BulkString(b"HSETNX".to_vec()),
+                RespValue::BulkString(key.into_bytes()),
+                RespValue::BulkString(field.into_bytes()),
+                RespValue::BulkString(value.into_bytes()),
+            ]),
+            Command::HDel { key, fields } => {
+                let mut args = vec![
+                    RespValue::BulkString(b"HDEL".to_vec()),
+                    RespValue::BulkString(key.into_bytes()),
+                ];
+                args.extend(fields.into_iter().map(|f| RespValue::BulkString(f.into_bytes())));
+                RespValue::Array(args)
+            }
+            // Como no `EXPIRE`, o prazo vai como instante absoluto.
+            Command::HExpire { key, fields, at, .. } => {
+                let mut args = vec![
+                    RespValue::BulkString(b"HPEXPIREAT".to_vec()),
+                    RespValue::BulkString(key.into_bytes()),
+                    RespValue::BulkString(to_unix_millis(at).to_string().into_bytes()),
+                    RespValue::BulkString(b"FIELDS".to_vec()),
+                    RespValue::BulkString(fields.len().to_string().into_bytes()),
+                ];
+                args.extend(fields.into_iter().map(|f| RespValue::BulkString(f.into_bytes())));
+                RespValue::Array(args)
+            }
+            Command::HPersist { key, fields } => {
+                let mut args = vec![
+                    RespValue::BulkString(b"HPERSIST".to_vec()),
+                    RespValue::BulkString(key.into_bytes()),
+                    RespValue::BulkString(b"FIELDS".to_vec()),
+                    RespValue::BulkString(fields.len().to_string().into_bytes()),
+                ];
+                args.extend(fields.into_iter().map(|f| RespValue::BulkString(f.into_bytes())));
+                RespValue::Array(args)
+            }
+            Command::SAdd { key, members } => {
+                let mut args = vec![
+                    RespValue::BulkString(b"SADD".to_vec()),
+                    RespValue::BulkString(key.into_bytes()),
+                ];
+                args.extend(members.into_iter().map(|m| RespValue::BulkString(m.into_bytes())));
+                RespValue::Array(args)
+            }
+            Command::SRem { key, members } => {
+                let mut args = vec![
+                    RespValue::BulkString(b"SREM".to_vec()),
+                    RespValue::BulkString(key.into_bytes()),
+                ];
+                args.extend(members.into_iter().map(|m| RespValue::BulkString(m.into_bytes())));
+                RespValue::Array(args)
+            }
+            // Nunca chega aqui: `propagated` troca o `SPOP` pelo `SREM` dos membros sorteados.
+            Command::SPop { .. } => unreachable!("SPOP é registrado como SREM"),
+            // O `format_score` volta exatamente ao mesmo score na reprodução.
+            // As condições são reproduzidas sobre o mesmo estado, então vão como vieram. O `INCR`
+            // nunca chega aqui: `propagated` o troca pelo score resultante.
+            Command::ZAdd { key, pairs, options } => {
+                let mut args = vec![
+                    RespValue::BulkString(b"ZADD".to_vec()),
+                    RespValue::BulkString(key.into_bytes()),
+                ];
+                let flags = [
+                    (options.nx, "NX"),
+                    (options.xx, "XX"),
+                    (options.gt, "GT"),
+                    (options.lt, "LT"),
+                    (options.ch, "CH"),
+                ];
+                for (_, flag) in flags.into_iter().filter(|(set, _)| *set) {
+                    args.push(RespValue::BulkString(flag.as_bytes().to_vec()));
+                }
+                for (score, member) in pairs {
+                    args.push(RespValue::BulkString(format_score(score).into_bytes()));
+                    args.push(RespValue::BulkString(member.into_bytes()));
+                }
+                RespValue::Array(args)
+            }
+            Command::ZIncrBy { key, member, increment } => RespValue::Array(vec![
+                RespValue::BulkString(b"ZINCRBY".to_vec()),
+                RespValue::BulkString(key.into_bytes()),
+                RespValue::BulkString(format_score(increment).into_bytes()),
+                RespValue::BulkString(member.into_bytes()),
+            ]),
+            // Nunca chega aqui: `propagated` troca o `ZPOPMIN`/`ZPOPMAX` pelo `ZREM` dos membros
+            // retirados.
+            Command::ZPop { .. } => unreachable!("ZPOPMIN e ZPOPMAX são registrados como ZREM"),
+            Command::ZRem { key, members } => {
+                let mut args = vec![
+                    RespValue::BulkString(b"ZREM".to_vec()),
+                    RespValue::BulkString(key.into_bytes()),
+                ];
+                args.extend(members.into_iter().map(|m| RespValue::BulkString(m.into_bytes())));
+                RespValue::Array(args)
+            }
+            Command::SMove { source, destination, member } => RespValue::Array(vec![
+                RespValue::BulkString(b"SMOVE".to_vec()),
+                RespValue::BulkString(source.into_bytes()),
+                RespValue::BulkString(destination.into_bytes()),
+                RespValue::BulkString(member.into_bytes()),
+            ]),
+            Command::Push { key, elements, end, .. } => {
+                let name = match end {
+                    ListEnd::Left => b"LPUSH".to_vec(),
+                    ListEnd::Right => b"RPUSH".to_vec(),
+                };
+                let mut args = vec![
+                    RespValue::BulkString(name),
+                    RespValue::BulkString(key.into_bytes()),
+                ];
+                args.extend(elements.into_iter().map(|e| RespValue::BulkString(e.into_bytes())));
+                RespValue::Array(args)
+            }
+            Command::Pop { key, count, end } => {
+                let name = match end {
+                    ListEnd::Left => b"LPOP".to_vec(),
+                    ListEnd::Right => b"RPOP".to_vec(),
+                };
+                RespValue::Array(vec![
+                    RespValue::BulkString(name),
+                    RespValue::BulkString(key.into_bytes()),
+                    RespValue::BulkString(count.to_string().into_bytes()),
+                ])
+            }
+            // Nunca chega aqui: `propagated` troca o `LMPOP` aplicado por um `LPOP`/`RPOP` da
+            // chave escolhida.
+            Command::MPop { .. } => unreachable!("LMPOP é registrado como LPOP ou RPOP"),
+            Command::LSet { key, index, element } => RespValue::Array(vec![
+                RespValue::BulkString(b"LSET".to_vec()),
+                RespValue::BulkString(key.into_bytes()),
+                RespValue::BulkString(index.to_string().into_bytes()),
+                RespValue::BulkString(element.into_bytes()),
+            ]),
+            Command::LInsert { key, before, pivot, element } => RespValue::Array(vec![
+                RespValue::BulkString(b"LINSERT".to_vec()),
+                RespValue::BulkString(key.into_bytes()),
+                RespValue::BulkString(if before { b"BEFORE".to_vec() } else { b"AFTER".to_vec() }),
+                RespValue::BulkString(pivot.into_bytes()),
+                RespValue::BulkString(element.into_bytes()),
+            ]),
+            Command::LRem { key, count, element } => RespValue::Array(vec![
+                RespValue::BulkString(b"LREM".to_vec()),
+                RespValue::BulkString(key.into_bytes()),
+                RespValue::BulkString(count.to_string().into_bytes()),
+                RespValue::BulkString(element.into_bytes()),
+            ]),
+            Command::LTrim { key, start, stop } => RespValue::Array(vec![
+                RespValue::BulkString(b"LTRIM".to_vec()),
+                RespValue::BulkString(key.into_bytes()),
+                RespValue::BulkString(start.to_string().into_bytes()),
+                RespValue::BulkString(stop.to_string().into_bytes()),
+            ]),
+            // Mesmo vindo de um `RPOPLPUSH`, o AOF registra o `LMOVE` equivalente.
+            Command::LMove { source, destination, from, to } => {
+                let end = |end| match end {
+                    ListEnd::Left => b"LEFT".to_vec(),
+                    ListEnd::Right => b"RIGHT".to_vec(),
+                };
+                RespValue::Array(vec![
+                    RespValue::BulkString(b"LMOVE".to_vec()),
+                    RespValue::BulkString(source.into_bytes()),
+                    RespValue::BulkString(destination.into_bytes()),
+                    RespValue::BulkString(end(from)),
+                    RespValue::BulkString(end(to)),
+                ])
+            }
+            Command::Delete { key }
+            | Command::DeleteExpired { key }
+            | Command::Unlink { key }
+            | Command::GetDel { key } => {
+                RespValue::Array(vec![
+                    RespValue::BulkString(b"DEL".to_vec()),
+                    RespValue::BulkString(key.into_bytes()),
+                ])
+            }
+            Command::IncrBy { key, delta } => RespValue::Array(vec![
+                RespValue::BulkString(b"INCRBY".to_vec()),
+                RespValue::BulkString(key.into_bytes()),
+                RespValue::BulkString(delta.to_string().into_bytes()),
+            ]),
+            Command::IncrByFloat { key, increment } => RespValue::Array(vec![
+                RespValue::BulkString(b"INCRBYFLOAT".to_vec()),
+                RespValue::BulkString(key.into_bytes()),
+                RespValue::BulkString(format_float(increment).into_bytes()),
+            ]),
+            Command::Append { key, value } => RespValue::Array(vec![
+                RespValue::BulkString(b"APPEND".to_vec()),
+                RespValue::BulkString(key.into_bytes()),
+                RespValue::BulkString(value.into_bytes()),
+            ]),
+            Command::SetRange { key, offset, value } => RespValue::Array(vec![
+                RespValue::BulkString(b"SETRANGE".to_vec()),
+                RespValue::BulkString(key.into_bytes()),
+                RespValue::BulkString(offset.to_string().into_bytes()),
+                RespValue::BulkString(value.into_bytes()),
+            ]),
+            Command::SetBit { key, offset, bit } => RespValue::Array(vec![
+                RespValue::BulkString(b"SETBIT".to_vec()),
+                RespValue::BulkString(key.into_bytes()),
+                RespValue::BulkString(offset.to_string().into_bytes()),
+                RespValue::BulkString(if bit { b"1".to_vec() } else { b"0".to_vec() }),
+            ]),
+            // Só as escritas vão para o AOF, cada uma precedida do seu `OVERFLOW` quando ele muda.
+            // As posições já vão resolvidas, sem a forma `#N`.
+            Command::BitField { key, ops } => {
+                let mut args = vec![
+                    RespValue::BulkString(b"BITFIELD".to_vec()),
+                    RespValue::BulkString(key.into_bytes()),
+                ];
+                let mut current = Overflow::Wrap;
+                for op in ops {
+                    let (name, field, argument, overflow) = match op {
+                        bitfield::Op::Get(_) => continue,
+                        bitfield::Op::Set { field, value, overflow } => {
+                            ("SET", field, value, overflow)
+                        }
+                        bitfield::Op::IncrBy { field, increment, overflow } => {
+                            ("INCRBY", field, increment, overflow)
+                        }
+                    };
+                    if overflow != current {
+                        let mode = match overflow {
+                            Overflow::Wrap => "WRAP",
+                            Overflow::Sat => "SAT",
+                            Overflow::Fail => "FAIL",
+                        };
+                        args.push(RespValue::BulkString(b"OVERFLOW".to_vec()));
+                        args.push(RespValue::BulkString(mode.as_bytes().to_vec()));
+                        current = overflow;
+                    }
+                    let op = [
+                        name.to_string(),
+                        field.type_name(),
+                        field.offset.to_string(),
+                        argument.to_string(),
+                    ];
+                    args.extend(op.map(|arg| RespValue::BulkString(arg.into_bytes())));
+                }
+                RespValue::Array(args)
+            }
+            // O valor antigo só interessa ao cliente; no AOF basta o `SET`.
+            Command::GetSet { key, value } => RespValue::Array(vec![
+                RespValue::BulkString(b"SET".to_vec()),
+                RespValue::BulkString(key.into_bytes()),
                 RespValue::BulkString(value.into_bytes()),
             ]),
-            Command::Delete { key } => RespValue::Array(vec![
-    RespValue::BulkString(b"DEL".to_vec()),
-    RespValue::BulkString(key.into_bytes()),
-]),
+            // O valor lido não precisa ir para o AOF, só a alteração do TTL.
+            Command::GetEx { key, ttl: TtlUpdate::ExpireAt(at) }
+            | Command::Expire { key, at, .. } => RespValue::Array(vec![
+                RespValue::BulkString(b"PEXPIREAT".to_vec()),
+                RespValue::BulkString(key.into_bytes()),
+                RespValue::BulkString(to_unix_millis(at).to_string().into_bytes()),
+            ]),
+            Command::GetEx { key, ttl: TtlUpdate::Persist } | Command::Persist { key } => {
+                RespValue::Array(vec![
+                    RespValue::BulkString(b"PERSIST".to_vec()),
+                    RespValue::BulkString(key.into_bytes()),
+                ])
+            }
+            Command::Move { key, db } => RespValue::Array(vec![
+                RespValue::BulkString(b"MOVE".to_vec()),
+                RespValue::BulkString(key.into_bytes()),
+                RespValue::BulkString(db.to_string().into_bytes()),
+            ]),
+            Command::SwapDb { first, second } => RespValue::Array(vec![
+                RespValue::BulkString(b"SWAPDB".to_vec()),
+                RespValue::BulkString(first.to_string().into_bytes()),
+                RespValue::BulkString(second.to_string().into_bytes()),
+            ]),
+            Command::Rename { key, new_key, nx } => RespValue::Array(vec![
+                RespValue::BulkString(if nx { b"RENAMENX".to_vec() } else { b"RENAME".to_vec() }),
+                RespValue::BulkString(key.into_bytes()),
+                RespValue::BulkString(new_key.into_bytes()),
+            ]),
+            // A origem no AOF está no mesmo estado de quando o comando foi aplicado, então basta
+            // reproduzir o próprio `SORT`.
+            Command::SortStore { key, destination, options } => {
+                let mut args = vec![
+                    RespValue::BulkString(b"SORT".to_vec()),
+                    RespValue::BulkString(key.into_bytes()),
+                ];
+                if let Some((offset, count)) = options.limit {
+                    args.push(RespValue::BulkString(b"LIMIT".to_vec()));
+                    args.push(RespValue::BulkString(offset.to_string().into_bytes()));
+                    args.push(RespValue::BulkString(count.to_string().into_bytes()));
+                }
+                if options.desc {
+                    args.push(RespValue::BulkString(b"DESC".to_vec()));
+                }
+                if options.alpha {
+                    args.push(RespValue::BulkString(b"ALPHA".to_vec()));
+                }
+                args.push(RespValue::BulkString(b"STORE".to_vec()));
+                args.push(RespValue::BulkString(destination.into_bytes()));
+                RespValue::Array(args)
+            }
+            // Como no `SORT ... STORE`, as origens no AOF estão no mesmo estado de quando o
+            // comando foi aplicado.
+            Command::SetStore { op, destination, keys } => {
+                let name = match op {
+                    SetOp::Inter => b"SINTERSTORE".to_vec(),
+                    SetOp::Union => b"SUNIONSTORE".to_vec(),
+                    SetOp::Diff => b"SDIFFSTORE".to_vec(),
+                };
+                let mut args = vec![
+                    RespValue::BulkString(name),
+                    RespValue::BulkString(destination.into_bytes()),
+                ];
+                args.extend(keys.into_iter().map(|k| RespValue::BulkString(k.into_bytes())));
+                RespValue::Array(args)
+            }
+            Command::BitOp { op, destination, keys } => {
+                let op = match op {
+                    BitOp::And => b"AND".to_vec(),
+                    BitOp::Or => b"OR".to_vec(),
+                    BitOp::Xor => b"XOR".to_vec(),
+                    BitOp::Not => b"NOT".to_vec(),
+                };
+                let mut args = vec![
+                    RespValue::BulkString(b"BITOP".to_vec()),
+                    RespValue::BulkString(op),
+                    RespValue::BulkString(destination.into_bytes()),
+                ];
+                args.extend(keys.into_iter().map(|k| RespValue::BulkString(k.into_bytes())));
+                RespValue::Array(args)
+            }
+            Command::PfAdd { key, elements } => {
+                let mut args = vec![
+                    RespValue::BulkString(b"PFADD".to_vec()),
+                    RespValue::BulkString(key.into_bytes()),
+                ];
+                args.extend(elements.into_iter().map(|e| RespValue::BulkString(e.into_bytes())));
+                RespValue::Array(args)
+            }
+            Command::PfMerge { destination, keys } => {
+                let mut args = vec![
+                    RespValue::BulkString(b"PFMERGE".to_vec()),
+                    RespValue::BulkString(destination.into_bytes()),
+                ];
+                args.extend(keys.into_iter().map(|k| RespValue::BulkString(k.into_bytes())));
+                RespValue::Array(args)
+            }
+            // Pesos e agregação só vão quando diferem do padrão.
+            Command::ZSetStore { op, destination, keys, weights, aggregate } => {
+                let name = match op {
+                    SetOp::Inter => b"ZINTERSTORE".to_vec(),
+                    SetOp::Union => b"ZUNIONSTORE".to_vec(),
+                    SetOp::Diff => unreachable!("não há ZDIFFSTORE"),
+                };
+                let mut args = vec![
+                    RespValue::BulkString(name),
+                    RespValue::BulkString(destination.into_bytes()),
+                    RespValue::BulkString(keys.len().to_string().into_bytes()),
+                ];
+                args.extend(keys.into_iter().map(|k| RespValue::BulkString(k.into_bytes())));
+                if weights.iter().any(|weight| *weight != 1.0) {
+                    args.push(RespValue::BulkString(b"WEIGHTS".to_vec()));
+                    args.extend(
+                        weights
+                            .iter()
+                            .map(|weight| RespValue::BulkString(format_score(*weight).into_bytes())),
+                    );
+                }
+                let aggregate = match aggregate {
+                    Aggregate::Sum => None,
+                    Aggregate::Min => Some(b"MIN".to_vec()),
+                    Aggregate::Max => Some(b"MAX".to_vec()),
+                };
+                if let Some(aggregate) = aggregate {
+                    args.push(RespValue::BulkString(b"AGGREGATE".to_vec()));
+                    args.push(RespValue::BulkString(aggregate));
+                }
+                RespValue::Array(args)
+            }
+            // Prazo absoluto (`ABSTTL`), para que a reprodução respeite o mesmo horário.
+            Command::Restore { key, value, expiry, replace } => {
+                let ttl = expiry.map_or(0, to_unix_millis);
+                let mut args = vec![
+                    RespValue::BulkString(b"RESTORE".to_vec()),
+                    RespValue::BulkString(key.into_bytes()),
+                    RespValue::BulkString(ttl.to_string().into_bytes()),
+                    RespValue::BulkString(dump::serialize(&value)),
+                    RespValue::BulkString(b"ABSTTL".to_vec()),
+                ];
+                if replace {
+                    args.push(RespValue::BulkString(b"REPLACE".to_vec()));
+                }
+                RespValue::Array(args)
+            }
+            Command::MSet { pairs } | Command::MSetNx { pairs } => {
+                let mut args = vec![RespValue::BulkString(b"MSET".to_vec())];
+                for (key, value) in pairs {
+                    args.push(RespValue::BulkString(key.into_bytes()));
+                    args.push(RespValue::BulkString(value.into_bytes()));
+                }
+                RespValue::Array(args)
+            }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::ListEnd;
+
+    fn store() -> Arc<Store> {
+        let (store, background_task) = Store::new(16, 512 * 1024 * 1024);
+        tokio::spawn(background_task);
+        Arc::new(store)
+    }
+
+    /// Salva um snapshot de `store` num arquivo temporário e o carrega num store novo.
+    async fn round_trip(store: Arc<Store>, name: &str) -> Arc<Store> {
+        let path = std::env::temp_dir()
+            .join(format!("altilium-{}-{name}.json", std::process::id()));
+        let manager = |store| PersistenceManager::new(store, path.clone(), PathBuf::new(), 60);
+        manager(store).create_snapshot().await.unwrap();
+        let loaded = self::store();
+        manager(loaded.clone()).load_from_disk().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        loaded
+    }
+
+    #[tokio::test]
+    async fn snapshot_keeps_list_order() {
+        let store = store();
+        let push = |key: &str, elements: &[&str], end| {
+            let elements = elements.iter().map(|e| e.to_string()).collect();
+            store.push(0, key.to_string(), elements, end, false)
+        };
+        push("l", &["c", "d"], ListEnd::Right).await.unwrap();
+        push("l", &["b", "a"], ListEnd::Left).await.unwrap();
+        push("l", &["e"], ListEnd::Right).await.unwrap();
+        push("other", &["x"], ListEnd::Left).await.unwrap();
+        store.push(3, "l".into(), vec!["db3".into()], ListEnd::Right, false).await.unwrap();
+
+        let loaded = round_trip(store, "lists").await;
+        let list = |db, key| {
+            let loaded = loaded.clone();
+            async move {
+                match loaded.get(db, key).await {
+                    Some(Value::List(list)) => Vec::from(list),
+                    _ => panic!("{key} não é uma lista"),
+                }
+            }
+        };
+        assert_eq!(list(0, "l").await, ["a", "b", "c", "d", "e"]);
+        assert_eq!(list(0, "other").await, ["x"]);
+        assert_eq!(list(3, "l").await, ["db3"]);
+        // A lista carregada continua aceitando escritas nas duas pontas.
+        let pushed = loaded.push(0, "l".into(), vec!["z".into()], ListEnd::Left, false).await;
+        assert_eq!(pushed, Ok(6));
+        assert_eq!(list(0, "l").await, ["z", "a", "b", "c", "d", "e"]);
+    }
+
+    #[tokio::test]
+    async fn snapshot_keeps_set_members() {
+        let store = store();
+        let members: Vec<String> = (0..1000).map(|i| format!("m{i}")).collect();
+        store.sadd(0, "s".into(), members.clone()).await.unwrap();
+        store.srem(0, "s".into(), vec!["m0".into(), "m999".into()]).await.unwrap();
+        store.sadd(0, "one".into(), vec!["".into()]).await.unwrap();
+        store.sadd(0, "gone".into(), vec!["a".into()]).await.unwrap();
+        store.srem(0, "gone".into(), vec!["a".into()]).await.unwrap();
+
+        let loaded = round_trip(store, "sets").await;
+        let members_of = |key| {
+            let loaded = loaded.clone();
+            async move {
+                match loaded.get(0, key).await {
+                    Some(Value::Set(set)) => {
+                        let mut members: Vec<String> = set.into_iter().collect();
+                        members.sort();
+                        members
+                    }
+                    _ => panic!("{key} não é um conjunto"),
+                }
+            }
+        };
+        let mut expected = members[1..999].to_vec();
+        expected.sort();
+        assert_eq!(members_of("s").await, expected);
+        assert_eq!(members_of("one").await, [String::new()]);
+        assert!(loaded.get(0, "gone").await.is_none());
+        // O conjunto carregado continua deduplicando membros.
+        assert_eq!(loaded.sadd(0, "s".into(), vec!["m1".into(), "m0".into()]).await, Ok(1));
+        assert_eq!(members_of("s").await.len(), 999);
+    }
+}
@@ -1,7 +1,9 @@
+// Os parsers são os `streaming` do nom: um frame que ainda não chegou inteiro resulta em
+// `Incomplete`, e a conexão espera por mais bytes em vez de tratar o buffer como inválido.
 use nom::{
     branch::alt,
-    bytes::complete::{is_not, tag, take},
-    character::complete::{crlf, i64},
+    bytes::streaming::{is_not, tag, take},
+    character::streaming::{crlf, i64},
     multi::count,
     sequence::{preceded, terminated},
     IResult,
@@ -19,7 +21,7 @@ pub enum RespValue {
 }
 
 impl RespValue {
-    
+    #[allow(clippy::wrong_self_convention)]
     pub fn to_string(self) -> Result<String, FromUtf8Error> {
         match self {
             RespValue::BulkString(bytes) => String::from_utf8(bytes),
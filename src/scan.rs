@@ -0,0 +1,292 @@
+use serde::{Deserialize, Serialize, Serializer};
+use std::borrow::Borrow;
+use std::collections::{hash_set, BTreeSet, HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::Deref;
+
+/// Chaves de uma coleção ordenadas pelo hash, para que o `SCAN` retome do cursor sem percorrer
+/// a coleção inteira.
+#[derive(Debug, Clone, Default)]
+struct ScanIndex(BTreeSet<(u64, String)>);
+
+impl ScanIndex {
+    fn insert(&mut self, key: &str) {
+        self.0.insert((key_hash(key), key.to_string()));
+    }
+
+    fn remove(&mut self, key: String) -> String {
+        self.0.take(&(key_hash(&key), key)).expect("chave presente no índice").1
+    }
+
+    /// Percorre o índice em lotes, na ordem do hash de cada chave. O cursor é o menor hash ainda
+    /// não visitado, então uma chave presente do início ao fim da iteração é retornada pelo
+    /// menos uma vez, mesmo que outras sejam inseridas ou removidas entre as chamadas. Chaves
+    /// com o mesmo hash sempre vêm no mesmo lote.
+    ///
+    /// Só o trecho a partir do cursor é percorrido, então cada chamada custa O(log n + lote), e
+    /// uma iteração completa visita cada chave uma vez. Retorna o próximo cursor (0 quando a
+    /// iteração termina) e as chaves do lote. `count` é apenas uma dica: o lote pode ter mais
+    /// chaves quando há colisões de hash.
+    fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<&str>) {
+        let count = count.max(1);
+        let mut batch = Vec::new();
+        let mut last = None;
+        for (hash, key) in self.0.range((cursor, String::new())..) {
+            // O lote só fecha entre hashes diferentes. O próximo hash é maior que o de qualquer
+            // chave já retornada, então nunca é 0.
+            if batch.len() >= count && last != Some(*hash) {
+                return (*hash, batch);
+            }
+            last = Some(*hash);
+            batch.push(key.as_str());
+        }
+        (0, batch)
+    }
+}
+
+/// Mapa com um [`ScanIndex`] das chaves. A leitura vai direto ao `HashMap`; as escritas passam
+/// pelos métodos abaixo, que mantêm o índice em dia.
+///
+/// Só o mapa é persistido; o índice é reconstruído na carga.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "HashMap<String, V>")]
+pub struct ScanMap<V> {
+    map: HashMap<String, V>,
+    index: ScanIndex,
+}
+
+impl<V> ScanMap<V> {
+    pub fn insert(&mut self, key: String, value: V) -> Option<V> {
+        if !self.map.contains_key(&key) {
+            self.index.insert(&key);
+        }
+        self.map.insert(key, value)
+    }
+
+    pub fn remove<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        String: Borrow<Q>,
+    {
+        self.remove_entry(key).map(|(_, value)| value)
+    }
+
+    pub fn remove_entry<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<(String, V)>
+    where
+        String: Borrow<Q>,
+    {
+        let (key, value) = self.map.remove_entry(key)?;
+        Some((self.index.remove(key), value))
+    }
+
+    pub fn get_mut<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        String: Borrow<Q>,
+    {
+        self.map.get_mut(key)
+    }
+
+    /// Valor da chave, inserindo o de `default` se ela não existir, como o `entry` do `HashMap`.
+    pub fn get_or_insert_with(&mut self, key: String, default: impl FnOnce() -> V) -> &mut V {
+        if !self.map.contains_key(&key) {
+            self.index.insert(&key);
+        }
+        self.map.entry(key).or_insert_with(default)
+    }
+
+    /// Um lote do `SCAN` sobre as entradas do mapa; veja [`ScanIndex::scan`].
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&str, &V)>) {
+        let (next, keys) = self.index.scan(cursor, count);
+        (next, keys.into_iter().map(|key| (key, &self.map[key])).collect())
+    }
+}
+
+impl<V> Default for ScanMap<V> {
+    fn default() -> Self {
+        ScanMap { map: HashMap::new(), index: ScanIndex::default() }
+    }
+}
+
+impl<V> Deref for ScanMap<V> {
+    type Target = HashMap<String, V>;
+
+    fn deref(&self) -> &HashMap<String, V> {
+        &self.map
+    }
+}
+
+impl<V> From<HashMap<String, V>> for ScanMap<V> {
+    fn from(map: HashMap<String, V>) -> Self {
+        let mut index = ScanIndex::default();
+        map.keys().for_each(|key| index.insert(key));
+        ScanMap { map, index }
+    }
+}
+
+impl<V> From<ScanMap<V>> for HashMap<String, V> {
+    fn from(map: ScanMap<V>) -> Self {
+        map.map
+    }
+}
+
+impl<V: Serialize> Serialize for ScanMap<V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.map.serialize(serializer)
+    }
+}
+
+/// Conjunto com um [`ScanIndex`] dos membros, nos mesmos moldes do [`ScanMap`].
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(from = "HashSet<String>")]
+pub struct ScanSet {
+    set: HashSet<String>,
+    index: ScanIndex,
+}
+
+impl ScanSet {
+    /// Adiciona o membro; retorna `false` se ele já existia.
+    pub fn insert(&mut self, member: String) -> bool {
+        if self.set.contains(&member) {
+            return false;
+        }
+        self.index.insert(&member);
+        self.set.insert(member)
+    }
+
+    /// Remove o membro; retorna `false` se ele não existia.
+    pub fn remove<Q: Hash + Eq + ?Sized>(&mut self, member: &Q) -> bool
+    where
+        String: Borrow<Q>,
+    {
+        self.set.take(member).map(|member| self.index.remove(member)).is_some()
+    }
+
+    /// Um lote do `SSCAN`; veja [`ScanIndex::scan`].
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<&str>) {
+        self.index.scan(cursor, count)
+    }
+}
+
+impl Deref for ScanSet {
+    type Target = HashSet<String>;
+
+    fn deref(&self) -> &HashSet<String> {
+        &self.set
+    }
+}
+
+impl From<HashSet<String>> for ScanSet {
+    fn from(set: HashSet<String>) -> Self {
+        let mut index = ScanIndex::default();
+        set.iter().for_each(|member| index.insert(member));
+        ScanSet { set, index }
+    }
+}
+
+impl FromIterator<String> for ScanSet {
+    fn from_iter<I: IntoIterator<Item = String>>(members: I) -> Self {
+        HashSet::from_iter(members).into()
+    }
+}
+
+impl IntoIterator for ScanSet {
+    type Item = String;
+    type IntoIter = hash_set::IntoIter<String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.set.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a ScanSet {
+    type Item = &'a String;
+    type IntoIter = hash_set::Iter<'a, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.set.iter()
+    }
+}
+
+impl Serialize for ScanSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.set.serialize(serializer)
+    }
+}
+
+fn key_hash(key: &str) -> u64 {
+    // `DefaultHasher::new` usa chaves fixas, então o hash é estável durante toda a execução.
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Compara `text` com um padrão glob no estilo do Redis: `*`, `?`, classes como `[abc]`,
+/// `[^a-z]` e `\` para escapar o próximo caractere.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text)
+}
+
+fn match_from(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Posições para retroceder quando um `*` precisa consumir mais caracteres.
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(p + 1),
+            Some('[') => match_class(pattern, p, text[t]),
+            Some('\\') if p + 1 < pattern.len() => (pattern[p + 1] == text[t]).then_some(p + 2),
+            Some(&c) => (c == text[t]).then_some(p + 1),
+            None => None,
+        };
+        match (step, star) {
+            (Some(next), _) => {
+                p = next;
+                t += 1;
+            }
+            (None, Some((star_p, star_t))) => {
+                p = star_p + 1;
+                t = star_t + 1;
+                star = Some((star_p, star_t + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Testa `c` contra a classe que começa em `pattern[start]` (`[`). Retorna a posição logo após
+/// o `]` se houver correspondência.
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<usize> {
+    let mut i = start + 1;
+    let negate = pattern.get(i) == Some(&'^');
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    while i < pattern.len() && pattern[i] != ']' {
+        if pattern[i] == '\\' && i + 1 < pattern.len() {
+            matched |= pattern[i + 1] == c;
+            i += 2;
+        } else if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            let (lo, hi) = if pattern[i] <= pattern[i + 2] {
+                (pattern[i], pattern[i + 2])
+            } else {
+                (pattern[i + 2], pattern[i])
+            };
+            matched |= (lo..=hi).contains(&c);
+            i += 3;
+        } else {
+            matched |= pattern[i] == c;
+            i += 1;
+        }
+    }
+    // Uma classe sem `]` vai até o fim do padrão, como no Redis.
+    (matched != negate).then_some((i + 1).min(pattern.len()))
+}
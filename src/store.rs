@@ -1,151 +1,4481 @@
-use crate::data_types::{Command, KeyMetadata, Value};
-use std::collections::HashMap;
-use std::sync::Arc;
+use crate::bitfield;
+use crate::blocking::{BlockedAction, BlockedClient, BlockedClients};
+use crate::dump;
+use crate::hyperloglog;
+use crate::scan::{glob_match, ScanMap, ScanSet};
+use crate::zset::{format_score, LexBound, ScoreBound, ZSet};
+use crate::data_types::{
+    to_unix_millis, Aggregate, BitOp, Command, ExpireCondition, KeyMetadata, ListEnd, Reply,
+    SetCondition, SetExpiry, SetOp, SortOptions, TtlUpdate, Value, ZAddOptions,
+};
+use rand::seq::{IteratorRandom, SliceRandom};
+use rand::Rng;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::ops::RangeInclusive;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 use std::time::{Duration, SystemTime};
-use tokio::sync::{broadcast, RwLock};
+use tokio::sync::{broadcast, mpsc, oneshot, RwLock};
+
+const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+/// A partir de quantos elementos o `UNLINK` libera o valor em background, como no Redis.
+const LAZYFREE_THRESHOLD: usize = 64;
+
+/// Comando de escrita, com o índice do banco a que se aplica, acompanhado do canal por onde a
+/// task de escrita devolve o resultado.
+type WriteRequest = (usize, Command, oneshot::Sender<Result<Reply, &'static str>>);
+
+/// Chaves com TTL, ou com campos de hash com TTL, ordenadas pelo prazo, da mais próxima de
+/// expirar para a mais distante. Entradas não são removidas quando o TTL muda ou a chave é
+/// apagada: ao saírem do índice, cada uma é conferida com os metadados atuais.
+type ExpiryIndex = BinaryHeap<Reverse<(SystemTime, String)>>;
+
+/// Um banco lógico, selecionado pelos clientes com `SELECT`.
+#[derive(Default)]
+pub struct Database {
+    pub data: RwLock<ScanMap<Value>>,
+    pub metadata: RwLock<HashMap<String, KeyMetadata>>,
+    /// Travado sempre depois de `data` e `metadata`, e só por instantes.
+    expiries: Mutex<ExpiryIndex>,
+    /// Clientes parados em comandos bloqueantes, à espera de chaves deste banco.
+    blocked: BlockedClients,
+    /// Chaves com um `DeleteExpired` já enviado à task de escrita e ainda não aplicado, para
+    /// que as leituras seguintes não enviem outro. Travado como o `expiries`.
+    pending_deletes: Mutex<HashSet<String>>,
+}
+
+impl Database {
+    /// Substitui todo o conteúdo do banco, reconstruindo o índice de expiração. Usado na carga
+    /// do snapshot.
+    pub async fn replace(
+        &self,
+        data: HashMap<String, Value>,
+        metadata: HashMap<String, KeyMetadata>,
+    ) {
+        let mut data_lock = self.data.write().await;
+        let mut meta_lock = self.metadata.write().await;
+        let mut expiries = self.expiries();
+
+        *expiries = metadata
+            .iter()
+            .filter_map(|(key, m)| m.next_deadline().map(|at| Reverse((at, key.clone()))))
+            .collect();
+        *data_lock = data.into();
+        *meta_lock = metadata;
+    }
+
+    fn expiries(&self) -> MutexGuard<'_, ExpiryIndex> {
+        // Nenhum trecho que segura o lock pode entrar em pânico no meio de uma alteração.
+        self.expiries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn pending_deletes(&self) -> MutexGuard<'_, HashSet<String>> {
+        self.pending_deletes.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
 
 #[derive(Clone)]
 pub struct Store {
-    pub data: Arc<RwLock<HashMap<String, Value>>>,
-    pub metadata: Arc<RwLock<HashMap<String, KeyMetadata>>>,
-    pub cmd_tx: broadcast::Sender<Command>,
+    /// Os bancos lógicos, indexados pelo número usado no `SELECT`. A quantidade é fixa.
+    pub databases: Arc<Vec<Database>>,
+    /// Comandos já aplicados, com o banco de cada um, publicados para os assinantes (AOF).
+    pub cmd_tx: broadcast::Sender<(usize, Command)>,
+    write_tx: mpsc::UnboundedSender<WriteRequest>,
+    /// Tamanho máximo, em bytes, que uma string pode atingir por comandos como `SETRANGE`. Também
+    /// limita quantos itens um sorteio com repetição pode responder.
+    max_string_len: usize,
 }
 
 impl Store {
     /// Cria uma nova instância da Store e a task de background para processar comandos.
-    pub fn new() -> (Self, impl std::future::Future<Output = ()>) {
+    pub fn new(
+        databases: usize,
+        max_string_len: usize,
+    ) -> (Self, impl std::future::Future<Output = ()>) {
         let (cmd_tx, _) = broadcast::channel(128);
+        let (write_tx, write_rx) = mpsc::unbounded_channel();
 
         let store = Self {
-            data: Arc::new(RwLock::new(HashMap::new())),
-            metadata: Arc::new(RwLock::new(HashMap::new())),
-            cmd_tx: cmd_tx.clone(),
+            databases: Arc::new((0..databases.max(1)).map(|_| Database::default()).collect()),
+            cmd_tx,
+            write_tx,
+            max_string_len,
         };
 
-        let cmd_rx = cmd_tx.subscribe();
-        let background_task = store.clone().process_commands(cmd_rx);
+        let background_task = store.clone().process_commands(write_rx);
 
         (store, background_task)
     }
 
-    /// Task que roda em background, ouvindo por comandos de escrita e aplicando-os.
-    /// Centraliza as escritas, evitando a necessidade de locks complexos nos handlers.
-    async fn process_commands(self, mut cmd_rx: broadcast::Receiver<Command>) {
-        while let Ok(cmd) = cmd_rx.recv().await {
-            match cmd {
-                Command::Set { key, value, expiry } => {
-                    let mut data_lock = self.data.write().await;
-                    data_lock.insert(key.clone(), value);
-                    drop(data_lock); // Libera o lock o mais rápido possível
-
-                    let mut meta_lock = self.metadata.write().await;
-                    if let Some(duration) = expiry {
-                        meta_lock.insert(
-                            key,
-                            KeyMetadata {
-                                expiry: Some(SystemTime::now() + duration),
-                            },
+    /// Task que roda em background, recebendo os comandos de escrita e aplicando-os.
+    /// Centraliza as escritas: como só ela altera os dados, operações de
+    /// leitura-modificação-escrita são atômicas em relação aos demais escritores.
+    async fn process_commands(self, mut write_rx: mpsc::UnboundedReceiver<WriteRequest>) {
+        while let Some((db, cmd, reply_tx)) = write_rx.recv().await {
+            let logged = cmd.clone();
+            let mut served = Vec::new();
+            let mut deadline = None;
+            let result = match cmd {
+                Command::Move { key, db: target } => self.move_key(db, key, target).await,
+                Command::SwapDb { first, second } => self.swap_databases(first, second).await,
+                cmd => {
+                    let database = &self.databases[db];
+                    let mut data_lock = database.data.write().await;
+                    let mut meta_lock = database.metadata.write().await;
+                    // Chaves e campos expirados saem antes da aplicação, para que nenhum
+                    // comando os veja.
+                    let now = SystemTime::now();
+                    let mut previous = Vec::new();
+                    for key in cmd.keys() {
+                        if is_expired(&meta_lock, key, now) {
+                            data_lock.remove(key);
+                            meta_lock.remove(key);
+                        }
+                        expire_fields(&mut data_lock, &mut meta_lock, key, now);
+                        previous.push(meta_lock.get(key).and_then(KeyMetadata::next_deadline));
+                    }
+                    // A partir daqui uma leitura que encontre algo expirado pede outra remoção.
+                    if let Command::DeleteExpired { key } = &cmd {
+                        database.pending_deletes().remove(key);
+                    }
+                    let result = Self::apply(&mut data_lock, &mut meta_lock, cmd);
+                    if result.is_ok() {
+                        served = Self::serve_blocked(
+                            database,
+                            &mut data_lock,
+                            &mut meta_lock,
+                            logged.keys(),
                         );
-                    } else {
-                        meta_lock.remove(&key);
                     }
-                }
-                Command::HSet { key, field, value } => {
-                    let mut data_lock = self.data.write().await;
-                    let entry = data_lock
-                        .entry(key.clone())
-                        .or_insert_with(|| Value::Hash(HashMap::new()));
 
-                    if let Value::Hash(hash) = entry {
-                        hash.insert(field, value);
+                    // Só os prazos que o comando alterou entram no índice.
+                    let mut expiries = database.expiries();
+                    for (key, previous) in logged.keys().into_iter().zip(previous) {
+                        prune_field_expiries(&data_lock, &mut meta_lock, key);
+                        let current = meta_lock.get(key).and_then(KeyMetadata::next_deadline);
+                        if let Some(at) = current.filter(|_| current != previous) {
+                            expiries.push(Reverse((at, key.to_string())));
+                        }
                     }
-                    // Se o tipo não for Hash, a operação falha silenciosamente aqui,
-                    // mas o `hset` no `process_command` já deve ter verificado o tipo.
+                    let key = logged.keys().first().copied();
+                    deadline = key.and_then(|key| meta_lock.get(key)).and_then(|m| m.expiry);
+                    result
                 }
-                Command::Delete { key } => {
-                    let mut data_lock = self.data.write().await;
-                    data_lock.remove(&key);
-                    drop(data_lock);
+            };
+            let propagated =
+                result.as_ref().ok().and_then(|r| Self::propagated(logged, r, deadline));
+            if let Some(logged) = propagated {
+                // Só esta task publica, então o AOF vê a mesma ordem de aplicação.
+                let _ = self.cmd_tx.send((db, logged));
+            }
+            // Os comandos dos clientes bloqueados foram aplicados depois do que os liberou.
+            for served in served {
+                let _ = self.cmd_tx.send((db, served));
+            }
 
-                    let mut meta_lock = self.metadata.write().await;
-                    meta_lock.remove(&key);
+            // O erro é ignorado pois só ocorre se quem enviou o comando desistiu da resposta.
+            let _ = reply_tx.send(result);
+        }
+    }
+
+    /// Atende, em ordem de chegada, os clientes bloqueados em `keys` enquanto elas guardarem
+    /// valores do tipo que eles consomem. Retorna os comandos aplicados em nome deles, já na
+    /// forma do AOF.
+    fn serve_blocked(
+        database: &Database,
+        data: &mut ScanMap<Value>,
+        meta: &mut HashMap<String, KeyMetadata>,
+        keys: Vec<&str>,
+    ) -> Vec<Command> {
+        let mut served = Vec::new();
+        let mut ready: Vec<String> = keys.into_iter().map(str::to_string).collect();
+        while let Some(key) = ready.pop() {
+            // Chaves vazias não ficam guardadas: se a chave existe, há o que retirar.
+            loop {
+                let next = data.get(&key).and_then(|value| {
+                    database.blocked.next(&key, |action| action.accepts(value))
+                });
+                let Some(client) = next else {
+                    break;
+                };
+                // Sem o canal, o cliente já foi atendido por outra chave ou desistiu.
+                let Some(reply_tx) = client.take_reply_tx() else {
+                    continue;
+                };
+                // Um `BLMOVE` com o destino do tipo errado falha sem tirar nada da origem, e o
+                // elemento fica para o próximo da fila.
+                let cmd = client.action.command(key.clone());
+                let result = Self::apply(data, meta, cmd.clone());
+                // Retiradas não definem prazos, então `propagated` não precisa do da chave.
+                let propagated = result.as_ref().ok().and_then(|r| Self::propagated(cmd, r, None));
+                if let Some(logged) = propagated {
+                    // O destino de um `BLMOVE` pode ser aguardado por outros clientes.
+                    if let Command::LMove { destination, .. } = &logged {
+                        ready.push(destination.clone());
+                    }
+                    served.push(logged);
                 }
+                let _ = reply_tx.send((key.clone(), result));
             }
         }
+        served
     }
 
-    /// Envia um comando `GET`. Operação de leitura, acessa diretamente o `RwLock`.
-    pub async fn get(&self, key: &str) -> Option<Value> {
-        self.data.read().await.get(key).cloned()
+    /// Forma do comando aplicado que é publicada para o AOF, ou `None` se ele não alterou nada.
+    /// Permite registrar comandos condicionais como escritas simples, sem depender do estado
+    /// anterior na hora de reproduzi-los. `deadline` é o prazo que a primeira chave do comando
+    /// ficou tendo depois da aplicação.
+    fn propagated(cmd: Command, reply: &Reply, deadline: Option<SystemTime>) -> Option<Command> {
+        match cmd {
+            Command::Set { .. }
+                if matches!(reply, Reply::Nil | Reply::Previous { written: false, .. }) =>
+            {
+                None
+            }
+            Command::MSetNx { .. }
+            | Command::HDel { .. }
+            | Command::HSetNx { .. }
+            | Command::LRem { .. }
+            | Command::SAdd { .. }
+            | Command::SRem { .. }
+            | Command::SMove { .. }
+            | Command::ZRem { .. }
+            | Command::PfAdd { .. }
+                if *reply == Reply::Integer(0) =>
+            {
+                None
+            }
+            Command::Push { if_exists: true, .. } if *reply == Reply::Integer(0) => None,
+            // Como no `SET NX`, a condição já foi verificada e a reprodução pode ser um `HSET`.
+            Command::HSetNx { key, field, value } => Some(Command::HSet {
+                key,
+                pairs: vec![(field, value)],
+            }),
+            // O sorteio não se repete na reprodução: ficam registrados os membros que saíram.
+            Command::SPop { key, .. } => match reply {
+                Reply::Elements(members) if !members.is_empty() => Some(Command::SRem {
+                    key,
+                    members: members.clone(),
+                }),
+                _ => None,
+            },
+            Command::ZAdd { options: ZAddOptions { incr: true, .. }, .. } if *reply == Reply::Nil => {
+                None
+            }
+            // Como o `ZINCRBY`, um `INCR` aplicado vira a definição do score resultante.
+            Command::ZAdd { key, mut pairs, options: ZAddOptions { incr: true, .. } } => {
+                let Reply::Bulk(score) = reply else {
+                    unreachable!("ZADD INCR responde com o novo score");
+                };
+                let (_, member) = pairs.remove(0);
+                let score = score.parse().expect("format_score volta ao mesmo score");
+                Some(Command::ZAdd {
+                    key,
+                    pairs: vec![(score, member)],
+                    options: ZAddOptions::default(),
+                })
+            }
+            // Como no `SPOP`, o que sai depende do estado atual: ficam registrados os membros.
+            Command::ZPop { key, .. } => match reply {
+                Reply::Scored(popped) if !popped.is_empty() => Some(Command::ZRem {
+                    key,
+                    members: popped.iter().map(|(member, _)| member.clone()).collect(),
+                }),
+                _ => None,
+            },
+            // Na reprodução, a lista escolhida já é conhecida.
+            Command::MPop { end, .. } => match reply {
+                Reply::Popped { key, elements } => Some(Command::Pop {
+                    key: key.clone(),
+                    count: elements.len(),
+                    end,
+                }),
+                _ => None,
+            },
+            Command::Push { key, elements, end, .. } => Some(Command::Push {
+                key,
+                elements,
+                end,
+                if_exists: false,
+            }),
+            Command::GetDel { .. } | Command::GetEx { .. } | Command::LMove { .. }
+                if *reply == Reply::Nil =>
+            {
+                None
+            }
+            // A reprodução precisa retirar exatamente o que foi retirado agora.
+            Command::Pop { key, end, .. } => match reply {
+                Reply::Elements(popped) if !popped.is_empty() => Some(Command::Pop {
+                    key,
+                    count: popped.len(),
+                    end,
+                }),
+                _ => None,
+            },
+            // Só os campos alterados vão para o AOF, e sem a condição, que já foi verificada.
+            Command::HExpire { key, fields, at, .. } => {
+                let fields = changed_fields(fields, reply, |status| status > 0)?;
+                Some(Command::HExpire { key, fields, at, condition: Default::default() })
+            }
+            Command::HPersist { key, fields } => {
+                let fields = changed_fields(fields, reply, |status| status == 1)?;
+                Some(Command::HPersist { key, fields })
+            }
+            Command::LInsert { .. } if matches!(reply, Reply::Integer(-1 | 0)) => None,
+            Command::Expire { .. } | Command::Persist { .. } if *reply == Reply::Integer(0) => {
+                None
+            }
+            // A condição do `RENAMENX` já foi verificada; a reprodução pode ser incondicional.
+            Command::Rename { .. } | Command::Move { .. } if *reply == Reply::Integer(0) => None,
+            // Todo prazo vai para o AOF como instante absoluto, então a reprodução expira a chave
+            // do mesmo jeito.
+            Command::DeleteExpired { .. } => None,
+            Command::Rename { key, new_key, .. } => Some(Command::Rename {
+                key,
+                new_key,
+                nx: false,
+            }),
+            // Se a chave existisse sem `REPLACE`, o `RESTORE` teria falhado e nem chegaria aqui.
+            Command::Restore { key, value, expiry, .. } => Some(Command::Restore {
+                key,
+                value,
+                expiry,
+                replace: true,
+            }),
+            // Um prazo relativo recomeçaria a contar na reprodução do AOF: vai o instante que o
+            // `apply` gravou, como um `PXAT`.
+            Command::Set { key, value, expiry, .. } => Some(Command::Set {
+                key,
+                value,
+                expiry: match expiry {
+                    SetExpiry::After(_) => deadline.map_or(SetExpiry::Clear, SetExpiry::At),
+                    expiry => expiry,
+                },
+                condition: SetCondition::Always,
+                get: false,
+            }),
+            cmd => Some(cmd),
+        }
+    }
+
+    /// Move uma chave, com o valor e os metadados, do banco `source` para o `target`. Os dois
+    /// bancos ficam travados durante a operação: a chave nunca some dos dois ao mesmo tempo.
+    async fn move_key(
+        &self,
+        source: usize,
+        key: String,
+        target: usize,
+    ) -> Result<Reply, &'static str> {
+        // Travar o mesmo banco duas vezes seria um deadlock.
+        if source == target {
+            return Err("ERR source and destination objects are the same");
+        }
+        let (source, target) = (&self.databases[source], &self.databases[target]);
+        let mut source_data = source.data.write().await;
+        let mut source_meta = source.metadata.write().await;
+        let mut target_data = target.data.write().await;
+        let mut target_meta = target.metadata.write().await;
+
+        let now = SystemTime::now();
+        if !source_data.contains_key(&key) || is_expired(&source_meta, &key, now) {
+            return Ok(Reply::Integer(0));
+        }
+        if target_data.contains_key(&key) && !is_expired(&target_meta, &key, now) {
+            return Ok(Reply::Integer(0));
+        }
+        if let Some(value) = source_data.remove(&key) {
+            target_data.insert(key.clone(), value);
+        }
+        let moved = source_meta.remove(&key).unwrap_or_default();
+        if let Some(at) = moved.next_deadline() {
+            let mut expiries = target.expiries();
+            expiries.push(Reverse((at, key.clone())));
+        }
+        target_meta.insert(key, moved);
+        Ok(Reply::Integer(1))
     }
 
-    /// Envia um comando `SET` para a task de processamento.
-    pub async fn set(&self, key: String, value: Value, expiry: Option<Duration>) {
-        let cmd = Command::Set { key, value, expiry };
-        // O erro é ignorado pois só ocorre se não houver receptores.
-        let _ = self.cmd_tx.send(cmd);
+    /// Troca o conteúdo de dois bancos sob os locks de escrita de ambos, então nenhum leitor
+    /// enxerga um banco trocado e o outro não. Os índices continuam os mesmos: quem estava com
+    /// um deles selecionado passa a ver os dados do outro.
+    async fn swap_databases(&self, first: usize, second: usize) -> Result<Reply, &'static str> {
+        if first == second {
+            return Ok(Reply::Ok);
+        }
+        let (first, second) = (&self.databases[first], &self.databases[second]);
+        let mut first_data = first.data.write().await;
+        let mut first_meta = first.metadata.write().await;
+        let mut second_data = second.data.write().await;
+        let mut second_meta = second.metadata.write().await;
+
+        let mut first_expiries = first.expiries();
+        let mut second_expiries = second.expiries();
+
+        std::mem::swap(&mut *first_data, &mut *second_data);
+        std::mem::swap(&mut *first_meta, &mut *second_meta);
+        std::mem::swap(&mut *first_expiries, &mut *second_expiries);
+        Ok(Reply::Ok)
     }
 
-    /// Envia um comando `HSET`. Verifica o tipo antes de enviar o comando.
-    pub async fn hset(&self, key: String, field: String, value: String) -> Result<i64, &'static str> {
-        let data_lock = self.data.read().await;
-        let mut created = true; 
+    /// Aplica um comando sobre os dados. Um comando que retorna erro não altera nada.
+    fn apply(
+        data: &mut ScanMap<Value>,
+        meta: &mut HashMap<String, KeyMetadata>,
+        cmd: Command,
+    ) -> Result<Reply, &'static str> {
+        match cmd {
+            Command::Set { key, value, expiry, condition, get } => {
+                let previous = data.get(&key);
+                if get && matches!(previous, Some(v) if !v.is_string()) {
+                    return Err(WRONGTYPE);
+                }
+                let blocked = match condition {
+                    SetCondition::Always => false,
+                    SetCondition::IfNotExists => previous.is_some(),
+                    SetCondition::IfExists => previous.is_none(),
+                };
+                if blocked {
+                    // Com `GET`, o valor anterior é retornado mesmo que o `SET` não aconteça.
+                    return Ok(match previous {
+                        Some(v) if get => Reply::Previous {
+                            value: v.as_bytes().map(Cow::into_owned),
+                            written: false,
+                        },
+                        _ if get => Reply::Previous { value: None, written: false },
+                        _ => Reply::Nil,
+                    });
+                }
+                let previous = data.insert(key.clone(), value);
+                match expiry {
+                    // Um prazo que já passou faz a chave expirar imediatamente.
+                    SetExpiry::At(at) if at <= SystemTime::now() => {
+                        data.remove(&key);
+                        meta.remove(&key);
+                    }
+                    SetExpiry::At(at) => written(meta, &key).expiry = Some(at),
+                    SetExpiry::Clear => written(meta, &key).expiry = None,
+                    SetExpiry::Keep => {
+                        written(meta, &key);
+                    }
+                    SetExpiry::After(duration) => {
+                        written(meta, &key).expiry = Some(SystemTime::now() + duration);
+                    }
+                }
+                if !get {
+                    return Ok(Reply::Ok);
+                }
+                Ok(Reply::Previous {
+                    value: previous.and_then(Value::into_bytes),
+                    written: true,
+                })
+            }
+            Command::HSet { key, pairs } => {
+                let entry =
+                    data.get_or_insert_with(key.clone(), || Value::Hash(ScanMap::default()));
+
+                match entry {
+                    Value::Hash(hash) => {
+                        let metadata = written(meta, &key);
+                        let mut created = 0;
+                        for (field, value) in pairs {
+                            // Sobrescrever um campo descarta o seu TTL, como o `SET` com a chave.
+                            metadata.field_expiries.remove(&field);
+                            created += hash.insert(field, value).is_none() as i64;
+                        }
+                        Ok(Reply::Integer(created))
+                    }
+                    _ => Err(WRONGTYPE),
+                }
+            }
+            Command::HIncrBy { key, field, delta } => {
+                let current = match hash_field(data, &key, &field)? {
+                    Some(v) => v
+                        .parse::<i64>()
+                        .map_err(|_| "ERR hash value is not an integer")?,
+                    None => 0,
+                };
+                let new_value = current
+                    .checked_add(delta)
+                    .ok_or("ERR increment or decrement would overflow")?;
+                hash_mut(data, &key).insert(field, new_value.to_string());
+                written(meta, &key);
+                Ok(Reply::Integer(new_value))
+            }
+            Command::HIncrByFloat { key, field, increment } => {
+                let current = match hash_field(data, &key, &field)? {
+                    Some(v) => v
+                        .parse::<f64>()
+                        .map_err(|_| "ERR hash value is not a float")?,
+                    None => 0.0,
+                };
+                let new_value = current + increment;
+                if !new_value.is_finite() {
+                    return Err("ERR increment would produce NaN or Infinity");
+                }
+                // Mesma formatação do `INCRBYFLOAT`.
+                let formatted = format_float(new_value);
+                hash_mut(data, &key).insert(field, formatted.clone());
+                written(meta, &key);
+                Ok(Reply::Bulk(formatted))
+            }
+            Command::HSetNx { key, field, value } => {
+                if hash_field(data, &key, &field)?.is_some() {
+                    return Ok(Reply::Integer(0));
+                }
+                hash_mut(data, &key).insert(field, value);
+                written(meta, &key);
+                Ok(Reply::Integer(1))
+            }
+            Command::HDel { key, fields } => {
+                let hash = match data.get_mut(&key) {
+                    Some(Value::Hash(hash)) => hash,
+                    Some(_) => return Err(WRONGTYPE),
+                    None => return Ok(Reply::Integer(0)),
+                };
+                let removed = fields.iter().filter(|f| hash.remove(*f).is_some()).count();
+                // Um hash sem campos deixa de existir, como no Redis.
+                if hash.is_empty() {
+                    data.remove(&key);
+                    meta.remove(&key);
+                } else if removed > 0 {
+                    written(meta, &key);
+                }
+                Ok(Reply::Integer(removed as i64))
+            }
+            Command::HExpire { key, fields, at, condition } => {
+                let hash = match data.get_mut(&key) {
+                    Some(Value::Hash(hash)) => hash,
+                    Some(_) => return Err(WRONGTYPE),
+                    None => return Ok(Reply::Integers(vec![-2; fields.len()])),
+                };
+                let now = SystemTime::now();
+                let metadata = written(meta, &key);
+                let statuses = fields
+                    .into_iter()
+                    .map(|field| {
+                        if !hash.contains_key(&field) {
+                            return -2;
+                        }
+                        if condition.blocks(metadata.field_expiries.get(&field).copied(), at) {
+                            return 0;
+                        }
+                        if at <= now {
+                            hash.remove(&field);
+                            metadata.field_expiries.remove(&field);
+                            return 2;
+                        }
+                        metadata.field_expiries.insert(field, at);
+                        1
+                    })
+                    .collect();
+                if hash.is_empty() {
+                    data.remove(&key);
+                    meta.remove(&key);
+                }
+                Ok(Reply::Integers(statuses))
+            }
+            Command::HPersist { key, fields } => {
+                let hash = match data.get(&key) {
+                    Some(Value::Hash(hash)) => hash,
+                    Some(_) => return Err(WRONGTYPE),
+                    None => return Ok(Reply::Integers(vec![-2; fields.len()])),
+                };
+                let metadata = written(meta, &key);
+                let statuses = fields
+                    .iter()
+                    .map(|field| match metadata.field_expiries.remove(field) {
+                        Some(_) => 1,
+                        None if hash.contains_key(field) => -1,
+                        None => -2,
+                    })
+                    .collect();
+                Ok(Reply::Integers(statuses))
+            }
+            Command::Push { key, elements, end, if_exists } => {
+                if if_exists && !data.contains_key(&key) {
+                    return Ok(Reply::Integer(0));
+                }
+                let entry = data.get_or_insert_with(key.clone(), || Value::List(VecDeque::new()));
+                let Value::List(list) = entry else {
+                    return Err(WRONGTYPE);
+                };
+                for element in elements {
+                    match end {
+                        ListEnd::Left => list.push_front(element),
+                        ListEnd::Right => list.push_back(element),
+                    }
+                }
+                written(meta, &key);
+                Ok(Reply::Integer(list.len() as i64))
+            }
+            Command::Pop { key, count, end } => {
+                let list = match data.get_mut(&key) {
+                    Some(Value::List(list)) => list,
+                    Some(_) => return Err(WRONGTYPE),
+                    None => return Ok(Reply::Nil),
+                };
+                let count = count.min(list.len());
+                let popped: Vec<String> = match end {
+                    ListEnd::Left => list.drain(..count).collect(),
+                    ListEnd::Right => list.drain(list.len() - count..).rev().collect(),
+                };
+                // Uma lista sem elementos deixa de existir.
+                if list.is_empty() {
+                    data.remove(&key);
+                    meta.remove(&key);
+                } else if count > 0 {
+                    written(meta, &key);
+                }
+                Ok(Reply::Elements(popped))
+            }
+            Command::MPop { keys, end, count } => {
+                // A primeira chave com uma lista é a escolhida; as ausentes são puladas.
+                let mut chosen = None;
+                for key in keys {
+                    match data.get(&key) {
+                        Some(Value::List(_)) => {
+                            chosen = Some(key);
+                            break;
+                        }
+                        Some(_) => return Err(WRONGTYPE),
+                        None => {}
+                    }
+                }
+                let Some(key) = chosen else {
+                    return Ok(Reply::Nil);
+                };
+                match Self::apply(data, meta, Command::Pop { key: key.clone(), count, end })? {
+                    Reply::Elements(elements) => Ok(Reply::Popped { key, elements }),
+                    _ => unreachable!("a chave guarda uma lista"),
+                }
+            }
+            Command::LSet { key, index, element } => {
+                let list = match data.get_mut(&key) {
+                    Some(Value::List(list)) => list,
+                    Some(_) => return Err(WRONGTYPE),
+                    None => return Err("ERR no such key"),
+                };
+                let index = list_index(list.len(), index).ok_or("ERR index out of range")?;
+                list[index] = element;
+                written(meta, &key);
+                Ok(Reply::Ok)
+            }
+            Command::LInsert { key, before, pivot, element } => {
+                let list = match data.get_mut(&key) {
+                    Some(Value::List(list)) => list,
+                    Some(_) => return Err(WRONGTYPE),
+                    None => return Ok(Reply::Integer(0)),
+                };
+                let Some(position) = list.iter().position(|e| *e == pivot) else {
+                    return Ok(Reply::Integer(-1));
+                };
+                list.insert(if before { position } else { position + 1 }, element);
+                written(meta, &key);
+                Ok(Reply::Integer(list.len() as i64))
+            }
+            Command::LRem { key, count, element } => {
+                let list = match data.get_mut(&key) {
+                    Some(Value::List(list)) => list,
+                    Some(_) => return Err(WRONGTYPE),
+                    None => return Ok(Reply::Integer(0)),
+                };
+                let limit = match count {
+                    0 => usize::MAX,
+                    count => usize::try_from(count.unsigned_abs()).unwrap_or(usize::MAX),
+                };
+                // As ocorrências removidas são as `limit` primeiras a partir da ponta escolhida,
+                // ou seja, as que ficam de um lado de uma posição de corte. Assim um único
+                // `retain` serve para os dois sentidos.
+                let matches = || list.iter().enumerate().filter(|(_, e)| **e == element);
+                let bounds = if count >= 0 {
+                    matches().take(limit).last().map(|(last, _)| 0..=last)
+                } else {
+                    matches().rev().take(limit).last().map(|(first, _)| first..=usize::MAX)
+                };
+                let Some(bounds) = bounds else {
+                    return Ok(Reply::Integer(0));
+                };
+                let before = list.len();
+                let mut index = 0;
+                list.retain(|e| {
+                    let removed = bounds.contains(&index) && *e == element;
+                    index += 1;
+                    !removed
+                });
+                let removed = before - list.len();
+                if list.is_empty() {
+                    data.remove(&key);
+                    meta.remove(&key);
+                } else {
+                    written(meta, &key);
+                }
+                Ok(Reply::Integer(removed as i64))
+            }
+            Command::LTrim { key, start, stop } => {
+                let list = match data.get_mut(&key) {
+                    Some(Value::List(list)) => list,
+                    Some(_) => return Err(WRONGTYPE),
+                    None => return Ok(Reply::Ok),
+                };
+                match list_range(list.len(), start, stop) {
+                    Some(range) => {
+                        list.truncate(range.end() + 1);
+                        list.drain(..range.start());
+                        written(meta, &key);
+                    }
+                    // Um trecho vazio esvazia a lista, e a chave deixa de existir.
+                    None => {
+                        data.remove(&key);
+                        meta.remove(&key);
+                    }
+                }
+                Ok(Reply::Ok)
+            }
+            Command::LMove { source, destination, from, to } => {
+                // O destino é verificado antes de qualquer alteração: com o tipo errado, o
+                // elemento continua na origem.
+                if matches!(data.get(&destination), Some(v) if !matches!(v, Value::List(_))) {
+                    return Err(WRONGTYPE);
+                }
+                let list = match data.get_mut(&source) {
+                    Some(Value::List(list)) => list,
+                    Some(_) => return Err(WRONGTYPE),
+                    None => return Ok(Reply::Nil),
+                };
+                let element = match from {
+                    ListEnd::Left => list.pop_front(),
+                    ListEnd::Right => list.pop_back(),
+                }
+                .expect("listas vazias não ficam guardadas");
+                // Na rotação de uma lista de um elemento, a chave (e o seu TTL) continua existindo.
+                if list.is_empty() && source != destination {
+                    data.remove(&source);
+                    meta.remove(&source);
+                } else {
+                    written(meta, &source);
+                }
+
+                let target = data
+                    .get_or_insert_with(destination.clone(), || Value::List(VecDeque::new()));
+                let Value::List(target) = target else {
+                    unreachable!("tipo do destino verificado acima");
+                };
+                match to {
+                    ListEnd::Left => target.push_front(element.clone()),
+                    ListEnd::Right => target.push_back(element.clone()),
+                }
+                written(meta, &destination);
+                Ok(Reply::Bulk(element))
+            }
+            Command::SAdd { key, members } => {
+                let entry = data.get_or_insert_with(key.clone(), || Value::Set(ScanSet::default()));
+                let Value::Set(set) = entry else {
+                    return Err(WRONGTYPE);
+                };
+                let added = members.into_iter().filter(|m| set.insert(m.clone())).count();
+                written(meta, &key);
+                Ok(Reply::Integer(added as i64))
+            }
+            Command::SRem { key, members } => {
+                let set = match data.get_mut(&key) {
+                    Some(Value::Set(set)) => set,
+                    Some(_) => return Err(WRONGTYPE),
+                    None => return Ok(Reply::Integer(0)),
+                };
+                let removed = members.iter().filter(|m| set.remove(*m)).count();
+                // Um conjunto sem membros deixa de existir, como o hash sem campos.
+                if set.is_empty() {
+                    data.remove(&key);
+                    meta.remove(&key);
+                } else if removed > 0 {
+                    written(meta, &key);
+                }
+                Ok(Reply::Integer(removed as i64))
+            }
+            Command::SPop { key, count } => {
+                let set = match data.get_mut(&key) {
+                    Some(Value::Set(set)) => set,
+                    Some(_) => return Err(WRONGTYPE),
+                    None => return Ok(Reply::Elements(Vec::new())),
+                };
+                let mut rng = rand::thread_rng();
+                // `choose_multiple` reserva espaço para `count` itens de antemão.
+                let mut popped: Vec<String> = set
+                    .iter()
+                    .choose_multiple(&mut rng, count.min(set.len()))
+                    .into_iter()
+                    .cloned()
+                    .collect();
+                // `choose_multiple` não garante uma ordem aleatória entre os escolhidos.
+                popped.shuffle(&mut rng);
+                for member in &popped {
+                    set.remove(member);
+                }
+                if set.is_empty() {
+                    data.remove(&key);
+                    meta.remove(&key);
+                } else if !popped.is_empty() {
+                    written(meta, &key);
+                }
+                Ok(Reply::Elements(popped))
+            }
+            Command::ZAdd { key, pairs, options } => {
+                let zset = match data.get(&key) {
+                    Some(Value::ZSet(zset)) => Some(zset),
+                    Some(_) => return Err(WRONGTYPE),
+                    None => None,
+                };
+                // Os novos scores são decididos antes de qualquer escrita, para que um NaN do
+                // `INCR` não deixe o comando aplicado pela metade.
+                let mut updates = Vec::with_capacity(pairs.len());
+                for (score, member) in pairs {
+                    let current = zset.and_then(|z| z.score(&member));
+                    let score = match current {
+                        Some(current) if options.incr => current + score,
+                        _ => score,
+                    };
+                    if score.is_nan() {
+                        return Err("ERR resulting score is not a number (NaN)");
+                    }
+                    let blocked = match current {
+                        Some(current) => {
+                            options.nx
+                                || (options.gt && score <= current)
+                                || (options.lt && score >= current)
+                        }
+                        None => options.xx,
+                    };
+                    if !blocked {
+                        updates.push((member, score, current));
+                    }
+                }
+                // Com `INCR`, a resposta é o novo score, ou Nil se uma condição impediu.
+                let incremented = updates.first().map(|(_, score, _)| format_score(*score));
+                let (mut added, mut changed) = (0, 0);
+                if !updates.is_empty() {
+                    let Value::ZSet(zset) =
+                        data.get_or_insert_with(key.clone(), || Value::ZSet(ZSet::default()))
+                    else {
+                        unreachable!("tipo verificado acima");
+                    };
+                    for (member, score, current) in updates {
+                        match current {
+                            None => added += 1,
+                            Some(current) if current != score => changed += 1,
+                            Some(_) => {}
+                        }
+                        zset.insert(member, score);
+                    }
+                    written(meta, &key);
+                }
+                match (options.incr, incremented) {
+                    (true, Some(score)) => Ok(Reply::Bulk(score)),
+                    (true, None) => Ok(Reply::Nil),
+                    (false, _) if options.ch => Ok(Reply::Integer(added + changed)),
+                    (false, _) => Ok(Reply::Integer(added)),
+                }
+            }
+            Command::ZIncrBy { key, member, increment } => {
+                let zset = match data.get(&key) {
+                    Some(Value::ZSet(zset)) => Some(zset),
+                    Some(_) => return Err(WRONGTYPE),
+                    None => None,
+                };
+                let current = zset.and_then(|z| z.score(&member)).unwrap_or(0.0);
+                let score = current + increment;
+                if score.is_nan() {
+                    return Err("ERR resulting score is not a number (NaN)");
+                }
+                let Value::ZSet(zset) =
+                    data.get_or_insert_with(key.clone(), || Value::ZSet(ZSet::default()))
+                else {
+                    unreachable!("tipo verificado acima");
+                };
+                zset.insert(member, score);
+                written(meta, &key);
+                Ok(Reply::Bulk(format_score(score)))
+            }
+            Command::ZPop { key, count, max } => {
+                let zset = match data.get_mut(&key) {
+                    Some(Value::ZSet(zset)) => zset,
+                    Some(_) => return Err(WRONGTYPE),
+                    None => return Ok(Reply::Scored(Vec::new())),
+                };
+                let popped: Vec<_> = (0..count).map_while(|_| zset.pop(max)).collect();
+                if zset.is_empty() {
+                    data.remove(&key);
+                    meta.remove(&key);
+                } else if !popped.is_empty() {
+                    written(meta, &key);
+                }
+                Ok(Reply::Scored(popped))
+            }
+            Command::ZRem { key, members } => {
+                let zset = match data.get_mut(&key) {
+                    Some(Value::ZSet(zset)) => zset,
+                    Some(_) => return Err(WRONGTYPE),
+                    None => return Ok(Reply::Integer(0)),
+                };
+                let removed = members.iter().filter(|m| zset.remove(m).is_some()).count();
+                if zset.is_empty() {
+                    data.remove(&key);
+                    meta.remove(&key);
+                } else if removed > 0 {
+                    written(meta, &key);
+                }
+                Ok(Reply::Integer(removed as i64))
+            }
+            Command::SMove { source, destination, member } => {
+                // Como no `LMOVE`, o destino é verificado antes de qualquer alteração.
+                if matches!(data.get(&destination), Some(v) if !matches!(v, Value::Set(_))) {
+                    return Err(WRONGTYPE);
+                }
+                let set = match data.get_mut(&source) {
+                    Some(Value::Set(set)) => set,
+                    Some(_) => return Err(WRONGTYPE),
+                    None => return Ok(Reply::Integer(0)),
+                };
+                if source == destination {
+                    return Ok(Reply::Integer(set.contains(&member) as i64));
+                }
+                if !set.remove(&member) {
+                    return Ok(Reply::Integer(0));
+                }
+                if set.is_empty() {
+                    data.remove(&source);
+                    meta.remove(&source);
+                } else {
+                    written(meta, &source);
+                }
+
+                let target = data
+                    .get_or_insert_with(destination.clone(), || Value::Set(ScanSet::default()));
+                let Value::Set(target) = target else {
+                    unreachable!("tipo do destino verificado acima");
+                };
+                target.insert(member);
+                written(meta, &destination);
+                Ok(Reply::Integer(1))
+            }
+            Command::Delete { key } => {
+                meta.remove(&key);
+                Ok(Reply::Integer(data.remove(&key).is_some() as i64))
+            }
+            Command::Unlink { key } => {
+                meta.remove(&key);
+                let removed = data.remove(&key);
+                let deleted = removed.is_some();
+                if let Some(value) = removed.filter(|v| free_effort(v) > LAZYFREE_THRESHOLD) {
+                    // Só o valor sai do mapa sob o lock; a desalocação acontece em outra thread.
+                    tokio::task::spawn_blocking(move || drop(value));
+                }
+                Ok(Reply::Integer(deleted as i64))
+            }
+            Command::IncrBy { key, delta } => {
+                let current = match data.get(&key) {
+                    Some(Value::Int(i)) => *i,
+                    Some(v) => v
+                        .as_str()
+                        .ok_or(WRONGTYPE)?
+                        .parse::<i64>()
+                        .map_err(|_| "ERR value is not an integer or out of range")?,
+                    None => 0,
+                };
+                let new_value = current
+                    .checked_add(delta)
+                    .ok_or("ERR increment or decrement would overflow")?;
+                // O TTL da chave é mantido, como no Redis.
+                written(meta, &key);
+                data.insert(key, Value::Int(new_value));
+                Ok(Reply::Integer(new_value))
+            }
+            Command::IncrByFloat { key, increment } => {
+                let current = match data.get(&key) {
+                    Some(v) => v
+                        .as_str()
+                        .ok_or(WRONGTYPE)?
+                        .parse::<f64>()
+                        .map_err(|_| "ERR value is not a valid float")?,
+                    None => 0.0,
+                };
+                let new_value = current + increment;
+                if !new_value.is_finite() {
+                    return Err("ERR increment would produce NaN or Infinity");
+                }
+                let formatted = format_float(new_value);
+                written(meta, &key);
+                data.insert(key, Value::String(formatted.clone()));
+                Ok(Reply::Bulk(formatted))
+            }
+            Command::Append { key, value } => {
+                let entry = data.get_or_insert_with(key.clone(), || Value::String(String::new()));
+                // Concatenar deixa de ser um inteiro: volta para a representação em texto.
+                if let Value::Int(i) = entry {
+                    *entry = Value::String(i.to_string());
+                }
+
+                match entry {
+                    Value::String(s) => {
+                        s.push_str(&value);
+                        written(meta, &key);
+                        Ok(Reply::Integer(s.len() as i64))
+                    }
+                    Value::Bytes(bytes) => {
+                        bytes.extend_from_slice(value.as_bytes());
+                        written(meta, &key);
+                        Ok(Reply::Integer(bytes.len() as i64))
+                    }
+                    _ => Err(WRONGTYPE),
+                }
+            }
+            Command::SetRange { key, offset, value } => {
+                let current = match data.get(&key) {
+                    Some(v) => v.as_bytes().ok_or(WRONGTYPE)?,
+                    // Sobrescrever com uma string vazia não cria a chave.
+                    None if value.is_empty() => return Ok(Reply::Integer(0)),
+                    None => Cow::Borrowed(&[][..]),
+                };
+                if value.is_empty() {
+                    return Ok(Reply::Integer(current.len() as i64));
+                }
+
+                let mut bytes = current.into_owned();
+                let end = offset + value.len();
+                if bytes.len() < end {
+                    bytes.resize(end, 0);
+                }
+                bytes[offset..end].copy_from_slice(value.as_bytes());
 
-        if let Some(v) = data_lock.get(&key) {
-            match v {
-                Value::Hash(hash) => {
-                    if hash.contains_key(&field) {
-                        created = false;
+                // Cortar um caractere no meio deixa de ser UTF-8: aí o valor fica em `Bytes`.
+                let len = bytes.len() as i64;
+                written(meta, &key);
+                data.insert(key, Value::from_bytes(bytes));
+                Ok(Reply::Integer(len))
+            }
+            Command::BitField { key, ops } => {
+                if matches!(data.get(&key), Some(v) if !v.is_string()) {
+                    return Err(WRONGTYPE);
+                }
+                let mut bytes = match data.remove(&key) {
+                    Some(v) => v.into_bytes().expect("o tipo foi verificado acima"),
+                    None => Vec::new(),
+                };
+                // Como no Redis, a string cresce até o maior campo escrito antes das operações,
+                // mesmo que o `OVERFLOW FAIL` impeça alguma delas.
+                let needed = ops
+                    .iter()
+                    .filter(|op| op.is_write())
+                    .map(|op| op.field().len_needed())
+                    .max()
+                    .unwrap_or(0);
+                if bytes.len() < needed {
+                    bytes.resize(needed, 0);
+                }
+                let results = ops.iter().map(|op| op.apply(&mut bytes)).collect();
+                written(meta, &key);
+                data.insert(key, Value::Bytes(bytes));
+                Ok(Reply::Fields(results))
+            }
+            Command::SetBit { key, offset, bit } => {
+                if matches!(data.get(&key), Some(v) if !v.is_string()) {
+                    return Err(WRONGTYPE);
+                }
+                // O bitmap fica em `Bytes`; vindo de `String`, os bytes são reaproveitados sem
+                // cópia.
+                let mut bytes = match data.remove(&key) {
+                    Some(v) => v.into_bytes().expect("o tipo foi verificado acima"),
+                    None => Vec::new(),
+                };
+                let index = (offset / 8) as usize;
+                if bytes.len() <= index {
+                    bytes.resize(index + 1, 0);
+                }
+                let mask = 0x80 >> (offset % 8);
+                let previous = bytes[index] & mask != 0;
+                if bit {
+                    bytes[index] |= mask;
+                } else {
+                    bytes[index] &= !mask;
+                }
+                // O TTL da chave é mantido, como no `SETRANGE`.
+                written(meta, &key);
+                data.insert(key, Value::Bytes(bytes));
+                Ok(Reply::Integer(previous as i64))
+            }
+            Command::PfAdd { key, elements } => {
+                let created = match data.get(&key) {
+                    Some(v) => {
+                        let bytes = v.as_bytes().ok_or(WRONGTYPE)?;
+                        if !hyperloglog::is_valid(&bytes) {
+                            return Err(hyperloglog::INVALID);
+                        }
+                        false
+                    }
+                    None => true,
+                };
+                let mut hll = match data.remove(&key) {
+                    Some(v) => v.into_bytes().expect("o tipo foi verificado acima"),
+                    None => hyperloglog::new(),
+                };
+                // Sem curto-circuito: todos os elementos precisam ser registrados.
+                let changed = elements.iter().fold(created, |changed, element| {
+                    hyperloglog::add(&mut hll, element.as_bytes()) | changed
+                });
+                if changed {
+                    written(meta, &key);
+                }
+                data.insert(key, Value::Bytes(hll));
+                Ok(Reply::Integer(changed as i64))
+            }
+            Command::GetSet { key, value } => {
+                if matches!(data.get(&key), Some(v) if !v.is_string()) {
+                    return Err(WRONGTYPE);
+                }
+                // Como o `SET`, substitui o valor e descarta o TTL anterior.
+                written(meta, &key).expiry = None;
+                match data.insert(key, Value::String(value)).and_then(Value::into_bytes) {
+                    Some(old) => Ok(Reply::Value(old)),
+                    None => Ok(Reply::Nil),
+                }
+            }
+            Command::MSet { pairs } => {
+                // Todos os pares são aplicados sob o mesmo lock de escrita, então nenhum
+                // leitor enxerga o lote pela metade.
+                for (key, value) in pairs {
+                    written(meta, &key).expiry = None;
+                    data.insert(key, Value::String(value));
+                }
+                Ok(Reply::Ok)
+            }
+            Command::MSetNx { pairs } => {
+                if pairs.iter().any(|(key, _)| data.contains_key(key)) {
+                    return Ok(Reply::Integer(0));
+                }
+                // Uma chave repetida na lista conta como nova nas duas vezes; vale o último valor.
+                for (key, value) in pairs {
+                    written(meta, &key).expiry = None;
+                    data.insert(key, Value::String(value));
+                }
+                Ok(Reply::Integer(1))
+            }
+            Command::GetDel { key } => {
+                if matches!(data.get(&key), Some(v) if !v.is_string()) {
+                    return Err(WRONGTYPE);
+                }
+                meta.remove(&key);
+                match data.remove(&key).and_then(Value::into_bytes) {
+                    Some(old) => Ok(Reply::Value(old)),
+                    None => Ok(Reply::Nil),
+                }
+            }
+            Command::GetEx { key, ttl } => {
+                let value = match data.get(&key) {
+                    Some(v) => v.as_bytes().ok_or(WRONGTYPE)?.into_owned(),
+                    None => return Ok(Reply::Nil),
+                };
+                match ttl {
+                    TtlUpdate::ExpireAt(at) => set_expiry(data, meta, key, at),
+                    TtlUpdate::Persist => written(meta, &key).expiry = None,
+                }
+                Ok(Reply::Value(value))
+            }
+            Command::Expire { key, at, condition } => {
+                if !data.contains_key(&key) || is_expired(meta, &key, SystemTime::now()) {
+                    return Ok(Reply::Integer(0));
+                }
+                let current = meta.get(&key).and_then(|m| m.expiry);
+                if condition.blocks(current, at) {
+                    return Ok(Reply::Integer(0));
+                }
+                set_expiry(data, meta, key, at);
+                Ok(Reply::Integer(1))
+            }
+            Command::Rename { key, new_key, nx } => {
+                let now = SystemTime::now();
+                if !data.contains_key(&key) || is_expired(meta, &key, now) {
+                    return Err("ERR no such key");
+                }
+                if nx && data.contains_key(&new_key) && !is_expired(meta, &new_key, now) {
+                    return Ok(Reply::Integer(0));
+                }
+                if key != new_key {
+                    // Valor e metadados trocam de chave na mesma operação, sob o lock de escrita.
+                    if let Some(value) = data.remove(&key) {
+                        data.insert(new_key.clone(), value);
                     }
+                    let moved = meta.remove(&key).unwrap_or_default();
+                    meta.insert(new_key, moved);
+                }
+                Ok(if nx { Reply::Integer(1) } else { Reply::Ok })
+            }
+            Command::SortStore { key, destination, options } => {
+                let source = data.get(&key).filter(|_| !is_expired(meta, &key, SystemTime::now()));
+                let sorted = sort_elements(source, options)?;
+                let len = sorted.len() as i64;
+                // Como no Redis, um resultado vazio remove o destino em vez de criar uma lista vazia.
+                if sorted.is_empty() {
+                    data.remove(&destination);
+                    meta.remove(&destination);
+                } else {
+                    written(meta, &destination).expiry = None;
+                    data.insert(destination, Value::List(sorted.into()));
+                }
+                Ok(Reply::Integer(len))
+            }
+            Command::SetStore { op, destination, keys } => {
+                let sets = keys
+                    .iter()
+                    .map(|key| match data.get(key) {
+                        Some(Value::Set(set)) => Ok(Some(set)),
+                        Some(_) => Err(WRONGTYPE),
+                        None => Ok(None),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                // O resultado é copiado antes da escrita, já que o destino pode ser uma das
+                // origens.
+                let result: ScanSet =
+                    combine_sets(op, &sets).into_iter().cloned().collect();
+                let len = result.len() as i64;
+                // Como no `SORT ... STORE`, um resultado vazio remove o destino.
+                meta.remove(&destination);
+                if result.is_empty() {
+                    data.remove(&destination);
+                } else {
+                    written(meta, &destination);
+                    data.insert(destination, Value::Set(result));
+                }
+                Ok(Reply::Integer(len))
+            }
+            Command::ZSetStore { op, destination, keys, weights, aggregate } => {
+                let inputs = keys
+                    .iter()
+                    .zip(weights)
+                    .map(|(key, weight)| match data.get(key) {
+                        Some(Value::ZSet(zset)) => Ok((Some(Scored::ZSet(zset)), weight)),
+                        Some(Value::Set(set)) => Ok((Some(Scored::Set(set)), weight)),
+                        Some(_) => Err(WRONGTYPE),
+                        None => Ok((None, weight)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                // Como no `SINTERSTORE`, o resultado é montado antes da escrita, já que o destino
+                // pode ser uma das origens.
+                let result = combine_zsets(op, inputs, aggregate);
+                let len = result.len() as i64;
+                meta.remove(&destination);
+                if result.is_empty() {
+                    data.remove(&destination);
+                } else {
+                    written(meta, &destination);
+                    data.insert(destination, Value::ZSet(result));
+                }
+                Ok(Reply::Integer(len))
+            }
+            Command::BitOp { op, destination, keys } => {
+                let sources = keys
+                    .iter()
+                    .map(|key| match data.get(key) {
+                        Some(v) => v.as_bytes().ok_or(WRONGTYPE),
+                        None => Ok(Cow::Borrowed(&[][..])),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                // As strings menores são completadas com zeros até o tamanho da maior.
+                let len = sources.iter().map(|source| source.len()).max().unwrap_or(0);
+                let byte = |source: &[u8], index: usize| source.get(index).copied().unwrap_or(0);
+                let result: Vec<u8> = (0..len)
+                    .map(|index| {
+                        let mut bytes = sources.iter().map(|source| byte(source, index));
+                        let first = bytes.next().unwrap_or(0);
+                        match op {
+                            BitOp::And => bytes.fold(first, |acc, b| acc & b),
+                            BitOp::Or => bytes.fold(first, |acc, b| acc | b),
+                            BitOp::Xor => bytes.fold(first, |acc, b| acc ^ b),
+                            BitOp::Not => !first,
+                        }
+                    })
+                    .collect();
+                // Como no `SINTERSTORE`, um resultado vazio remove o destino.
+                meta.remove(&destination);
+                if result.is_empty() {
+                    data.remove(&destination);
+                } else {
+                    written(meta, &destination);
+                    data.insert(destination, Value::Bytes(result));
+                }
+                Ok(Reply::Integer(len as i64))
+            }
+            Command::PfMerge { destination, keys } => {
+                // O próprio destino entra na união, como no Redis.
+                let sources = std::iter::once(&destination)
+                    .chain(&keys)
+                    .filter_map(|key| data.get(key))
+                    .map(|v| match v.as_bytes() {
+                        Some(bytes) if hyperloglog::is_valid(&bytes) => Ok(bytes),
+                        Some(_) => Err(hyperloglog::INVALID),
+                        None => Err(WRONGTYPE),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let registers = hyperloglog::merge(sources.iter().map(|bytes| &bytes[..]));
+                // O TTL do destino é mantido.
+                written(meta, &destination);
+                data.insert(destination, Value::Bytes(hyperloglog::from_registers(&registers)));
+                Ok(Reply::Ok)
+            }
+            Command::Restore { key, value, expiry, replace } => {
+                if !replace && data.contains_key(&key) && !is_expired(meta, &key, SystemTime::now())
+                {
+                    return Err("BUSYKEY Target key name already exists.");
+                }
+                data.insert(key.clone(), value);
+                // O payload não carrega TTLs de campos: os do valor substituído não valem mais.
+                written(meta, &key).field_expiries.clear();
+                match expiry {
+                    Some(at) => set_expiry(data, meta, key, at),
+                    None => written(meta, &key).expiry = None,
+                }
+                Ok(Reply::Ok)
+            }
+            // A chave (ou os campos) já foi removida antes da aplicação, se ainda estiver expirada.
+            Command::DeleteExpired { .. } => Ok(Reply::Ok),
+            Command::Move { .. } => unreachable!("MOVE é aplicado por move_key"),
+            Command::SwapDb { .. } => unreachable!("SWAPDB é aplicado por swap_databases"),
+            Command::Persist { key } => {
+                let now = SystemTime::now();
+                let has_ttl = data.contains_key(&key)
+                    && meta.get(&key).is_some_and(|m| m.expiry.is_some_and(|at| at > now));
+                if has_ttl {
+                    // Só o prazo sai; o valor e as estatísticas de acesso permanecem.
+                    written(meta, &key).expiry = None;
                 }
-                _ => return Err("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                Ok(Reply::Integer(has_ttl as i64))
             }
         }
-        drop(data_lock);
+    }
 
-        let cmd = Command::HSet { key, field, value };
-        let _ = self.cmd_tx.send(cmd);
+    /// Envia um comando para a task de escrita e aguarda o resultado da sua aplicação.
+    async fn execute(&self, db: usize, cmd: Command) -> Result<Reply, &'static str> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if self.write_tx.send((db, cmd, reply_tx)).is_err() {
+            return Err("ERR write task is not running");
+        }
+        reply_rx.await.unwrap_or(Err("ERR write task is not running"))
+    }
 
-        Ok(if created { 1 } else { 0 })
+    /// Recusa um sorteio com repetição maior que o limite do `proto-max-bulk-len`. Com `count`
+    /// negativo, a resposta tem `|count|` itens mesmo num conjunto pequeno, e um valor enorme
+    /// derrubaria o servidor ao montá-la.
+    fn check_sample_count(&self, count: i64) -> Result<(), &'static str> {
+        if count < 0 && count.unsigned_abs() > self.max_string_len as u64 {
+            return Err("ERR count exceeds maximum allowed size (proto-max-bulk-len)");
+        }
+        Ok(())
     }
 
-    /// Deleta uma chave do store
-    pub async fn delete(&self, key: &str) -> bool {
-        let data_lock = self.data.read().await;
-        let exists = data_lock.contains_key(key);
-        drop(data_lock);
-
-        if exists {
-            let cmd = Command::Delete { key: key.to_string() };
-            let _ = self.cmd_tx.send(cmd);
-            true
-        } else {
-            false
+    /// Busca uma chave para uma leitura, tratando uma chave expirada (ou um hash com todos os
+    /// campos expirados) como ausente. Ao encontrar algo expirado, também pede à task de escrita
+    /// que o remova, sem esperar pela limpeza periódica; o pedido vai uma vez só por chave até
+    /// ser aplicado.
+    fn lookup<'a>(
+        &self,
+        db: usize,
+        data: &'a HashMap<String, Value>,
+        meta: &HashMap<String, KeyMetadata>,
+        key: &str,
+    ) -> Option<&'a Value> {
+        let value = data.get(key)?;
+        let (expired, due_fields) = expiry_state(value, meta, key, SystemTime::now());
+        let first_request = || self.databases[db].pending_deletes().insert(key.to_string());
+        if (expired || due_fields > 0) && first_request() {
+            // A resposta é descartada: ninguém espera pela remoção.
+            let (reply_tx, _) = oneshot::channel();
+            let cmd = Command::DeleteExpired { key: key.to_string() };
+            let _ = self.write_tx.send((db, cmd, reply_tx));
         }
+        (!expired).then_some(value)
     }
 
-    /// Varre e remove todas as chaves expiradas.
-    pub async fn clean_expired(&self) {
-        let now = SystemTime::now();
-        let mut expired_keys = Vec::new();
+    /// Envia um comando `GET`. Operação de leitura, acessa diretamente o `RwLock`.
+    pub async fn get(&self, db: usize, key: &str) -> Option<Value> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+        read(&meta_lock, key);
+        self.lookup(db, &data_lock, &meta_lock, key).cloned()
+    }
 
-        let meta_lock = self.metadata.read().await;
-        for (key, meta) in meta_lock.iter() {
-            if let Some(expiry_time) = meta.expiry {
-                if now >= expiry_time {
-                    expired_keys.push(key.clone());
-                }
-            }
+    /// Retorna os bytes da string entre `start` e `end` (inclusivos), com a semântica de índices
+    /// do Redis: negativos contam a partir do fim e valores fora do intervalo são ajustados.
+    /// Só o trecho pedido é copiado, não o valor inteiro.
+    pub async fn getrange(
+        &self,
+        db: usize,
+        key: &str,
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<u8>, &'static str> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+        read(&meta_lock, key);
+        let bytes = match self.lookup(db, &data_lock, &meta_lock, key) {
+            Some(v) => v.as_bytes().ok_or(WRONGTYPE)?,
+            None => return Ok(Vec::new()),
+        };
+
+        let len = bytes.len() as i64;
+        let start = if start < 0 { len.saturating_add(start).max(0) } else { start };
+        let end = if end < 0 { len.saturating_add(end).max(0) } else { end.min(len - 1) };
+        if len == 0 || start > end {
+            return Ok(Vec::new());
         }
-        drop(meta_lock);
+        Ok(bytes[start as usize..=end as usize].to_vec())
+    }
+
+    /// Executa `f` sobre o hash guardado na chave, sob o lock de leitura. `f` recebe `None` se a
+    /// chave não existir (ou tiver expirado); se ela guardar outro tipo, o resultado é
+    /// `WRONGTYPE`. Campos com o TTL vencido ficam de fora. Base de todas as leituras de hash.
+    async fn with_hash<R>(
+        &self,
+        db: usize,
+        key: &str,
+        f: impl FnOnce(Option<LiveHash<'_>>) -> R,
+    ) -> Result<R, &'static str> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+        read(&meta_lock, key);
 
-        if !expired_keys.is_empty() {
-            let mut data_lock = self.data.write().await;
-            let mut meta_lock = self.metadata.write().await;
-            for key in expired_keys {
-                data_lock.remove(&key);
-                meta_lock.remove(&key);
+        match self.lookup(db, &data_lock, &meta_lock, key) {
+            // O `lookup` já pediu a remoção dos campos vencidos; até lá, eles são filtrados.
+            Some(Value::Hash(hash)) => {
+                Ok(f(Some(LiveHash::new(hash, meta_lock.get(key), SystemTime::now()))))
             }
+            Some(_) => Err(WRONGTYPE),
+            None => Ok(f(None)),
         }
     }
-}
\ No newline at end of file
+
+    /// Prazo restante de cada campo do hash, como o `ttl` faz para a chave: `None` se o campo
+    /// (ou a chave) não existir e `Some(None)` se ele existir sem TTL.
+    pub async fn field_ttls(
+        &self,
+        db: usize,
+        key: &str,
+        fields: &[String],
+    ) -> Result<Vec<Option<Option<Duration>>>, &'static str> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+        read(&meta_lock, key);
+
+        let hash = match self.lookup(db, &data_lock, &meta_lock, key) {
+            Some(Value::Hash(hash)) => Some(hash),
+            Some(_) => return Err(WRONGTYPE),
+            None => None,
+        };
+        let expiries = meta_lock.get(key).map(|m| &m.field_expiries);
+        let now = SystemTime::now();
+        Ok(fields
+            .iter()
+            .map(|field| {
+                hash.filter(|hash| hash.contains_key(field))?;
+                match expiries.and_then(|expiries| expiries.get(field)) {
+                    Some(at) => at.duration_since(now).ok().map(Some),
+                    None => Some(None),
+                }
+            })
+            .collect())
+    }
+
+    /// Valor de um campo do hash, ou `None` se o campo ou a chave não existirem.
+    pub async fn hget(
+        &self,
+        db: usize,
+        key: &str,
+        field: &str,
+    ) -> Result<Option<String>, &'static str> {
+        self.with_hash(db, key, |hash| hash?.get(field).cloned()).await
+    }
+
+    /// Valores de vários campos do hash, na ordem pedida, com `None` para os que não existirem.
+    /// Uma chave inexistente resulta em `None` para todos.
+    pub async fn hmget(
+        &self,
+        db: usize,
+        key: &str,
+        fields: &[String],
+    ) -> Result<Vec<Option<String>>, &'static str> {
+        self.with_hash(db, key, |hash| {
+            fields
+                .iter()
+                .map(|field| hash.and_then(|h| h.get(field).cloned()))
+                .collect()
+        })
+        .await
+    }
+
+    /// Indica se o campo existe no hash.
+    pub async fn hexists(&self, db: usize, key: &str, field: &str) -> Result<bool, &'static str> {
+        self.with_hash(db, key, |hash| hash.is_some_and(|h| h.contains_key(field)))
+            .await
+    }
+
+    /// Número de campos do hash; 0 se a chave não existir.
+    pub async fn hlen(&self, db: usize, key: &str) -> Result<i64, &'static str> {
+        self.with_hash(db, key, |hash| hash.map_or(0, |h| h.len() as i64))
+            .await
+    }
+
+    /// Tamanho em bytes do valor de um campo; 0 se o campo ou a chave não existirem.
+    pub async fn hstrlen(&self, db: usize, key: &str, field: &str) -> Result<i64, &'static str> {
+        self.with_hash(db, key, |hash| {
+            hash.and_then(|h| h.get(field)).map_or(0, |value| value.len() as i64)
+        })
+        .await
+    }
+
+    /// Nomes dos campos do hash; vazio se a chave não existir.
+    pub async fn hkeys(&self, db: usize, key: &str) -> Result<Vec<String>, &'static str> {
+        self.with_hash(db, key, |hash| hash.into_iter().flat_map(|h| h.keys().cloned()).collect())
+            .await
+    }
+
+    /// Valores dos campos do hash, na mesma ordem em que o `HKEYS` listaria os campos.
+    pub async fn hvals(&self, db: usize, key: &str) -> Result<Vec<String>, &'static str> {
+        self.with_hash(db, key, |hash| hash.into_iter().flat_map(|h| h.values().cloned()).collect())
+            .await
+    }
+
+    /// Campos sorteados do hash, com os valores. Com `count` positivo, até `count` campos
+    /// distintos; negativo, exatamente `|count|` campos, podendo repetir. Só os campos
+    /// sorteados são copiados.
+    pub async fn hrandfield(
+        &self,
+        db: usize,
+        key: &str,
+        count: i64,
+    ) -> Result<Vec<(String, String)>, &'static str> {
+        self.check_sample_count(count)?;
+        self.with_hash(db, key, |hash| {
+            let Some(hash) = hash else {
+                return Vec::new();
+            };
+            random_sample(hash.iter(), hash.len(), count)
+                .into_iter()
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect()
+        })
+        .await
+    }
+
+    /// Uma chamada do `HSCAN`: mesmo cursor do `SCAN`, aplicado aos campos do hash. Uma chave
+    /// inexistente se comporta como um hash vazio.
+    pub async fn hscan(
+        &self,
+        db: usize,
+        key: &str,
+        cursor: u64,
+        count: usize,
+        pattern: Option<&str>,
+    ) -> Result<(u64, Vec<(String, String)>), &'static str> {
+        self.with_hash(db, key, |hash| {
+            let Some(hash) = hash else {
+                return (0, Vec::new());
+            };
+            let (next, batch) = hash.scan(cursor, count);
+            let pairs = batch
+                .into_iter()
+                .filter(|(field, _)| pattern.is_none_or(|p| glob_match(p, field)))
+                .map(|(field, value)| (field.to_string(), value.clone()))
+                .collect();
+            (next, pairs)
+        })
+        .await
+    }
+
+    /// Todos os pares campo/valor do hash; vazio se a chave não existir. Os pares são copiados
+    /// sob o lock de leitura, que é liberado antes de a resposta ser serializada.
+    pub async fn hgetall(
+        &self,
+        db: usize,
+        key: &str,
+    ) -> Result<Vec<(String, String)>, &'static str> {
+        self.with_hash(db, key, |hash| {
+            hash.into_iter()
+                .flat_map(LiveHash::iter)
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect()
+        })
+        .await
+    }
+
+    /// Executa `f` sobre a lista guardada na chave, sob o lock de leitura, como o `with_hash`
+    /// faz para hashes. Base de todas as leituras de lista.
+    async fn with_list<R>(
+        &self,
+        db: usize,
+        key: &str,
+        f: impl FnOnce(Option<&VecDeque<String>>) -> R,
+    ) -> Result<R, &'static str> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+        read(&meta_lock, key);
+
+        match self.lookup(db, &data_lock, &meta_lock, key) {
+            Some(Value::List(list)) => Ok(f(Some(list))),
+            Some(_) => Err(WRONGTYPE),
+            None => Ok(f(None)),
+        }
+    }
+
+    /// Tamanho da lista; 0 se a chave não existir.
+    pub async fn llen(&self, db: usize, key: &str) -> Result<i64, &'static str> {
+        self.with_list(db, key, |list| list.map_or(0, |l| l.len() as i64)).await
+    }
+
+    /// Posições em que `element` aparece na lista, como o `LPOS`. A busca começa na `rank`-ésima
+    /// ocorrência (negativo conta a partir da cauda, sem nunca ser zero), devolve até `count`
+    /// posições e examina no máximo `maxlen` elementos; zero em `count` ou `maxlen` é sem limite.
+    pub async fn lpos(
+        &self,
+        db: usize,
+        key: &str,
+        element: &str,
+        rank: i64,
+        count: usize,
+        maxlen: usize,
+    ) -> Result<Vec<i64>, &'static str> {
+        self.with_list(db, key, |list| {
+            let Some(list) = list else {
+                return Vec::new();
+            };
+            let scanned = if maxlen == 0 { list.len() } else { maxlen.min(list.len()) };
+            let wanted = if count == 0 { usize::MAX } else { count };
+            let skipped = usize::try_from(rank.unsigned_abs() - 1).unwrap_or(usize::MAX);
+            let positions = list.iter().enumerate();
+            let ordered: Box<dyn Iterator<Item = (usize, &String)>> = if rank > 0 {
+                Box::new(positions)
+            } else {
+                Box::new(positions.rev())
+            };
+            ordered
+                .take(scanned)
+                .filter(|(_, e)| *e == element)
+                .skip(skipped)
+                .take(wanted)
+                .map(|(i, _)| i as i64)
+                .collect()
+        })
+        .await
+    }
+
+    /// Elemento na posição `index` da lista, ou `None` se ela estiver fora da lista ou a chave
+    /// não existir.
+    pub async fn lindex(
+        &self,
+        db: usize,
+        key: &str,
+        index: i64,
+    ) -> Result<Option<String>, &'static str> {
+        self.with_list(db, key, |list| {
+            let list = list?;
+            list.get(list_index(list.len(), index)?).cloned()
+        })
+        .await
+    }
+
+    /// Executa `f` sobre o conjunto guardado na chave, sob o lock de leitura, como o `with_hash`
+    /// faz para hashes. Base de todas as leituras de conjunto.
+    async fn with_set<R>(
+        &self,
+        db: usize,
+        key: &str,
+        f: impl FnOnce(Option<&ScanSet>) -> R,
+    ) -> Result<R, &'static str> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+        read(&meta_lock, key);
+
+        match self.lookup(db, &data_lock, &meta_lock, key) {
+            Some(Value::Set(set)) => Ok(f(Some(set))),
+            Some(_) => Err(WRONGTYPE),
+            None => Ok(f(None)),
+        }
+    }
+
+    /// Membros do conjunto, copiados para que o lock seja liberado antes da serialização;
+    /// vazio se a chave não existir.
+    pub async fn smembers(&self, db: usize, key: &str) -> Result<Vec<String>, &'static str> {
+        self.with_set(db, key, |set| set.into_iter().flatten().cloned().collect()).await
+    }
+
+    /// Membros sorteados do conjunto, sem removê-los, como o `SRANDMEMBER`: com `count`
+    /// positivo, até `count` membros distintos; com negativo, exatamente `|count|`, podendo
+    /// repetir.
+    pub async fn srandmember(
+        &self,
+        db: usize,
+        key: &str,
+        count: i64,
+    ) -> Result<Vec<String>, &'static str> {
+        self.check_sample_count(count)?;
+        self.with_set(db, key, |set| {
+            let Some(set) = set else {
+                return Vec::new();
+            };
+            random_sample(set.iter(), set.len(), count).into_iter().cloned().collect()
+        })
+        .await
+    }
+
+    /// Uma chamada do `SSCAN`: mesmo cursor do `SCAN`, aplicado aos membros do conjunto. Uma
+    /// chave inexistente se comporta como um conjunto vazio.
+    pub async fn sscan(
+        &self,
+        db: usize,
+        key: &str,
+        cursor: u64,
+        count: usize,
+        pattern: Option<&str>,
+    ) -> Result<(u64, Vec<String>), &'static str> {
+        self.with_set(db, key, |set| {
+            let Some(set) = set else {
+                return (0, Vec::new());
+            };
+            let (next, batch) = set.scan(cursor, count);
+            let members = batch
+                .into_iter()
+                .filter(|member| pattern.is_none_or(|p| glob_match(p, member)))
+                .map(str::to_string)
+                .collect();
+            (next, members)
+        })
+        .await
+    }
+
+    /// Executa `f` sobre o conjunto ordenado guardado na chave, sob o lock de leitura, como o
+    /// `with_hash` faz para hashes. Base de todas as leituras de conjunto ordenado.
+    async fn with_zset<R>(
+        &self,
+        db: usize,
+        key: &str,
+        f: impl FnOnce(Option<&ZSet>) -> R,
+    ) -> Result<R, &'static str> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+        read(&meta_lock, key);
+
+        match self.lookup(db, &data_lock, &meta_lock, key) {
+            Some(Value::ZSet(zset)) => Ok(f(Some(zset))),
+            Some(_) => Err(WRONGTYPE),
+            None => Ok(f(None)),
+        }
+    }
+
+    /// Score do membro no conjunto ordenado, ou `None` se ele (ou a chave) não existir.
+    pub async fn zscore(
+        &self,
+        db: usize,
+        key: &str,
+        member: &str,
+    ) -> Result<Option<f64>, &'static str> {
+        self.with_zset(db, key, |zset| zset?.score(member)).await
+    }
+
+    /// Score de cada membro, na ordem dada, com `None` para os ausentes. Todos são consultados
+    /// sob uma única aquisição do lock.
+    pub async fn zmscore(
+        &self,
+        db: usize,
+        key: &str,
+        members: &[String],
+    ) -> Result<Vec<Option<f64>>, &'static str> {
+        self.with_zset(db, key, |zset| {
+            members.iter().map(|m| zset.and_then(|z| z.score(m))).collect()
+        })
+        .await
+    }
+
+    /// Posição do membro em ordem de score, crescente ou, com `rev`, decrescente, junto com o
+    /// seu score. `None` se o membro (ou a chave) não existir.
+    pub async fn zrank(
+        &self,
+        db: usize,
+        key: &str,
+        member: &str,
+        rev: bool,
+    ) -> Result<Option<(usize, f64)>, &'static str> {
+        self.with_zset(db, key, |zset| {
+            let zset = zset?;
+            let rank = zset.rank(member)?;
+            let rank = if rev { zset.len() - 1 - rank } else { rank };
+            Some((rank, zset.score(member)?))
+        })
+        .await
+    }
+
+    /// Membros sorteados do conjunto ordenado, com os scores, como o `ZRANDMEMBER`: com `count`
+    /// positivo, até `count` membros distintos; com negativo, exatamente `|count|`, podendo
+    /// repetir. Só os membros sorteados são copiados.
+    pub async fn zrandmember(
+        &self,
+        db: usize,
+        key: &str,
+        count: i64,
+    ) -> Result<Vec<(String, f64)>, &'static str> {
+        self.check_sample_count(count)?;
+        self.with_zset(db, key, |zset| {
+            let Some(zset) = zset else {
+                return Vec::new();
+            };
+            random_sample(zset.iter(), zset.len(), count)
+                .into_iter()
+                .map(|(member, score)| (member.to_string(), score))
+                .collect()
+        })
+        .await
+    }
+
+    /// Uma chamada do `ZSCAN`: mesmo cursor do `SCAN`, aplicado aos membros do conjunto
+    /// ordenado. Uma chave inexistente se comporta como um conjunto vazio.
+    pub async fn zscan(
+        &self,
+        db: usize,
+        key: &str,
+        cursor: u64,
+        count: usize,
+        pattern: Option<&str>,
+    ) -> Result<(u64, Vec<(String, f64)>), &'static str> {
+        self.with_zset(db, key, |zset| {
+            let Some(zset) = zset else {
+                return (0, Vec::new());
+            };
+            let (next, batch) = zset.scan(cursor, count);
+            let members = batch
+                .into_iter()
+                .filter(|(member, _)| pattern.is_none_or(|p| glob_match(p, member)))
+                .map(|(member, score)| (member.to_string(), score))
+                .collect();
+            (next, members)
+        })
+        .await
+    }
+
+    /// Número de membros do conjunto ordenado; 0 se a chave não existir.
+    pub async fn zcard(&self, db: usize, key: &str) -> Result<i64, &'static str> {
+        self.with_zset(db, key, |zset| zset.map_or(0, |z| z.len() as i64)).await
+    }
+
+    /// Número de membros com score entre `min` e `max`. Só a faixa do índice é percorrida.
+    pub async fn zcount(
+        &self,
+        db: usize,
+        key: &str,
+        min: ScoreBound,
+        max: ScoreBound,
+    ) -> Result<i64, &'static str> {
+        self.with_zset(db, key, |zset| {
+            zset.map_or(0, |z| z.range_by_score(min, max).count() as i64)
+        })
+        .await
+    }
+
+    /// Membros com score entre `min` e `max`, com os seus scores, como o `ZRANGEBYSCORE`. Com
+    /// `rev`, a ordem é decrescente, mas os limites continuam sendo o menor e o maior. `limit` é
+    /// o `LIMIT offset count`.
+    pub async fn zrange_by_score(
+        &self,
+        db: usize,
+        key: &str,
+        min: ScoreBound,
+        max: ScoreBound,
+        rev: bool,
+        limit: Option<(i64, i64)>,
+    ) -> Result<Vec<(String, f64)>, &'static str> {
+        self.with_zset(db, key, |zset| {
+            zset.map_or_else(Vec::new, |z| page(z.range_by_score(min, max), rev, limit))
+        })
+        .await
+    }
+
+    /// Membros entre `min` e `max` na ordem do texto, como o `ZRANGEBYLEX`, com o mesmo `rev` e
+    /// `limit` do `zrange_by_score`.
+    pub async fn zrange_by_lex(
+        &self,
+        db: usize,
+        key: &str,
+        min: LexBound,
+        max: LexBound,
+        rev: bool,
+        limit: Option<(i64, i64)>,
+    ) -> Result<Vec<String>, &'static str> {
+        self.with_zset(db, key, |zset| {
+            let Some(zset) = zset else {
+                return Vec::new();
+            };
+            let page = page(zset.range_by_lex(min, max), rev, limit);
+            page.into_iter().map(|(member, _)| member).collect()
+        })
+        .await
+    }
+
+    /// Número de membros do conjunto; 0 se a chave não existir.
+    pub async fn scard(&self, db: usize, key: &str) -> Result<i64, &'static str> {
+        self.with_set(db, key, |set| set.map_or(0, |s| s.len() as i64)).await
+    }
+
+    /// Indica se `member` pertence ao conjunto.
+    pub async fn sismember(&self, db: usize, key: &str, member: &str) -> Result<bool, &'static str> {
+        self.with_set(db, key, |set| set.is_some_and(|s| s.contains(member))).await
+    }
+
+    /// Indica, para cada membro e na ordem dada, se ele pertence ao conjunto. Todos são
+    /// consultados sob uma única aquisição do lock.
+    pub async fn smismember(
+        &self,
+        db: usize,
+        key: &str,
+        members: &[String],
+    ) -> Result<Vec<bool>, &'static str> {
+        self.with_set(db, key, |set| {
+            members.iter().map(|m| set.is_some_and(|s| s.contains(m))).collect()
+        })
+        .await
+    }
+
+    /// Conjuntos guardados nas chaves, como o `lookup` os vê; `None` para as ausentes e
+    /// `WRONGTYPE` se alguma guardar outro tipo.
+    fn lookup_sets<'a>(
+        &self,
+        db: usize,
+        data: &'a HashMap<String, Value>,
+        meta: &HashMap<String, KeyMetadata>,
+        keys: &[String],
+    ) -> Result<Vec<Option<&'a ScanSet>>, &'static str> {
+        keys.iter()
+            .map(|key| {
+                read(meta, key);
+                match self.lookup(db, data, meta, key) {
+                    Some(Value::Set(set)) => Ok(Some(set)),
+                    Some(_) => Err(WRONGTYPE),
+                    None => Ok(None),
+                }
+            })
+            .collect()
+    }
+
+    /// Resultado de `SINTER`, `SUNION` ou `SDIFF` sobre os conjuntos das chaves, lidos todos sob
+    /// uma única aquisição do lock. Chaves inexistentes contam como conjuntos vazios.
+    pub async fn set_op(
+        &self,
+        db: usize,
+        op: SetOp,
+        keys: &[String],
+    ) -> Result<Vec<String>, &'static str> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+
+        let sets = self.lookup_sets(db, &data_lock, &meta_lock, keys)?;
+        Ok(combine_sets(op, &sets).into_iter().cloned().collect())
+    }
+
+    /// Tamanho da interseção dos conjuntos das chaves, como o `SINTERCARD`. A contagem para ao
+    /// chegar em `limit`, sem calcular o resto da interseção; zero é sem limite.
+    pub async fn sintercard(
+        &self,
+        db: usize,
+        keys: &[String],
+        limit: usize,
+    ) -> Result<i64, &'static str> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+
+        let sets = self.lookup_sets(db, &data_lock, &meta_lock, keys)?;
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        Ok(intersection(&sets).take(limit).count() as i64)
+    }
+
+    /// Busca várias chaves com uma única aquisição do lock de leitura. Chaves ausentes, expiradas
+    /// ou de outro tipo resultam em `None`, como no Redis.
+    pub async fn mget(&self, db: usize, keys: &[String]) -> Vec<Option<Vec<u8>>> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+
+        keys.iter()
+            .map(|key| {
+                read(&meta_lock, key);
+                self.lookup(db, &data_lock, &meta_lock, key)
+                    .and_then(|v| v.as_bytes().map(Cow::into_owned))
+            })
+            .collect()
+    }
+
+    /// Conta quantas das chaves existem, contando repetidas mais de uma vez. Chaves expiradas
+    /// que a limpeza ainda não removeu são consideradas ausentes.
+    pub async fn exists(&self, db: usize, keys: &[String]) -> i64 {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+
+        keys.iter()
+            .filter(|key| self.lookup(db, &data_lock, &meta_lock, key).is_some())
+            .count() as i64
+    }
+
+    /// Conta quantas das chaves existem, como o `EXISTS`, registrando um acesso em cada uma.
+    pub async fn touch(&self, db: usize, keys: &[String]) -> i64 {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+
+        keys.iter()
+            .filter(|key| self.lookup(db, &data_lock, &meta_lock, key).is_some())
+            .inspect(|key| read(&meta_lock, key))
+            .count() as i64
+    }
+
+    /// Número de chaves, sem contar as que o `lookup` leria como ausentes mas a limpeza ainda
+    /// não removeu. Essas têm um prazo vencido no índice de expiração, então só as entradas
+    /// vencidas são visitadas, e não todas as chaves.
+    pub async fn dbsize(&self, db: usize) -> i64 {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+        let now = SystemTime::now();
+        let mut expiries = self.databases[db].expiries();
+
+        // As entradas vencidas saem do índice só para serem contadas e voltam em seguida. O
+        // índice guarda entradas desatualizadas, então cada chave é conferida com os metadados.
+        let mut due = Vec::new();
+        while expiries.peek().is_some_and(|Reverse((at, _))| *at <= now) {
+            due.extend(expiries.pop());
+        }
+        let expired: HashSet<&str> = due
+            .iter()
+            .map(|Reverse((_, key))| key.as_str())
+            .filter(|key| {
+                data_lock.get(*key).is_some_and(|value| expiry_state(value, &meta_lock, key, now).0)
+            })
+            .collect();
+        let len = data_lock.len() - expired.len();
+        expiries.extend(due);
+        len as i64
+    }
+
+    /// Nome do tipo do valor guardado na chave, ou `none` se ela não existir ou tiver expirado.
+    pub async fn type_of(&self, db: usize, key: &str) -> &'static str {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+
+        self.lookup(db, &data_lock, &meta_lock, key)
+            .map_or("none", Value::type_name)
+    }
+
+    /// Tempo de vida restante da chave: `None` se ela não existir (ou já tiver expirado) e
+    /// `Some(None)` se existir sem TTL.
+    pub async fn ttl(&self, db: usize, key: &str) -> Option<Option<Duration>> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+
+        self.lookup(db, &data_lock, &meta_lock, key)?;
+        match meta_lock.get(key).and_then(|m| m.expiry) {
+            Some(expiry) => expiry.duration_since(SystemTime::now()).ok().map(Some),
+            None => Some(None),
+        }
+    }
+
+    /// Instante absoluto em que a chave expira: `None` se ela não existir (ou já tiver
+    /// expirado) e `Some(None)` se existir sem TTL.
+    pub async fn expire_time(&self, db: usize, key: &str) -> Option<Option<SystemTime>> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+
+        self.lookup(db, &data_lock, &meta_lock, key)?;
+        Some(meta_lock.get(key).and_then(|m| m.expiry))
+    }
+
+    /// Todas as chaves do banco que ainda não expiraram.
+    pub async fn keys(&self, db: usize) -> Vec<String> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+
+        data_lock
+            .keys()
+            .filter(|key| self.lookup(db, &data_lock, &meta_lock, key).is_some())
+            .cloned()
+            .collect()
+    }
+
+    /// Uma chamada do `SCAN`: retorna o próximo cursor e as chaves do lote que passam nos filtros
+    /// `MATCH` e `TYPE`. O lote vem do índice por hash do banco, então a chamada só percorre as
+    /// chaves que retorna.
+    pub async fn scan(
+        &self,
+        db: usize,
+        cursor: u64,
+        count: usize,
+        pattern: Option<&str>,
+        type_name: Option<&str>,
+    ) -> (u64, Vec<String>) {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+
+        let (next, batch) = data_lock.scan(cursor, count);
+        let keys = batch
+            .into_iter()
+            .filter(|(key, value)| {
+                self.lookup(db, &data_lock, &meta_lock, key).is_some()
+                    && pattern.is_none_or(|p| glob_match(p, key))
+                    && type_name.is_none_or(|t| value.type_name().eq_ignore_ascii_case(t))
+            })
+            .map(|(key, _)| key.to_string())
+            .collect();
+        (next, keys)
+    }
+
+    /// Codificação interna do valor (`OBJECT ENCODING`), ou `None` se a chave não existir.
+    pub async fn encoding(&self, db: usize, key: &str) -> Option<&'static str> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+
+        self.lookup(db, &data_lock, &meta_lock, key)
+            .map(Value::encoding)
+    }
+
+    /// Tempo desde o último acesso à chave (`OBJECT IDLETIME`). Consultá-lo não conta como
+    /// acesso.
+    pub async fn idle_time(&self, db: usize, key: &str) -> Option<Duration> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+        self.lookup(db, &data_lock, &meta_lock, key)?;
+        let last_access = meta_lock
+            .get(key)
+            .map_or(0, |m| m.last_access.load(Ordering::Relaxed));
+        let elapsed = to_unix_millis(SystemTime::now()).saturating_sub(last_access as i64);
+        Some(Duration::from_millis(elapsed.max(0) as u64))
+    }
+
+    /// Elementos da lista ou do conjunto em `key`, ordenados segundo `options`. Uma chave
+    /// inexistente resulta numa lista vazia.
+    pub async fn sort(
+        &self,
+        db: usize,
+        key: &str,
+        options: SortOptions,
+    ) -> Result<Vec<String>, &'static str> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+
+        read(&meta_lock, key);
+        sort_elements(self.lookup(db, &data_lock, &meta_lock, key), options)
+    }
+
+    /// Valor da chave serializado no formato do `DUMP`, ou `None` se ela não existir.
+    pub async fn dump(&self, db: usize, key: &str) -> Option<Vec<u8>> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+
+        read(&meta_lock, key);
+        self.lookup(db, &data_lock, &meta_lock, key)
+            .map(dump::serialize)
+    }
+
+    /// Uma chave aleatória que não tenha expirado, ou `None` se o banco estiver vazio. Sorteia
+    /// uma posição e percorre o mapa a partir dela, sem copiar as chaves.
+    pub async fn random_key(&self, db: usize) -> Option<String> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+        if data_lock.is_empty() {
+            return None;
+        }
+        let start = rand::thread_rng().gen_range(0..data_lock.len());
+
+        data_lock
+            .keys()
+            .skip(start)
+            .chain(data_lock.keys().take(start))
+            .find(|key| self.lookup(db, &data_lock, &meta_lock, key).is_some())
+            .cloned()
+    }
+
+    /// Envia um comando `SET` para a task de processamento. A condição (`NX`/`XX`) é verificada
+    /// junto com a escrita; retorna `false` se ela impediu o `SET`.
+    pub async fn set(
+        &self,
+        db: usize,
+        key: String,
+        value: Value,
+        expiry: SetExpiry,
+        condition: SetCondition,
+    ) -> bool {
+        let cmd = Command::Set { key, value, expiry, condition, get: false };
+        matches!(self.execute(db, cmd).await, Ok(Reply::Ok))
+    }
+
+    /// `SET` com a opção `GET`: retorna o valor anterior, lido na mesma operação da escrita.
+    pub async fn set_get(
+        &self,
+        db: usize,
+        key: String,
+        value: Value,
+        expiry: SetExpiry,
+        condition: SetCondition,
+    ) -> Result<Option<Vec<u8>>, &'static str> {
+        let cmd = Command::Set { key, value, expiry, condition, get: true };
+        match self.execute(db, cmd).await? {
+            Reply::Previous { value, .. } => Ok(value),
+            _ => unreachable!("SET ... GET sempre responde com o valor anterior"),
+        }
+    }
+
+    /// Envia um comando `HSET`. Retorna quantos campos foram criados; os sobrescritos não contam.
+    pub async fn hset(
+        &self,
+        db: usize,
+        key: String,
+        pairs: Vec<(String, String)>,
+    ) -> Result<i64, &'static str> {
+        match self.execute(db, Command::HSet { key, pairs }).await? {
+            Reply::Integer(created) => Ok(created),
+            _ => unreachable!("HSET sempre responde com um inteiro"),
+        }
+    }
+
+    /// Define o campo do hash só se ele não existir, criando a chave se necessário. A verificação
+    /// e a escrita acontecem juntas na task de escrita.
+    pub async fn hsetnx(
+        &self,
+        db: usize,
+        key: String,
+        field: String,
+        value: String,
+    ) -> Result<bool, &'static str> {
+        let reply = self.execute(db, Command::HSetNx { key, field, value }).await?;
+        Ok(reply == Reply::Integer(1))
+    }
+
+    /// Remove campos do hash e retorna quantos existiam. A chave é removida junto com o último
+    /// campo.
+    pub async fn hdel(
+        &self,
+        db: usize,
+        key: String,
+        fields: Vec<String>,
+    ) -> Result<i64, &'static str> {
+        match self.execute(db, Command::HDel { key, fields }).await? {
+            Reply::Integer(removed) => Ok(removed),
+            _ => unreachable!("HDEL sempre responde com um inteiro"),
+        }
+    }
+
+    /// Define o prazo de expiração de campos do hash. Retorna um código por campo: -2 se ele
+    /// não existir, 0 se a condição impediu, 1 se o prazo foi definido e 2 se o campo foi
+    /// removido por causa de um prazo que já passou.
+    pub async fn hexpire(
+        &self,
+        db: usize,
+        key: String,
+        fields: Vec<String>,
+        at: SystemTime,
+        condition: ExpireCondition,
+    ) -> Result<Vec<i64>, &'static str> {
+        match self.execute(db, Command::HExpire { key, fields, at, condition }).await? {
+            Reply::Integers(statuses) => Ok(statuses),
+            _ => unreachable!("HEXPIRE sempre responde com um código por campo"),
+        }
+    }
+
+    /// Remove o TTL de campos do hash. Retorna um código por campo: -2 se ele não existir, -1
+    /// se não tiver TTL e 1 se o TTL foi removido.
+    pub async fn hpersist(
+        &self,
+        db: usize,
+        key: String,
+        fields: Vec<String>,
+    ) -> Result<Vec<i64>, &'static str> {
+        match self.execute(db, Command::HPersist { key, fields }).await? {
+            Reply::Integers(statuses) => Ok(statuses),
+            _ => unreachable!("HPERSIST sempre responde com um código por campo"),
+        }
+    }
+
+    /// Soma `delta` ao inteiro guardado no campo do hash, criando a chave e o campo com 0 se
+    /// necessário. Retorna o novo valor.
+    pub async fn hincr_by(
+        &self,
+        db: usize,
+        key: String,
+        field: String,
+        delta: i64,
+    ) -> Result<i64, &'static str> {
+        match self.execute(db, Command::HIncrBy { key, field, delta }).await? {
+            Reply::Integer(value) => Ok(value),
+            _ => unreachable!("HINCRBY sempre responde com um inteiro"),
+        }
+    }
+
+    /// Soma `increment` ao número de ponto flutuante guardado no campo do hash e retorna o
+    /// novo valor formatado.
+    pub async fn hincr_by_float(
+        &self,
+        db: usize,
+        key: String,
+        field: String,
+        increment: f64,
+    ) -> Result<String, &'static str> {
+        match self.execute(db, Command::HIncrByFloat { key, field, increment }).await? {
+            Reply::Bulk(value) => Ok(value),
+            _ => unreachable!("HINCRBYFLOAT sempre responde com um bulk string"),
+        }
+    }
+
+    /// Insere os elementos na ponta `end` da lista, criando a chave se necessário; com
+    /// `if_exists`, só se ela já existir. Retorna o novo tamanho da lista, ou 0 se nada foi
+    /// inserido. A verificação e a inserção acontecem juntas na task de escrita.
+    pub async fn push(
+        &self,
+        db: usize,
+        key: String,
+        elements: Vec<String>,
+        end: ListEnd,
+        if_exists: bool,
+    ) -> Result<i64, &'static str> {
+        match self.execute(db, Command::Push { key, elements, end, if_exists }).await? {
+            Reply::Integer(len) => Ok(len),
+            _ => unreachable!("LPUSH e RPUSH sempre respondem com um inteiro"),
+        }
+    }
+
+    /// Retira até `count` elementos da ponta `end` da lista, na ordem em que saem. A chave é
+    /// removida junto com o último elemento. Retorna `None` se ela não existir.
+    pub async fn pop(
+        &self,
+        db: usize,
+        key: String,
+        count: usize,
+        end: ListEnd,
+    ) -> Result<Option<Vec<String>>, &'static str> {
+        match self.execute(db, Command::Pop { key, count, end }).await? {
+            Reply::Elements(popped) => Ok(Some(popped)),
+            Reply::Nil => Ok(None),
+            _ => unreachable!("LPOP e RPOP respondem com os elementos retirados"),
+        }
+    }
+
+    /// Retira até `count` elementos da ponta `end` da primeira das `keys` que guardar uma
+    /// lista. Retorna a chave escolhida e os elementos, ou `None` se nenhuma existir.
+    pub async fn mpop(
+        &self,
+        db: usize,
+        keys: Vec<String>,
+        end: ListEnd,
+        count: usize,
+    ) -> Result<Option<(String, Vec<String>)>, &'static str> {
+        match self.execute(db, Command::MPop { keys, end, count }).await? {
+            Reply::Popped { key, elements } => Ok(Some((key, elements))),
+            Reply::Nil => Ok(None),
+            _ => unreachable!("LMPOP responde com a chave e os elementos retirados"),
+        }
+    }
+
+    /// Substitui o elemento na posição `index` da lista.
+    pub async fn lset(
+        &self,
+        db: usize,
+        key: String,
+        index: i64,
+        element: String,
+    ) -> Result<(), &'static str> {
+        self.execute(db, Command::LSet { key, index, element }).await?;
+        Ok(())
+    }
+
+    /// Insere `element` antes ou depois do primeiro `pivot` da lista. Retorna o novo tamanho,
+    /// -1 se o `pivot` não for encontrado e 0 se a chave não existir.
+    pub async fn linsert(
+        &self,
+        db: usize,
+        key: String,
+        before: bool,
+        pivot: String,
+        element: String,
+    ) -> Result<i64, &'static str> {
+        let cmd = Command::LInsert { key, before, pivot, element };
+        match self.execute(db, cmd).await? {
+            Reply::Integer(len) => Ok(len),
+            _ => unreachable!("LINSERT sempre responde com um inteiro"),
+        }
+    }
+
+    /// Remove até `count` ocorrências de `element` da lista e retorna quantas foram removidas. A
+    /// chave é removida se a lista ficar vazia.
+    pub async fn lrem(
+        &self,
+        db: usize,
+        key: String,
+        count: i64,
+        element: String,
+    ) -> Result<i64, &'static str> {
+        match self.execute(db, Command::LRem { key, count, element }).await? {
+            Reply::Integer(removed) => Ok(removed),
+            _ => unreachable!("LREM sempre responde com um inteiro"),
+        }
+    }
+
+    /// Reduz a lista aos elementos entre `start` e `stop`, removendo a chave se não sobrar nada.
+    pub async fn ltrim(
+        &self,
+        db: usize,
+        key: String,
+        start: i64,
+        stop: i64,
+    ) -> Result<(), &'static str> {
+        self.execute(db, Command::LTrim { key, start, stop }).await?;
+        Ok(())
+    }
+
+    /// Move um elemento de uma lista para outra numa única operação da task de escrita.
+    /// Retorna o elemento movido, ou `None` se a origem não existir.
+    pub async fn lmove(
+        &self,
+        db: usize,
+        source: String,
+        destination: String,
+        from: ListEnd,
+        to: ListEnd,
+    ) -> Result<Option<String>, &'static str> {
+        match self.execute(db, Command::LMove { source, destination, from, to }).await? {
+            Reply::Bulk(element) => Ok(Some(element)),
+            Reply::Nil => Ok(None),
+            _ => unreachable!("LMOVE responde com o elemento movido"),
+        }
+    }
+
+    /// `BLPOP`/`BRPOP`: retira um elemento da primeira das `keys` que tiver elementos,
+    /// esperando até que alguma receba um se todas estiverem vazias. Retorna a chave e o
+    /// elemento, ou `None` se o `timeout` passar antes; sem `timeout`, a espera não tem fim.
+    pub async fn blocking_pop(
+        &self,
+        db: usize,
+        keys: &[String],
+        end: ListEnd,
+        timeout: Option<Duration>,
+    ) -> Result<Option<(String, String)>, &'static str> {
+        let served = self.block_on(db, keys, BlockedAction::Pop(end), timeout).await?;
+        Ok(served.map(|(key, reply)| match reply {
+            Reply::Elements(mut popped) => (key, popped.remove(0)),
+            _ => unreachable!("o BLPOP só é atendido com um elemento retirado"),
+        }))
+    }
+
+    /// `BLMOVE`/`BRPOPLPUSH`: move um elemento de `source` para `destination` como o `LMOVE`,
+    /// esperando que a origem receba elementos se estiver vazia. Retorna o elemento movido, ou
+    /// `None` se o `timeout` passar antes.
+    pub async fn blocking_move(
+        &self,
+        db: usize,
+        source: String,
+        destination: String,
+        from: ListEnd,
+        to: ListEnd,
+        timeout: Option<Duration>,
+    ) -> Result<Option<String>, &'static str> {
+        let action = BlockedAction::Move { destination, from, to };
+        let served = self.block_on(db, &[source], action, timeout).await?;
+        Ok(served.map(|(_, reply)| match reply {
+            Reply::Bulk(element) => element,
+            _ => unreachable!("o BLMOVE só é atendido com o elemento movido"),
+        }))
+    }
+
+    /// `BZPOPMIN`/`BZPOPMAX`: retira o membro de menor (ou, com `max`, maior) score do primeiro
+    /// conjunto ordenado não vazio entre as `keys`, esperando até `timeout` que algum receba
+    /// membros. Retorna a chave, o membro e o score, ou `None` se o `timeout` passar antes.
+    pub async fn blocking_zpop(
+        &self,
+        db: usize,
+        keys: &[String],
+        max: bool,
+        timeout: Option<Duration>,
+    ) -> Result<Option<(String, String, f64)>, &'static str> {
+        let served = self.block_on(db, keys, BlockedAction::ZPop { max }, timeout).await?;
+        Ok(served.map(|(key, reply)| match reply {
+            Reply::Scored(mut popped) => {
+                let (member, score) = popped.remove(0);
+                (key, member, score)
+            }
+            _ => unreachable!("o BZPOPMIN só é atendido com um membro retirado"),
+        }))
+    }
+
+    /// Executa `action` na primeira das `keys` que guardar um valor do tipo que ela consome, ou
+    /// espera até que uma delas receba elementos. Nesse caso quem executa a ação é a task de escrita, na mesma
+    /// operação que liberou o cliente.
+    async fn block_on(
+        &self,
+        db: usize,
+        keys: &[String],
+        action: BlockedAction,
+        timeout: Option<Duration>,
+    ) -> Result<Option<(String, Reply)>, &'static str> {
+        // Um timeout grande demais para o relógio equivale a esperar para sempre.
+        let deadline = timeout.and_then(|t| tokio::time::Instant::now().checked_add(t));
+        let database = &self.databases[db];
+        loop {
+            let data_lock = database.data.read().await;
+            let meta_lock = database.metadata.read().await;
+            let mut ready = None;
+            for key in keys {
+                match self.lookup(db, &data_lock, &meta_lock, key) {
+                    Some(value) if action.accepts(value) => {
+                        ready = Some(key);
+                        break;
+                    }
+                    Some(_) => return Err(WRONGTYPE),
+                    None => {}
+                }
+            }
+
+            if let Some(key) = ready {
+                drop((data_lock, meta_lock));
+                let reply = self.execute(db, action.command(key.clone())).await?;
+                // Outro cliente pode ter esvaziado a lista desde a verificação.
+                match &reply {
+                    Reply::Nil => continue,
+                    Reply::Elements(popped) if popped.is_empty() => continue,
+                    Reply::Scored(popped) if popped.is_empty() => continue,
+                    _ => return Ok(Some((key.clone(), reply))),
+                }
+            }
+
+            // O registro acontece sob o lock de leitura: nenhuma escrita cabe entre a
+            // verificação acima e a entrada na fila.
+            let (client, mut reply_rx) = BlockedClient::new(action.clone());
+            let _registration = database.blocked.register(keys, client.clone());
+            drop((data_lock, meta_lock));
+
+            let outcome = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, &mut reply_rx).await,
+                None => Ok((&mut reply_rx).await),
+            };
+            let served = match outcome {
+                Ok(served) => served,
+                // Se a task de escrita pegou o canal antes, a resposta já está a caminho.
+                Err(_) if client.take_reply_tx().is_some() => return Ok(None),
+                Err(_) => reply_rx.await,
+            };
+            match served {
+                Ok((key, result)) => return result.map(|reply| Some((key, reply))),
+                // A task de escrita descartou o canal sem responder; a verificação recomeça.
+                Err(_) => continue,
+            }
+        }
+    }
+
+    /// Adiciona membros ao conjunto, criando a chave se necessário. Retorna quantos eram novos.
+    pub async fn sadd(
+        &self,
+        db: usize,
+        key: String,
+        members: Vec<String>,
+    ) -> Result<i64, &'static str> {
+        match self.execute(db, Command::SAdd { key, members }).await? {
+            Reply::Integer(added) => Ok(added),
+            _ => unreachable!("SADD sempre responde com um inteiro"),
+        }
+    }
+
+    /// Remove membros do conjunto e retorna quantos existiam. A chave é removida junto com o
+    /// último membro.
+    pub async fn srem(
+        &self,
+        db: usize,
+        key: String,
+        members: Vec<String>,
+    ) -> Result<i64, &'static str> {
+        match self.execute(db, Command::SRem { key, members }).await? {
+            Reply::Integer(removed) => Ok(removed),
+            _ => unreachable!("SREM sempre responde com um inteiro"),
+        }
+    }
+
+    /// Remove até `count` membros sorteados do conjunto e os retorna. A chave é removida junto
+    /// com o último membro. O sorteio acontece na task de escrita, junto com a remoção.
+    pub async fn spop(
+        &self,
+        db: usize,
+        key: String,
+        count: usize,
+    ) -> Result<Vec<String>, &'static str> {
+        match self.execute(db, Command::SPop { key, count }).await? {
+            Reply::Elements(popped) => Ok(popped),
+            _ => unreachable!("SPOP sempre responde com os membros retirados"),
+        }
+    }
+
+    /// Define o score de cada membro do conjunto ordenado, criando a chave se necessário, e
+    /// respeitando as condições de `options`. Retorna quantos membros eram novos ou, com `ch`,
+    /// quantos foram adicionados ou mudaram de score.
+    pub async fn zadd(
+        &self,
+        db: usize,
+        key: String,
+        pairs: Vec<(f64, String)>,
+        options: ZAddOptions,
+    ) -> Result<i64, &'static str> {
+        match self.execute(db, Command::ZAdd { key, pairs, options }).await? {
+            Reply::Integer(count) => Ok(count),
+            _ => unreachable!("ZADD sem INCR sempre responde com um inteiro"),
+        }
+    }
+
+    /// `ZADD ... INCR`: soma `increment` ao score do membro como o `ZINCRBY`, mas respeitando as
+    /// condições de `options`. Retorna o novo score formatado, ou `None` se uma delas impediu.
+    pub async fn zadd_incr(
+        &self,
+        db: usize,
+        key: String,
+        member: String,
+        increment: f64,
+        options: ZAddOptions,
+    ) -> Result<Option<String>, &'static str> {
+        let options = ZAddOptions { incr: true, ..options };
+        let pairs = vec![(increment, member)];
+        match self.execute(db, Command::ZAdd { key, pairs, options }).await? {
+            Reply::Bulk(score) => Ok(Some(score)),
+            Reply::Nil => Ok(None),
+            _ => unreachable!("ZADD INCR responde com o novo score"),
+        }
+    }
+
+    /// Soma `increment` ao score do membro, criando o membro e a chave se necessário, e retorna
+    /// o novo score formatado.
+    pub async fn zincr_by(
+        &self,
+        db: usize,
+        key: String,
+        member: String,
+        increment: f64,
+    ) -> Result<String, &'static str> {
+        match self.execute(db, Command::ZIncrBy { key, member, increment }).await? {
+            Reply::Bulk(score) => Ok(score),
+            _ => unreachable!("ZINCRBY sempre responde com um bulk string"),
+        }
+    }
+
+    /// Retira até `count` membros de menor score (ou de maior, com `max`), na ordem em que
+    /// saem, com os seus scores. A chave é removida junto com o último membro.
+    pub async fn zpop(
+        &self,
+        db: usize,
+        key: String,
+        count: usize,
+        max: bool,
+    ) -> Result<Vec<(String, f64)>, &'static str> {
+        match self.execute(db, Command::ZPop { key, count, max }).await? {
+            Reply::Scored(popped) => Ok(popped),
+            _ => unreachable!("ZPOPMIN e ZPOPMAX respondem com os membros retirados"),
+        }
+    }
+
+    /// Remove membros do conjunto ordenado e retorna quantos existiam. A chave é removida junto
+    /// com o último membro.
+    pub async fn zrem(
+        &self,
+        db: usize,
+        key: String,
+        members: Vec<String>,
+    ) -> Result<i64, &'static str> {
+        match self.execute(db, Command::ZRem { key, members }).await? {
+            Reply::Integer(removed) => Ok(removed),
+            _ => unreachable!("ZREM sempre responde com um inteiro"),
+        }
+    }
+
+    /// Move `member` de `source` para `destination`, criando o destino se necessário. Retorna
+    /// se o membro estava na origem.
+    pub async fn smove(
+        &self,
+        db: usize,
+        source: String,
+        destination: String,
+        member: String,
+    ) -> Result<bool, &'static str> {
+        let reply = self.execute(db, Command::SMove { source, destination, member }).await?;
+        Ok(reply == Reply::Integer(1))
+    }
+
+    /// Deleta uma chave do store
+    pub async fn delete(&self, db: usize, key: &str) -> bool {
+        let cmd = Command::Delete { key: key.to_string() };
+        matches!(self.execute(db, cmd).await, Ok(Reply::Integer(1)))
+    }
+
+    /// Remove uma chave como o `delete`, mas sem liberar valores grandes na task de escrita, que
+    /// fica livre para as próximas escritas enquanto a memória é devolvida em background.
+    pub async fn unlink(&self, db: usize, key: &str) -> bool {
+        let cmd = Command::Unlink { key: key.to_string() };
+        matches!(self.execute(db, cmd).await, Ok(Reply::Integer(1)))
+    }
+
+    /// Soma `delta` ao inteiro armazenado na chave, criando-a com 0 se não existir.
+    /// A leitura e a escrita acontecem na task de escrita, então não há perda de atualizações.
+    pub async fn incr_by(&self, db: usize, key: String, delta: i64) -> Result<i64, &'static str> {
+        match self.execute(db, Command::IncrBy { key, delta }).await? {
+            Reply::Integer(value) => Ok(value),
+            _ => unreachable!("INCRBY sempre responde com um inteiro"),
+        }
+    }
+
+    /// Soma `increment` ao número de ponto flutuante armazenado na chave e retorna o novo valor formatado.
+    pub async fn incr_by_float(
+        &self,
+        db: usize,
+        key: String,
+        increment: f64,
+    ) -> Result<String, &'static str> {
+        match self.execute(db, Command::IncrByFloat { key, increment }).await? {
+            Reply::Bulk(value) => Ok(value),
+            _ => unreachable!("INCRBYFLOAT sempre responde com um bulk string"),
+        }
+    }
+
+    /// Concatena `value` ao final da string, criando a chave se necessário. Retorna o novo tamanho.
+    pub async fn append(&self, db: usize, key: String, value: String) -> Result<i64, &'static str> {
+        match self.execute(db, Command::Append { key, value }).await? {
+            Reply::Integer(len) => Ok(len),
+            _ => unreachable!("APPEND sempre responde com um inteiro"),
+        }
+    }
+
+    /// Sobrescreve a string a partir de `offset`, completando com bytes `\0` se ela for menor.
+    /// Retorna o novo tamanho.
+    pub async fn setrange(
+        &self,
+        db: usize,
+        key: String,
+        offset: usize,
+        value: String,
+    ) -> Result<i64, &'static str> {
+        if offset.saturating_add(value.len()) > self.max_string_len {
+            return Err("ERR string exceeds maximum allowed size (proto-max-bulk-len)");
+        }
+        match self.execute(db, Command::SetRange { key, offset, value }).await? {
+            Reply::Integer(len) => Ok(len),
+            _ => unreachable!("SETRANGE sempre responde com um inteiro"),
+        }
+    }
+
+    /// Define o bit na posição `offset` da string, completando-a com bytes `\0` se ela for
+    /// menor. Retorna o valor anterior do bit.
+    pub async fn setbit(
+        &self,
+        db: usize,
+        key: String,
+        offset: u64,
+        bit: bool,
+    ) -> Result<i64, &'static str> {
+        // O limite é o mesmo do `SETRANGE`, para que um único comando não aloque demais.
+        if offset / 8 >= self.max_string_len as u64 {
+            return Err("ERR bit offset is not an integer or out of range");
+        }
+        match self.execute(db, Command::SetBit { key, offset, bit }).await? {
+            Reply::Integer(previous) => Ok(previous),
+            _ => unreachable!("SETBIT sempre responde com um inteiro"),
+        }
+    }
+
+    /// Valor do bit na posição `offset` da string; 0 além do fim dela ou se a chave não existir.
+    pub async fn getbit(&self, db: usize, key: &str, offset: u64) -> Result<i64, &'static str> {
+        if offset / 8 >= self.max_string_len as u64 {
+            return Err("ERR bit offset is not an integer or out of range");
+        }
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+        read(&meta_lock, key);
+        let bytes = match self.lookup(db, &data_lock, &meta_lock, key) {
+            Some(v) => v.as_bytes().ok_or(WRONGTYPE)?,
+            None => return Ok(0),
+        };
+        let byte = bytes.get((offset / 8) as usize).copied().unwrap_or(0);
+        Ok((byte & (0x80 >> (offset % 8)) != 0) as i64)
+    }
+
+    /// Executa as operações de um `BITFIELD` em ordem, retornando um resultado por operação.
+    /// Só com `GET`, é uma leitura sob o lock de leitura, e uma chave inexistente não é criada.
+    pub async fn bitfield(
+        &self,
+        db: usize,
+        key: String,
+        ops: Vec<bitfield::Op>,
+    ) -> Result<Vec<Option<i64>>, &'static str> {
+        // O limite é o mesmo do `SETBIT`, para todas as operações.
+        let max_bits = self.max_string_len as u64 * 8;
+        if ops.iter().any(|op| op.field().offset.saturating_add(op.field().bits as u64) > max_bits)
+        {
+            return Err("ERR bit offset is not an integer or out of range");
+        }
+        if ops.iter().any(bitfield::Op::is_write) {
+            return match self.execute(db, Command::BitField { key, ops }).await? {
+                Reply::Fields(results) => Ok(results),
+                _ => unreachable!("BITFIELD sempre responde com um resultado por operação"),
+            };
+        }
+
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+        read(&meta_lock, &key);
+        let bytes = match self.lookup(db, &data_lock, &meta_lock, &key) {
+            Some(v) => v.as_bytes().ok_or(WRONGTYPE)?,
+            None => Cow::Borrowed(&[][..]),
+        };
+        Ok(ops.iter().map(|op| Some(op.field().get(&bytes))).collect())
+    }
+
+    /// Número de bits ligados na string, ou só entre `start` e `end` (inclusivos), contados em
+    /// bytes ou, com `in_bits`, em bits. Os índices seguem a semântica do `GETRANGE`.
+    pub async fn bitcount(
+        &self,
+        db: usize,
+        key: &str,
+        range: Option<(i64, i64)>,
+        in_bits: bool,
+    ) -> Result<i64, &'static str> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+        read(&meta_lock, key);
+        let bytes = match self.lookup(db, &data_lock, &meta_lock, key) {
+            Some(v) => v.as_bytes().ok_or(WRONGTYPE)?,
+            None => return Ok(0),
+        };
+        let (start, end) = range.unwrap_or((0, -1));
+        let Some((first, last)) = bit_range(bytes.len(), start, end, in_bits) else {
+            return Ok(0);
+        };
+        let (first_byte, last_byte) = ((first / 8) as usize, (last / 8) as usize);
+        let count: u64 = bytes[first_byte..=last_byte].iter().map(|b| b.count_ones() as u64).sum();
+        // Os bits das pontas que ficam fora da faixa são descontados.
+        let before = bytes[first_byte] & !(0xFF >> (first % 8));
+        let after = bytes[last_byte] & 0xFFu8.checked_shr((last % 8 + 1) as u32).unwrap_or(0);
+        Ok((count - before.count_ones() as u64 - after.count_ones() as u64) as i64)
+    }
+
+    /// Posição do primeiro bit igual a `bit` a partir de `start`, em bytes ou, com `in_bits`,
+    /// em bits, como o `BITPOS`. Procurando 0 sem `end`, a string é tratada como seguida de
+    /// zeros: se só houver bits ligados, a resposta é a posição logo depois do fim. Fora isso,
+    /// -1 quando não há o bit na faixa.
+    pub async fn bitpos(
+        &self,
+        db: usize,
+        key: &str,
+        bit: bool,
+        start: i64,
+        end: Option<i64>,
+        in_bits: bool,
+    ) -> Result<i64, &'static str> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+        read(&meta_lock, key);
+        let bytes = match self.lookup(db, &data_lock, &meta_lock, key) {
+            Some(v) => v.as_bytes().ok_or(WRONGTYPE)?,
+            // Uma chave inexistente é uma string vazia seguida de zeros.
+            None => return Ok(if bit { -1 } else { 0 }),
+        };
+        let Some((first, last)) = bit_range(bytes.len(), start, end.unwrap_or(-1), in_bits) else {
+            return Ok(-1);
+        };
+        let (first_byte, last_byte) = ((first / 8) as usize, (last / 8) as usize);
+        for (index, byte) in bytes.iter().enumerate().take(last_byte + 1).skip(first_byte) {
+            // Nas pontas, só os bits dentro da faixa são candidatos.
+            let mut mask = 0xFFu8;
+            if index == first_byte {
+                mask &= 0xFF >> (first % 8);
+            }
+            if index == last_byte {
+                mask &= !0xFFu8.checked_shr((last % 8 + 1) as u32).unwrap_or(0);
+            }
+            let candidates = if bit { *byte } else { !*byte } & mask;
+            if candidates != 0 {
+                return Ok((index * 8) as i64 + candidates.leading_zeros() as i64);
+            }
+        }
+        Ok(if !bit && end.is_none() { (bytes.len() * 8) as i64 } else { -1 })
+    }
+
+    /// Define várias chaves de uma só vez, de forma atômica para os leitores.
+    pub async fn mset(&self, db: usize, pairs: Vec<(String, String)>) {
+        // `MSET` não falha; o resultado só é aguardado para garantir a ordem das escritas.
+        let _ = self.execute(db, Command::MSet { pairs }).await;
+    }
+
+    /// Define várias chaves somente se nenhuma delas existir. Retorna `false` sem escrever nada
+    /// caso alguma já exista.
+    pub async fn msetnx(&self, db: usize, pairs: Vec<(String, String)>) -> bool {
+        matches!(
+            self.execute(db, Command::MSetNx { pairs }).await,
+            Ok(Reply::Integer(1))
+        )
+    }
+
+    /// Define o novo valor da chave e retorna o anterior, numa única operação na task de escrita.
+    pub async fn getset(
+        &self,
+        db: usize,
+        key: String,
+        value: String,
+    ) -> Result<Option<Vec<u8>>, &'static str> {
+        match self.execute(db, Command::GetSet { key, value }).await? {
+            Reply::Value(old) => Ok(Some(old)),
+            Reply::Nil => Ok(None),
+            _ => unreachable!("GETSET sempre responde com um bulk string ou nulo"),
+        }
+    }
+
+    /// Remove a chave e retorna o valor que ela tinha, numa única operação. Dois clientes nunca
+    /// recebem o mesmo valor.
+    pub async fn getdel(&self, db: usize, key: String) -> Result<Option<Vec<u8>>, &'static str> {
+        match self.execute(db, Command::GetDel { key }).await? {
+            Reply::Value(old) => Ok(Some(old)),
+            Reply::Nil => Ok(None),
+            _ => unreachable!("GETDEL sempre responde com um bulk string ou nulo"),
+        }
+    }
+
+    /// Retorna o valor da string e, na mesma operação, altera ou remove o seu TTL.
+    pub async fn getex(
+        &self,
+        db: usize,
+        key: String,
+        ttl: TtlUpdate,
+    ) -> Result<Option<Vec<u8>>, &'static str> {
+        match self.execute(db, Command::GetEx { key, ttl }).await? {
+            Reply::Value(value) => Ok(Some(value)),
+            Reply::Nil => Ok(None),
+            _ => unreachable!("GETEX sempre responde com um bulk string ou nulo"),
+        }
+    }
+
+    /// Define o instante de expiração de uma chave. Retorna `false` se ela não existir ou se a
+    /// condição (`NX`, `XX`, `GT`, `LT`) impediu a alteração.
+    pub async fn expire(
+        &self,
+        db: usize,
+        key: String,
+        at: SystemTime,
+        condition: ExpireCondition,
+    ) -> bool {
+        matches!(
+            self.execute(db, Command::Expire { key, at, condition }).await,
+            Ok(Reply::Integer(1))
+        )
+    }
+
+    /// Remove o TTL da chave. Retorna `false` se ela não existir ou não tiver TTL.
+    pub async fn persist(&self, db: usize, key: String) -> bool {
+        matches!(
+            self.execute(db, Command::Persist { key }).await,
+            Ok(Reply::Integer(1))
+        )
+    }
+
+    /// Move a chave para o banco `target`, levando junto o seu TTL. Retorna `false` se ela não
+    /// existir ou se já houver uma chave com o mesmo nome no destino.
+    pub async fn move_to(&self, db: usize, key: String, target: usize) -> bool {
+        matches!(
+            self.execute(db, Command::Move { key, db: target }).await,
+            Ok(Reply::Integer(1))
+        )
+    }
+
+    /// Troca o conteúdo de dois bancos numa única operação.
+    pub async fn swapdb(&self, first: usize, second: usize) {
+        // `SWAPDB` não falha; o resultado só é aguardado para garantir a ordem das escritas.
+        let _ = self.execute(first, Command::SwapDb { first, second }).await;
+    }
+
+    /// Renomeia uma chave, levando junto o seu TTL. Com `nx`, retorna `Ok(false)` sem alterar
+    /// nada se o destino já existir.
+    pub async fn rename(
+        &self,
+        db: usize,
+        key: String,
+        new_key: String,
+        nx: bool,
+    ) -> Result<bool, &'static str> {
+        let reply = self.execute(db, Command::Rename { key, new_key, nx }).await?;
+        Ok(reply != Reply::Integer(0))
+    }
+
+    /// `SORT ... STORE`: grava o resultado ordenado como uma lista em `destination`, na mesma
+    /// operação que lê a origem. Retorna o número de elementos gravados.
+    pub async fn sort_store(
+        &self,
+        db: usize,
+        key: String,
+        destination: String,
+        options: SortOptions,
+    ) -> Result<i64, &'static str> {
+        match self.execute(db, Command::SortStore { key, destination, options }).await? {
+            Reply::Integer(len) => Ok(len),
+            _ => unreachable!("SORT ... STORE sempre responde com um inteiro"),
+        }
+    }
+
+    /// Grava em `destination` o resultado de `SINTER`, `SUNION` ou `SDIFF` sobre as chaves, na
+    /// mesma operação que as lê. Retorna o número de membros gravados.
+    pub async fn set_store(
+        &self,
+        db: usize,
+        op: SetOp,
+        destination: String,
+        keys: Vec<String>,
+    ) -> Result<i64, &'static str> {
+        match self.execute(db, Command::SetStore { op, destination, keys }).await? {
+            Reply::Integer(len) => Ok(len),
+            _ => unreachable!("SINTERSTORE, SUNIONSTORE e SDIFFSTORE respondem com um inteiro"),
+        }
+    }
+
+    /// Grava em `destination` a união ou a interseção dos conjuntos das chaves, como o
+    /// `ZUNIONSTORE` e o `ZINTERSTORE`, na mesma operação que as lê. `weights` tem um peso por
+    /// chave. Retorna o número de membros gravados.
+    pub async fn zset_store(
+        &self,
+        db: usize,
+        op: SetOp,
+        destination: String,
+        keys: Vec<String>,
+        weights: Vec<f64>,
+        aggregate: Aggregate,
+    ) -> Result<i64, &'static str> {
+        let command = Command::ZSetStore { op, destination, keys, weights, aggregate };
+        match self.execute(db, command).await? {
+            Reply::Integer(len) => Ok(len),
+            _ => unreachable!("ZUNIONSTORE e ZINTERSTORE respondem com um inteiro"),
+        }
+    }
+
+    /// Grava em `destination` o resultado do `BITOP` sobre as strings das chaves, na mesma
+    /// operação que as lê. Retorna o tamanho do resultado, que é o da maior origem.
+    pub async fn bitop(
+        &self,
+        db: usize,
+        op: BitOp,
+        destination: String,
+        keys: Vec<String>,
+    ) -> Result<i64, &'static str> {
+        match self.execute(db, Command::BitOp { op, destination, keys }).await? {
+            Reply::Integer(len) => Ok(len),
+            _ => unreachable!("BITOP sempre responde com um inteiro"),
+        }
+    }
+
+    /// Registra os elementos no HyperLogLog da chave, criando-o se ela não existir. Retorna 1 se
+    /// a estimativa pode ter mudado ou a chave foi criada, e 0 caso contrário.
+    pub async fn pfadd(
+        &self,
+        db: usize,
+        key: String,
+        elements: Vec<String>,
+    ) -> Result<i64, &'static str> {
+        match self.execute(db, Command::PfAdd { key, elements }).await? {
+            Reply::Integer(changed) => Ok(changed),
+            _ => unreachable!("PFADD sempre responde com um inteiro"),
+        }
+    }
+
+    /// Cardinalidade estimada da união dos HyperLogLogs das chaves, combinados na hora sem
+    /// gravar nada. Chaves inexistentes contam como vazias.
+    pub async fn pfcount(&self, db: usize, keys: &[String]) -> Result<i64, &'static str> {
+        let data_lock = self.databases[db].data.read().await;
+        let meta_lock = self.databases[db].metadata.read().await;
+        let mut sources = Vec::with_capacity(keys.len());
+        for key in keys {
+            read(&meta_lock, key);
+            let Some(v) = self.lookup(db, &data_lock, &meta_lock, key) else {
+                continue;
+            };
+            let bytes = v.as_bytes().ok_or(WRONGTYPE)?;
+            if !hyperloglog::is_valid(&bytes) {
+                return Err(hyperloglog::INVALID);
+            }
+            sources.push(bytes);
+        }
+        // Com uma chave só, o cache do cabeçalho vale quando está em dia; como esta é uma
+        // leitura, a estimativa recalculada não é gravada nele.
+        let count = match &sources[..] {
+            [] => 0,
+            [hll] => hyperloglog::cardinality(hll),
+            _ => hyperloglog::count(&hyperloglog::merge(sources.iter().map(|bytes| &bytes[..]))),
+        };
+        Ok(count as i64)
+    }
+
+    /// Grava em `destination` a união dos HyperLogLogs das chaves com o do próprio destino, na
+    /// mesma operação que as lê. O TTL do destino é mantido.
+    pub async fn pfmerge(
+        &self,
+        db: usize,
+        destination: String,
+        keys: Vec<String>,
+    ) -> Result<(), &'static str> {
+        self.execute(db, Command::PfMerge { destination, keys }).await?;
+        Ok(())
+    }
+
+    /// Recria uma chave a partir do payload de um `DUMP`, já validado pelo chamador. Com
+    /// `expiry`, a chave expira no instante informado.
+    pub async fn restore(
+        &self,
+        db: usize,
+        key: String,
+        value: Value,
+        expiry: Option<SystemTime>,
+        replace: bool,
+    ) -> Result<(), &'static str> {
+        self.execute(db, Command::Restore { key, value, expiry, replace }).await?;
+        Ok(())
+    }
+
+    /// Remove as chaves cujo prazo já passou. Só percorre as entradas vencidas do índice de
+    /// expiração, então o custo é proporcional ao número de chaves expiradas, não ao tamanho
+    /// do banco.
+    pub async fn clean_expired(&self) {
+        for database in self.databases.iter() {
+            let now = SystemTime::now();
+            let is_due = |expiries: &ExpiryIndex| {
+                expiries.peek().is_some_and(|Reverse((at, _))| *at <= now)
+            };
+            // Consulta rápida, sem os locks dos dados, para não travar bancos sem nada vencido.
+            if !is_due(&database.expiries()) {
+                continue;
+            }
+
+            let mut data_lock = database.data.write().await;
+            let mut meta_lock = database.metadata.write().await;
+            let mut expiries = database.expiries();
+            while is_due(&expiries) {
+                let Some(Reverse((_, key))) = expiries.pop() else {
+                    break;
+                };
+                // A entrada pode estar desatualizada: a chave pode ter ganhado outro prazo,
+                // perdido o TTL ou sido apagada depois de entrar no índice.
+                if is_expired(&meta_lock, &key, now) {
+                    data_lock.remove(&key);
+                    meta_lock.remove(&key);
+                    continue;
+                }
+                // Os campos que ainda não venceram precisam de uma nova entrada no índice.
+                expire_fields(&mut data_lock, &mut meta_lock, &key, now);
+                let next = meta_lock.get(&key).and_then(KeyMetadata::next_deadline);
+                if let Some(at) = next.filter(|at| *at > now) {
+                    expiries.push(Reverse((at, key)));
+                }
+            }
+        }
+    }
+}
+
+/// Remove do hash os campos cujo TTL já passou, e a chave inteira se não sobrar nenhum.
+fn expire_fields(
+    data: &mut ScanMap<Value>,
+    meta: &mut HashMap<String, KeyMetadata>,
+    key: &str,
+    now: SystemTime,
+) {
+    let Some(metadata) = meta.get_mut(key) else {
+        return;
+    };
+    let Some(Value::Hash(hash)) = data.get_mut(key) else {
+        return;
+    };
+    metadata.field_expiries.retain(|field, at| {
+        let due = *at <= now;
+        if due {
+            hash.remove(field);
+        }
+        !due
+    });
+    if hash.is_empty() {
+        data.remove(key);
+        meta.remove(key);
+    }
+}
+
+/// Descarta os TTLs de campos que deixaram de existir, seja porque foram removidos ou porque a
+/// chave passou a guardar outro tipo.
+fn prune_field_expiries(
+    data: &HashMap<String, Value>,
+    meta: &mut HashMap<String, KeyMetadata>,
+    key: &str,
+) {
+    let Some(metadata) = meta.get_mut(key).filter(|m| !m.field_expiries.is_empty()) else {
+        return;
+    };
+    match data.get(key) {
+        Some(Value::Hash(hash)) => {
+            metadata.field_expiries.retain(|field, _| hash.contains_key(field))
+        }
+        _ => metadata.field_expiries.clear(),
+    }
+}
+
+/// Campos de um `HEXPIRE` ou `HPERSIST` cujo código de resposta passa em `changed`, ou `None`
+/// se nenhum passar.
+fn changed_fields(
+    fields: Vec<String>,
+    reply: &Reply,
+    changed: impl Fn(i64) -> bool,
+) -> Option<Vec<String>> {
+    let Reply::Integers(statuses) = reply else {
+        return None;
+    };
+    let fields: Vec<String> = fields
+        .into_iter()
+        .zip(statuses)
+        .filter(|(_, status)| changed(**status))
+        .map(|(field, _)| field)
+        .collect();
+    (!fields.is_empty()).then_some(fields)
+}
+
+/// Posição que um índice do Redis (negativos contam a partir do fim) ocupa numa lista com
+/// `len` elementos, ou `None` se ele cair fora dela.
+fn list_index(len: usize, index: i64) -> Option<usize> {
+    let index = if index < 0 { (len as i64).checked_add(index)? } else { index };
+    usize::try_from(index).ok().filter(|index| *index < len)
+}
+
+/// Trecho `start..=stop` de uma lista com `len` elementos, com a semântica de índices do
+/// `LRANGE`: negativos contam a partir do fim e valores fora da lista são ajustados. `None` se
+/// o trecho ficar vazio.
+fn list_range(len: usize, start: i64, stop: i64) -> Option<RangeInclusive<usize>> {
+    let len = len as i64;
+    let start = if start < 0 { len.saturating_add(start).max(0) } else { start };
+    let stop = if stop < 0 { len.saturating_add(stop) } else { stop.min(len - 1) };
+    (start <= stop && start < len).then_some(start as usize..=stop as usize)
+}
+
+/// Valor atual de um campo do hash, para comandos que o alteram. `None` se o campo ou a chave
+/// não existirem.
+fn hash_field<'a>(
+    data: &'a HashMap<String, Value>,
+    key: &str,
+    field: &str,
+) -> Result<Option<&'a str>, &'static str> {
+    match data.get(key) {
+        Some(Value::Hash(hash)) => Ok(hash.get(field).map(String::as_str)),
+        Some(_) => Err(WRONGTYPE),
+        None => Ok(None),
+    }
+}
+
+/// Hash guardado na chave, criado vazio se ela não existir. Só deve ser chamado depois de
+/// verificado o tipo da chave, quando o comando já não pode mais falhar.
+fn hash_mut<'a>(data: &'a mut ScanMap<Value>, key: &str) -> &'a mut ScanMap<String> {
+    if !data.contains_key(key) {
+        data.insert(key.to_string(), Value::Hash(ScanMap::default()));
+    }
+    match data.get_mut(key) {
+        Some(Value::Hash(hash)) => hash,
+        _ => unreachable!("o tipo da chave já foi verificado"),
+    }
+}
+
+/// Define o instante de expiração da chave; um prazo que já passou a remove na hora.
+fn set_expiry(
+    data: &mut ScanMap<Value>,
+    meta: &mut HashMap<String, KeyMetadata>,
+    key: String,
+    at: SystemTime,
+) {
+    if at <= SystemTime::now() {
+        data.remove(&key);
+        meta.remove(&key);
+    } else {
+        written(meta, &key).expiry = Some(at);
+    }
+}
+
+/// Metadados de uma chave que acabou de ser escrita, criados se ela for nova, com o acesso já
+/// registrado.
+fn written<'a>(meta: &'a mut HashMap<String, KeyMetadata>, key: &str) -> &'a mut KeyMetadata {
+    if !meta.contains_key(key) {
+        meta.insert(key.to_string(), KeyMetadata::default());
+    }
+    let entry = meta.get_mut(key).expect("entrada criada acima");
+    entry.touch();
+    entry
+}
+
+/// Registra uma leitura da chave. Só precisa do lock de leitura dos metadados.
+fn read(meta: &HashMap<String, KeyMetadata>, key: &str) {
+    if let Some(m) = meta.get(key) {
+        m.touch();
+    }
+}
+
+/// Ordena os elementos de uma lista ou conjunto e aplica o `LIMIT`. Sem `ALPHA`, todos os
+/// elementos precisam ser números.
+fn sort_elements(value: Option<&Value>, options: SortOptions) -> Result<Vec<String>, &'static str> {
+    let mut elements: Vec<String> = match value {
+        Some(Value::List(list)) => list.iter().cloned().collect(),
+        Some(Value::Set(set)) => set.iter().cloned().collect(),
+        Some(Value::ZSet(zset)) => zset.iter().map(|(member, _)| member.to_string()).collect(),
+        Some(_) => return Err(WRONGTYPE),
+        None => Vec::new(),
+    };
+
+    if options.alpha {
+        elements.sort();
+    } else {
+        let mut scored = elements
+            .into_iter()
+            .map(|e| match e.trim().parse::<f64>() {
+                Ok(score) if !score.is_nan() => Ok((score, e)),
+                _ => Err("ERR One or more scores can't be converted into double"),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // Empates são desfeitos pelo texto, para que conjuntos tenham uma ordem determinística.
+        scored.sort_by(|(a, ea), (b, eb)| a.total_cmp(b).then_with(|| ea.cmp(eb)));
+        elements = scored.into_iter().map(|(_, e)| e).collect();
+    }
+    if options.desc {
+        elements.reverse();
+    }
+
+    if let Some((offset, count)) = options.limit {
+        let start = (offset.max(0) as usize).min(elements.len());
+        let end = if count < 0 {
+            elements.len()
+        } else {
+            start.saturating_add(count as usize).min(elements.len())
+        };
+        elements.truncate(end);
+        elements.drain(..start);
+    }
+    Ok(elements)
+}
+
+/// Membros comuns a todos os conjuntos, produzidos sob demanda: quem só precisa de alguns pode
+/// parar antes de percorrer tudo. Só o menor conjunto é percorrido; os demais são consultados.
+fn intersection<'a>(sets: &[Option<&'a ScanSet>]) -> impl Iterator<Item = &'a String> {
+    // Um conjunto ausente esvazia a interseção.
+    let mut sets = sets.iter().copied().collect::<Option<Vec<_>>>().unwrap_or_default();
+    sets.sort_by_key(|set| set.len());
+    let smallest = (!sets.is_empty()).then(|| sets.remove(0));
+    smallest
+        .into_iter()
+        .flatten()
+        .filter(move |member| sets.iter().all(|set| set.contains(*member)))
+}
+
+/// Converte os índices `start` e `end` (inclusivos) de `BITCOUNT` e `BITPOS` nas posições do
+/// primeiro e do último bit da faixa, numa string de `len` bytes. Os índices são em bytes ou,
+/// com `in_bits`, em bits; negativos contam a partir do fim e os demais são ajustados aos
+/// limites da string. `None` se a faixa for vazia.
+fn bit_range(len: usize, start: i64, end: i64, in_bits: bool) -> Option<(u64, u64)> {
+    let total = if in_bits { len as i64 * 8 } else { len as i64 };
+    let start = if start < 0 { total.saturating_add(start).max(0) } else { start };
+    let end = if end < 0 { total.saturating_add(end).max(0) } else { end.min(total - 1) };
+    if total == 0 || start > end {
+        return None;
+    }
+    let (start, end) = (start as u64, end as u64);
+    Some(if in_bits { (start, end) } else { (start * 8, end * 8 + 7) })
+}
+
+/// Sorteio do `HRANDFIELD`, `SRANDMEMBER` e `ZRANDMEMBER`, em ordem aleatória: com `count`
+/// positivo, até `count` itens distintos; com negativo, exatamente `|count|`, podendo repetir.
+/// `len` é o número de itens. Como `count` vem do cliente e pode ser enorme, nenhum vetor é
+/// reservado com esse tamanho: o positivo é limitado a `len` e o resultado com repetição
+/// cresce conforme é preenchido. Com repetição e menos sorteios que itens, só as posições
+/// sorteadas são guardadas, sem juntar os itens num vetor.
+fn random_sample<T: Copy>(items: impl Iterator<Item = T>, len: usize, count: i64) -> Vec<T> {
+    let mut rng = rand::thread_rng();
+    if count >= 0 {
+        let count = usize::try_from(count).unwrap_or(usize::MAX).min(len);
+        let mut picked = items.choose_multiple(&mut rng, count);
+        // `choose_multiple` não garante uma ordem aleatória entre os escolhidos.
+        picked.shuffle(&mut rng);
+        return picked;
+    }
+    let mut picked = Vec::new();
+    if len == 0 {
+        return picked;
+    }
+    let repeats = count.unsigned_abs();
+    if repeats < len as u64 {
+        // As posições sorteadas, em ordem, são colhidas numa só passada pelos itens.
+        let mut positions: Vec<usize> = (0..repeats).map(|_| rng.gen_range(0..len)).collect();
+        positions.sort_unstable();
+        let mut positions = positions.into_iter().peekable();
+        for (index, item) in items.enumerate() {
+            while positions.next_if_eq(&index).is_some() {
+                picked.push(item);
+            }
+            if positions.peek().is_none() {
+                break;
+            }
+        }
+        picked.shuffle(&mut rng);
+        return picked;
+    }
+    // Com mais sorteios que itens, cada um precisa de acesso aleatório: só os itens, em geral
+    // referências, vão para o vetor.
+    let items: Vec<T> = items.collect();
+    for _ in 0..repeats {
+        picked.push(items[rng.gen_range(0..items.len())]);
+    }
+    picked
+}
+
+/// Aplica a ordem e o `LIMIT offset count` de `ZRANGEBYSCORE` e afins a uma faixa de um
+/// conjunto ordenado, copiando só os membros que entram na resposta. Offset negativo não
+/// retorna nada e count negativo é sem limite.
+fn page<'a>(
+    range: impl DoubleEndedIterator<Item = (&'a str, f64)>,
+    rev: bool,
+    limit: Option<(i64, i64)>,
+) -> Vec<(String, f64)> {
+    let (offset, count) = limit.unwrap_or((0, -1));
+    let Ok(offset) = usize::try_from(offset) else {
+        return Vec::new();
+    };
+    let count = usize::try_from(count).unwrap_or(usize::MAX);
+    let ordered: Box<dyn Iterator<Item = (&str, f64)>> =
+        if rev { Box::new(range.rev()) } else { Box::new(range) };
+    ordered
+        .skip(offset)
+        .take(count)
+        .map(|(member, score)| (member.to_string(), score))
+        .collect()
+}
+
+/// Combina os conjuntos conforme `op`; `None` é um conjunto vazio.
+fn combine_sets<'a>(op: SetOp, sets: &[Option<&'a ScanSet>]) -> HashSet<&'a String> {
+    match op {
+        SetOp::Inter => intersection(sets).collect(),
+        SetOp::Union => sets.iter().flatten().flat_map(|set| set.iter()).collect(),
+        SetOp::Diff => {
+            let Some((Some(first), others)) = sets.split_first() else {
+                return HashSet::new();
+            };
+            first
+                .iter()
+                .filter(|member| !others.iter().flatten().any(|set| set.contains(*member)))
+                .collect()
+        }
+    }
+}
+
+/// Hash visto por uma leitura: os campos com o TTL vencido, que a task de escrita ainda não
+/// removeu, ficam de fora sem que o hash seja copiado.
+#[derive(Clone, Copy)]
+struct LiveHash<'a> {
+    hash: &'a ScanMap<String>,
+    /// Prazos dos campos, só quando algum deles já venceu.
+    expiries: Option<&'a HashMap<String, SystemTime>>,
+    now: SystemTime,
+    len: usize,
+}
+
+impl<'a> LiveHash<'a> {
+    fn new(
+        hash: &'a ScanMap<String>,
+        meta: Option<&'a KeyMetadata>,
+        now: SystemTime,
+    ) -> Self {
+        let expiries = meta.map(|m| &m.field_expiries);
+        // Os prazos são só de campos que existem no hash.
+        let due = expiries.map_or(0, |e| e.values().filter(|at| **at <= now).count());
+        let expiries = expiries.filter(|_| due > 0);
+        Self { hash, expiries, now, len: hash.len() - due }
+    }
+
+    fn is_live(&self, field: &str) -> bool {
+        self.expiries.and_then(|e| e.get(field)).is_none_or(|at| *at > self.now)
+    }
+
+    fn len(self) -> usize {
+        self.len
+    }
+
+    fn get(self, field: &str) -> Option<&'a String> {
+        self.hash.get(field).filter(|_| self.is_live(field))
+    }
+
+    fn contains_key(self, field: &str) -> bool {
+        self.get(field).is_some()
+    }
+
+    fn iter(self) -> impl Iterator<Item = (&'a String, &'a String)> + Clone {
+        self.hash.iter().filter(move |(field, _)| self.is_live(field))
+    }
+
+    /// Um lote do `HSCAN`, retomado do cursor pelo índice do hash.
+    fn scan(self, cursor: u64, count: usize) -> (u64, Vec<(&'a str, &'a String)>) {
+        let (next, mut batch) = self.hash.scan(cursor, count);
+        batch.retain(|(field, _)| self.is_live(field));
+        (next, batch)
+    }
+
+    fn keys(self) -> impl Iterator<Item = &'a String> {
+        self.iter().map(|(field, _)| field)
+    }
+
+    fn values(self) -> impl Iterator<Item = &'a String> {
+        self.iter().map(|(_, value)| value)
+    }
+}
+
+/// Origem do `ZUNIONSTORE` e do `ZINTERSTORE`: um conjunto ordenado ou um conjunto comum, cujos
+/// membros valem 1.
+enum Scored<'a> {
+    Set(&'a ScanSet),
+    ZSet(&'a ZSet),
+}
+
+impl Scored<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Scored::Set(set) => set.len(),
+            Scored::ZSet(zset) => zset.len(),
+        }
+    }
+
+    fn score(&self, member: &str) -> Option<f64> {
+        match self {
+            Scored::Set(set) => set.contains(member).then_some(1.0),
+            Scored::ZSet(zset) => zset.score(member),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&str, f64)> + '_> {
+        match self {
+            Scored::Set(set) => Box::new(set.iter().map(|member| (member.as_str(), 1.0))),
+            Scored::ZSet(zset) => Box::new(zset.iter()),
+        }
+    }
+}
+
+/// Combina as origens conforme `op`, com cada score multiplicado pelo peso da sua origem;
+/// `None` é um conjunto vazio. Como no Redis, `inf * 0` vale 0.
+fn combine_zsets(op: SetOp, mut inputs: Vec<(Option<Scored>, f64)>, aggregate: Aggregate) -> ZSet {
+    let weighted = |score: f64, weight: f64| {
+        let score = score * weight;
+        if score.is_nan() {
+            0.0
+        } else {
+            score
+        }
+    };
+    let mut result = ZSet::default();
+    match op {
+        SetOp::Union => {
+            let mut scores: HashMap<&str, f64> = HashMap::new();
+            for (input, weight) in &inputs {
+                for (member, score) in input.iter().flat_map(Scored::iter) {
+                    let score = weighted(score, *weight);
+                    scores
+                        .entry(member)
+                        .and_modify(|current| *current = aggregate.apply(*current, score))
+                        .or_insert(score);
+                }
+            }
+            for (member, score) in scores {
+                result.insert(member.to_string(), score);
+            }
+        }
+        SetOp::Inter => {
+            // Um conjunto ausente esvazia a interseção. Como em `intersection`, só o menor
+            // conjunto é percorrido.
+            if inputs.iter().any(|(input, _)| input.is_none()) {
+                return result;
+            }
+            inputs.sort_by_key(|(input, _)| input.as_ref().map_or(0, Scored::len));
+            let Some(((Some(smallest), weight), others)) = inputs.split_first() else {
+                return result;
+            };
+            for (member, score) in smallest.iter() {
+                let total = others.iter().try_fold(weighted(score, *weight), |total, other| {
+                    let (Some(input), weight) = other else { return None };
+                    let score = weighted(input.score(member)?, *weight);
+                    Some(aggregate.apply(total, score))
+                });
+                if let Some(total) = total {
+                    result.insert(member.to_string(), total);
+                }
+            }
+        }
+        SetOp::Diff => unreachable!("não há ZDIFFSTORE"),
+    }
+    result
+}
+
+/// Custo aproximado de liberar o valor: o número de alocações, que cresce com os elementos das
+/// coleções. Uma string é uma única alocação, qualquer que seja o tamanho.
+fn free_effort(value: &Value) -> usize {
+    match value {
+        Value::String(_) | Value::Int(_) | Value::Bytes(_) => 1,
+        Value::List(list) => list.len(),
+        Value::Set(set) => set.len(),
+        Value::Hash(hash) => hash.len(),
+        Value::ZSet(zset) => zset.len(),
+    }
+}
+
+/// Indica se a chave tem um TTL que já passou, mesmo que a limpeza ainda não a tenha removido.
+fn is_expired(meta: &HashMap<String, KeyMetadata>, key: &str, now: SystemTime) -> bool {
+    meta.get(key)
+        .and_then(|m| m.expiry)
+        .is_some_and(|expiry| now >= expiry)
+}
+
+/// Se a chave deve ser lida como ausente, porque o TTL passou ou porque é um hash com todos os
+/// campos vencidos, e quantos campos do hash já venceram.
+fn expiry_state(
+    value: &Value,
+    meta: &HashMap<String, KeyMetadata>,
+    key: &str,
+    now: SystemTime,
+) -> (bool, usize) {
+    let due_fields = meta.get(key).map_or(0, |m| {
+        m.field_expiries.values().filter(|at| **at <= now).count()
+    });
+    let expired = is_expired(meta, key, now)
+        || matches!(value, Value::Hash(hash) if due_fields > 0 && due_fields >= hash.len());
+    (expired, due_fields)
+}
+
+/// Formata um número de ponto flutuante como o Redis: sem zeros à direita nem notação
+/// científica (`3` em vez de `3.0000`). O `Display` do Rust já gera a menor representação
+/// que volta ao mesmo `f64`.
+pub fn format_float(value: f64) -> String {
+    format!("{}", value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Store com a task de escrita rodando, como no `main`.
+    fn store() -> Store {
+        let (store, background_task) = Store::new(16, 512 * 1024 * 1024);
+        tokio::spawn(background_task);
+        store
+    }
+
+    #[tokio::test]
+    async fn msetnx_with_a_repeated_key() {
+        let store = store();
+        let pairs = |items: &[(&str, &str)]| {
+            items.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<_>>()
+        };
+        let store = &store;
+        let value = |key| async move {
+            match store.get(0, key).await {
+                Some(Value::String(value)) => Some(value),
+                _ => None,
+            }
+        };
+        // A chave repetida não bloqueia a própria escrita e fica com o último valor.
+        assert!(store.msetnx(0, pairs(&[("a", "1"), ("b", "2"), ("a", "3")])).await);
+        assert_eq!(value("a").await.as_deref(), Some("3"));
+        assert_eq!(value("b").await.as_deref(), Some("2"));
+        // Com uma das chaves já existente, nada é escrito, nem a repetida.
+        assert!(!store.msetnx(0, pairs(&[("c", "1"), ("a", "4"), ("c", "2")])).await);
+        assert!(value("c").await.is_none());
+        assert_eq!(value("a").await.as_deref(), Some("3"));
+    }
+
+    #[tokio::test]
+    async fn relative_set_expiry_is_published_as_a_deadline() {
+        let store = store();
+        let mut published = store.cmd_tx.subscribe();
+        let before = SystemTime::now();
+        let expiry = SetExpiry::After(Duration::from_secs(1));
+        store.set(0, "k".into(), Value::String("v".into()), expiry, SetCondition::Always).await;
+        let stored = store.databases[0].metadata.read().await["k"].expiry.unwrap();
+        assert!(stored >= before + Duration::from_secs(1));
+        match published.recv().await.unwrap() {
+            (0, Command::Set { expiry: SetExpiry::At(at), .. }) => assert_eq!(at, stored),
+            other => panic!("comando inesperado: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_keys_read_as_missing() {
+        let store = store();
+        let expiry = SetExpiry::After(Duration::from_millis(50));
+        store.set(0, "k".into(), Value::String("v".into()), expiry, SetCondition::Always).await;
+        assert!(store.get(0, "k").await.is_some());
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(store.get(0, "k").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn expired_keys_are_deleted_once() {
+        // Sem a task de escrita rodando, os pedidos de remoção ficam na fila.
+        let (store, background_task) = Store::new(16, 512 * 1024 * 1024);
+        let past = SystemTime::now() - Duration::from_secs(1);
+        let metadata = KeyMetadata { expiry: Some(past), ..Default::default() };
+        let database = &store.databases[0];
+        database.data.write().await.insert("k".into(), Value::String("v".into()));
+        database.metadata.write().await.insert("k".into(), metadata);
+        for _ in 0..3 {
+            assert!(store.get(0, "k").await.is_none());
+        }
+        assert_eq!(database.pending_deletes().len(), 1);
+
+        tokio::spawn(background_task);
+        let value = Value::String("v".into());
+        store.set(0, "other".into(), value, SetExpiry::Clear, SetCondition::Always).await;
+        assert!(database.pending_deletes().is_empty());
+        assert!(!database.data.read().await.contains_key("k"));
+    }
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    async fn set_string(store: &Store, key: &str) {
+        let value = Value::String("v".into());
+        store.set(0, key.into(), value, SetExpiry::Clear, SetCondition::Always).await;
+    }
+
+    /// Dá às chaves um TTL curto e espera ele vencer, sem que ninguém as leia antes: a remoção
+    /// fica a cargo da próxima leitura.
+    async fn expire_soon(store: &Store, keys: &[&str]) {
+        let soon = SystemTime::now() + Duration::from_millis(30);
+        for key in keys {
+            assert!(store.expire(0, key.to_string(), soon, ExpireCondition::default()).await);
+        }
+        tokio::time::sleep(Duration::from_millis(40)).await;
+    }
+
+    /// Tamanho da resposta de um comando simples sobre a chave, para a tabela de
+    /// `small_reads_on_wrong_types_and_expired_keys`.
+    async fn small_read(store: &Store, command: &str, key: &str) -> Result<usize, &'static str> {
+        match command {
+            "HKEYS" => store.hkeys(0, key).await.map(|fields| fields.len()),
+            "HVALS" => store.hvals(0, key).await.map(|values| values.len()),
+            "HLEN" => store.hlen(0, key).await.map(|len| len as usize),
+            "HMGET" => {
+                let values = store.hmget(0, key, &strings(&["f", "x"])).await;
+                values.map(|values| values.into_iter().flatten().count())
+            }
+            "LLEN" => store.llen(0, key).await.map(|len| len as usize),
+            "LREM" => {
+                let removed = store.lrem(0, key.into(), 0, "x".into()).await;
+                removed.map(|removed| removed as usize)
+            }
+            "SCARD" => store.scard(0, key).await.map(|len| len as usize),
+            _ => unreachable!("comando sem caso: {command}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn small_reads_on_wrong_types_and_expired_keys() {
+        let store = store();
+        store.hset(0, "hash".into(), vec![("f".into(), "v".into())]).await.unwrap();
+        store.push(0, "list".into(), strings(&["v"]), ListEnd::Right, false).await.unwrap();
+        store.sadd(0, "set".into(), strings(&["v"])).await.unwrap();
+        set_string(&store, "string").await;
+        // Comando, chave do tipo que ele lê e o tamanho esperado da resposta sobre ela.
+        let cases = [
+            ("HKEYS", "hash", 1),
+            ("HVALS", "hash", 1),
+            ("HLEN", "hash", 1),
+            ("HMGET", "hash", 1),
+            ("LLEN", "list", 1),
+            ("LREM", "list", 0),
+            ("SCARD", "set", 1),
+        ];
+        for (command, key, len) in cases {
+            assert_eq!(small_read(&store, command, key).await, Ok(len), "{command}");
+            assert_eq!(small_read(&store, command, "string").await, Err(WRONGTYPE), "{command}");
+            assert_eq!(small_read(&store, command, "missing").await, Ok(0), "{command}");
+        }
+
+        // Chaves vencidas, de qualquer tipo, são lidas como ausentes.
+        expire_soon(&store, &["hash", "list", "set", "string"]).await;
+        for (command, key, _) in cases {
+            assert_eq!(small_read(&store, command, key).await, Ok(0), "{command}");
+            assert_eq!(small_read(&store, command, "string").await, Ok(0), "{command}");
+        }
+    }
+
+    #[tokio::test]
+    async fn hkeys_and_hvals_on_thousands_of_fields() {
+        let store = store();
+        let pairs: Vec<_> = (0..5000).map(|i| (format!("f{i}"), format!("v{i}"))).collect();
+        store.hset(0, "h".into(), pairs.clone()).await.unwrap();
+        let keys = store.hkeys(0, "h").await.unwrap();
+        let values = store.hvals(0, "h").await.unwrap();
+        // As duas listas saem na mesma ordem, campo a campo.
+        let mut zipped: Vec<_> = keys.into_iter().zip(values).collect();
+        zipped.sort_by_key(|(field, _)| field[1..].parse::<usize>().unwrap());
+        assert_eq!(zipped, pairs);
+    }
+
+    #[tokio::test]
+    async fn hlen_counts_fields() {
+        let store = store();
+        let pairs = (0..10).map(|i| (format!("f{i}"), "v".to_string())).collect();
+        store.hset(0, "h".into(), pairs).await.unwrap();
+        store.hset(0, "h".into(), vec![("f0".into(), "w".into())]).await.unwrap();
+        assert_eq!(store.hlen(0, "h").await, Ok(10));
+        store.hdel(0, "h".into(), vec!["f1".into(), "f2".into()]).await.unwrap();
+        assert_eq!(store.hlen(0, "h").await, Ok(8));
+    }
+
+    #[tokio::test]
+    async fn hmget_interleaves_present_and_absent_fields() {
+        let store = store();
+        let pairs = vec![("a".to_string(), "1".to_string()), ("c".to_string(), "3".to_string())];
+        store.hset(0, "h".into(), pairs).await.unwrap();
+        let fields = strings(&["x", "a", "b", "c", "a", "d"]);
+        let some = |value: &str| Some(value.to_string());
+        let expected = vec![None, some("1"), None, some("3"), some("1"), None];
+        assert_eq!(store.hmget(0, "h", &fields).await, Ok(expected));
+        assert_eq!(store.hmget(0, "missing", &fields).await, Ok(vec![None; 6]));
+    }
+
+    /// Elementos da lista, da esquerda para a direita; vazia se a chave não existir.
+    async fn list(store: &Store, key: &str) -> Vec<String> {
+        match store.get(0, key).await {
+            Some(Value::List(list)) => list.into_iter().collect(),
+            None => Vec::new(),
+            Some(other) => panic!("{key} não é uma lista: {other:?}"),
+        }
+    }
+
+    async fn rpush(store: &Store, key: &str, elements: &[&str]) {
+        store.push(0, key.into(), strings(elements), ListEnd::Right, false).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn lrem_removal_order() {
+        let store = store();
+        let elements = ["x", "a", "x", "b", "x", "c", "x"];
+        // Cada caso parte da mesma lista: contagem, removidos e o que sobra.
+        let cases: &[(i64, i64, &[&str])] = &[
+            (2, 2, &["a", "b", "x", "c", "x"]),
+            (-2, 2, &["x", "a", "x", "b", "c"]),
+            (0, 4, &["a", "b", "c"]),
+            (10, 4, &["a", "b", "c"]),
+            (-10, 4, &["a", "b", "c"]),
+            (1, 1, &["a", "x", "b", "x", "c", "x"]),
+            (-1, 1, &["x", "a", "x", "b", "x", "c"]),
+        ];
+        for &(count, removed, rest) in cases {
+            rpush(&store, "l", &elements).await;
+            let reply = store.lrem(0, "l".into(), count, "x".into()).await;
+            assert_eq!(reply, Ok(removed), "LREM {count}");
+            assert_eq!(list(&store, "l").await, rest, "LREM {count}");
+            store.delete(0, "l").await;
+        }
+
+        // Sem sobrar nada, a chave deixa de existir.
+        rpush(&store, "l", &["x", "x"]).await;
+        assert_eq!(store.lrem(0, "l".into(), 0, "x".into()).await, Ok(2));
+        assert!(store.get(0, "l").await.is_none());
+        assert_eq!(store.lrem(0, "l".into(), 0, "x".into()).await, Ok(0));
+    }
+
+    /// Dá tempo para as tasks já criadas chegarem ao bloqueio, na ordem em que foram criadas.
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    /// `BLMOVE source destination LEFT RIGHT` numa task à parte.
+    fn spawn_blmove(
+        store: &Store,
+        destination: &str,
+        timeout: Option<Duration>,
+    ) -> tokio::task::JoinHandle<Result<Option<String>, &'static str>> {
+        let (store, destination) = (store.clone(), destination.to_string());
+        tokio::spawn(async move {
+            let (from, to) = (ListEnd::Left, ListEnd::Right);
+            store.blocking_move(0, "source".into(), destination, from, to, timeout).await
+        })
+    }
+
+    #[tokio::test]
+    async fn blmove_serves_waiters_in_arrival_order() {
+        let store = store();
+        let first = spawn_blmove(&store, "first", None);
+        settle().await;
+        let second = spawn_blmove(&store, "second", None);
+        settle().await;
+
+        rpush(&store, "source", &["a"]).await;
+        assert_eq!(first.await.unwrap(), Ok(Some("a".to_string())));
+        settle().await;
+        assert!(!second.is_finished(), "o segundo só é atendido pelo próximo elemento");
+        rpush(&store, "source", &["b"]).await;
+        assert_eq!(second.await.unwrap(), Ok(Some("b".to_string())));
+
+        assert_eq!(list(&store, "first").await, strings(&["a"]));
+        assert_eq!(list(&store, "second").await, strings(&["b"]));
+        assert!(list(&store, "source").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn blmove_times_out_with_null() {
+        let store = store();
+        let started = tokio::time::Instant::now();
+        let moved = spawn_blmove(&store, "destination", Some(Duration::from_millis(50)));
+        assert_eq!(moved.await.unwrap(), Ok(None));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        // O cliente saiu da fila: um elemento novo fica na origem.
+        rpush(&store, "source", &["a"]).await;
+        assert_eq!(list(&store, "source").await, strings(&["a"]));
+    }
+
+    #[tokio::test]
+    async fn blmove_to_a_wrong_type_leaves_the_element_for_the_next_waiter() {
+        let store = store();
+        set_string(&store, "string").await;
+        let wrong = spawn_blmove(&store, "string", None);
+        settle().await;
+        let next = spawn_blmove(&store, "list", None);
+        settle().await;
+
+        rpush(&store, "source", &["a"]).await;
+        assert_eq!(wrong.await.unwrap(), Err(WRONGTYPE));
+        assert_eq!(next.await.unwrap(), Ok(Some("a".to_string())));
+        assert_eq!(list(&store, "list").await, strings(&["a"]));
+        assert!(matches!(store.get(0, "string").await, Some(Value::String(_))));
+    }
+
+    #[tokio::test]
+    async fn sscan_returns_every_member_despite_concurrent_writes() {
+        let store = store();
+        let members: Vec<String> = (0..100_000).map(|i| format!("m{i}")).collect();
+        store.sadd(0, "s".into(), members.clone()).await.unwrap();
+
+        // Um escritor concorrente adiciona e remove outros membros durante toda a iteração.
+        let writer = tokio::spawn({
+            let store = store.clone();
+            async move {
+                for round in 0.. {
+                    let extra: Vec<String> = (0..100).map(|i| format!("x{round}-{i}")).collect();
+                    store.sadd(0, "s".into(), extra.clone()).await.unwrap();
+                    if round % 2 == 0 {
+                        store.srem(0, "s".into(), extra).await.unwrap();
+                    }
+                    tokio::task::yield_now().await;
+                }
+            }
+        });
+
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        loop {
+            let (next, batch) = store.sscan(0, "s", cursor, 5000, None).await.unwrap();
+            assert!(batch.len() <= 5000 + 10, "lote de {} membros", batch.len());
+            seen.extend(batch);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+            tokio::task::yield_now().await;
+        }
+        writer.abort();
+
+        let missing: Vec<&String> = members.iter().filter(|m| !seen.contains(*m)).collect();
+        assert!(missing.is_empty(), "{} membros não foram retornados", missing.len());
+        assert!(seen.iter().any(|member| member.starts_with('x')));
+    }
+
+    #[tokio::test]
+    async fn scan_returns_every_key_despite_concurrent_writes() {
+        let store = store();
+        let keys: Vec<String> = (0..100_000).map(|i| format!("k{i}")).collect();
+        store.mset(0, keys.iter().map(|key| (key.clone(), "v".to_string())).collect()).await;
+
+        // Um escritor concorrente cria e apaga outras chaves durante toda a iteração.
+        let writer = tokio::spawn({
+            let store = store.clone();
+            async move {
+                for round in 0.. {
+                    let extra: Vec<String> = (0..100).map(|i| format!("x{round}-{i}")).collect();
+                    let pairs = extra.iter().map(|key| (key.clone(), "v".into())).collect();
+                    store.mset(0, pairs).await;
+                    if round % 2 == 0 {
+                        for key in &extra {
+                            store.delete(0, key).await;
+                        }
+                    }
+                    tokio::task::yield_now().await;
+                }
+            }
+        });
+
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        loop {
+            let (next, batch) = store.scan(0, cursor, 5000, None, None).await;
+            assert!(batch.len() <= 5000 + 10, "lote de {} chaves", batch.len());
+            seen.extend(batch);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+            tokio::task::yield_now().await;
+        }
+        writer.abort();
+
+        let missing: Vec<&String> = keys.iter().filter(|key| !seen.contains(*key)).collect();
+        assert!(missing.is_empty(), "{} chaves não foram retornadas", missing.len());
+        assert!(seen.iter().any(|key| key.starts_with('x')));
+    }
+
+    #[tokio::test]
+    async fn hscan_returns_every_field_despite_concurrent_writes() {
+        let store = store();
+        let fields: Vec<String> = (0..100_000).map(|i| format!("f{i}")).collect();
+        let pairs = fields.iter().map(|field| (field.clone(), "v".to_string())).collect();
+        store.hset(0, "h".into(), pairs).await.unwrap();
+
+        // Um escritor concorrente cria e apaga outros campos durante toda a iteração.
+        let writer = tokio::spawn({
+            let store = store.clone();
+            async move {
+                for round in 0.. {
+                    let extra: Vec<String> = (0..100).map(|i| format!("x{round}-{i}")).collect();
+                    let pairs = extra.iter().map(|field| (field.clone(), "v".into())).collect();
+                    store.hset(0, "h".into(), pairs).await.unwrap();
+                    if round % 2 == 0 {
+                        store.hdel(0, "h".into(), extra).await.unwrap();
+                    }
+                    tokio::task::yield_now().await;
+                }
+            }
+        });
+
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        loop {
+            let (next, batch) = store.hscan(0, "h", cursor, 5000, None).await.unwrap();
+            assert!(batch.len() <= 5000 + 10, "lote de {} campos", batch.len());
+            seen.extend(batch.into_iter().map(|(field, _)| field));
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+            tokio::task::yield_now().await;
+        }
+        writer.abort();
+
+        let missing: Vec<&String> = fields.iter().filter(|f| !seen.contains(*f)).collect();
+        assert!(missing.is_empty(), "{} campos não foram retornados", missing.len());
+        assert!(seen.iter().any(|field| field.starts_with('x')));
+    }
+
+    #[tokio::test]
+    async fn bzpopmin_serves_the_first_waiter() {
+        let store = store();
+        let keys = strings(&["z"]);
+        let spawn_bzpopmin = |timeout| {
+            let (store, keys) = (store.clone(), keys.clone());
+            tokio::spawn(async move { store.blocking_zpop(0, &keys, false, Some(timeout)).await })
+        };
+        let first = spawn_bzpopmin(Duration::from_secs(5));
+        settle().await;
+        let second = spawn_bzpopmin(Duration::from_millis(100));
+        settle().await;
+
+        let pairs = vec![(1.0, "member".to_string())];
+        store.zadd(0, "z".into(), pairs, ZAddOptions::default()).await.unwrap();
+        let popped = first.await.unwrap();
+        assert_eq!(popped, Ok(Some(("z".to_string(), "member".to_string(), 1.0))));
+        assert_eq!(second.await.unwrap(), Ok(None));
+    }
+
+    /// Opções do `ZADD` a partir dos nomes, como `"xx gt ch"`.
+    fn zadd_options(flags: &str) -> ZAddOptions {
+        let mut options = ZAddOptions::default();
+        for flag in flags.split_whitespace() {
+            match flag {
+                "nx" => options.nx = true,
+                "xx" => options.xx = true,
+                "gt" => options.gt = true,
+                "lt" => options.lt = true,
+                "ch" => options.ch = true,
+                "incr" => options.incr = true,
+                _ => unreachable!("opção desconhecida: {flag}"),
+            }
+        }
+        options
+    }
+
+    #[tokio::test]
+    async fn zadd_flag_matrix() {
+        // Cada caso parte de `z` = {a: 5}, e o `ZADD` recebe a: `score` e o membro novo b: 1.
+        // Resposta esperada, score final de `a` e se `b` foi adicionado.
+        let cases = [
+            ("", 7.0, 1, 7.0, true),
+            ("ch", 7.0, 2, 7.0, true),
+            ("ch", 5.0, 1, 5.0, true),
+            ("nx", 7.0, 1, 5.0, true),
+            ("nx ch", 7.0, 1, 5.0, true),
+            ("xx", 7.0, 0, 7.0, false),
+            ("xx ch", 7.0, 1, 7.0, false),
+            ("gt", 7.0, 1, 7.0, true),
+            ("gt ch", 7.0, 2, 7.0, true),
+            ("gt ch", 3.0, 1, 5.0, true),
+            ("gt ch", 5.0, 1, 5.0, true),
+            ("lt ch", 3.0, 2, 3.0, true),
+            ("lt ch", 7.0, 1, 5.0, true),
+            ("xx gt ch", 7.0, 1, 7.0, false),
+            ("xx gt ch", 3.0, 0, 5.0, false),
+            ("xx lt ch", 3.0, 1, 3.0, false),
+        ];
+        for (flags, score, reply, final_score, added) in cases {
+            let store = store();
+            let initial = vec![(5.0, "a".to_string())];
+            store.zadd(0, "z".into(), initial, ZAddOptions::default()).await.unwrap();
+            let pairs = vec![(score, "a".to_string()), (1.0, "b".to_string())];
+            let result = store.zadd(0, "z".into(), pairs, zadd_options(flags)).await;
+            assert_eq!(result, Ok(reply), "ZADD {flags} a {score}");
+            assert_eq!(store.zscore(0, "z", "a").await, Ok(Some(final_score)), "{flags}");
+            let b = store.zscore(0, "z", "b").await.unwrap();
+            assert_eq!(b.is_some(), added, "{flags}");
+        }
+    }
+
+    #[tokio::test]
+    async fn zadd_gt_and_lt_add_new_members() {
+        let store = store();
+        for flags in ["gt", "lt", "gt ch", "lt ch"] {
+            let pairs = vec![(1.0, flags.to_string())];
+            assert_eq!(store.zadd(0, "z".into(), pairs, zadd_options(flags)).await, Ok(1));
+        }
+        assert_eq!(store.zcard(0, "z").await, Ok(4));
+    }
+
+    #[tokio::test]
+    async fn zadd_incr_with_conditions() {
+        let store = store();
+        let incr = |member: &str, increment, flags| {
+            store.zadd_incr(0, "z".into(), member.into(), increment, zadd_options(flags))
+        };
+        assert_eq!(incr("a", 5.0, "").await, Ok(Some("5".to_string())));
+        assert_eq!(incr("a", 1.5, "").await, Ok(Some("6.5".to_string())));
+        // Condições que impedem o `INCR` respondem Nil e não alteram nada.
+        assert_eq!(incr("a", 1.0, "nx").await, Ok(None));
+        assert_eq!(incr("b", 1.0, "xx").await, Ok(None));
+        assert_eq!(incr("a", -1.0, "gt").await, Ok(None));
+        assert_eq!(incr("a", 1.0, "lt").await, Ok(None));
+        assert_eq!(incr("a", 0.0, "gt").await, Ok(None));
+        assert_eq!(incr("a", 1.0, "gt").await, Ok(Some("7.5".to_string())));
+        assert_eq!(incr("a", -2.0, "xx lt").await, Ok(Some("5.5".to_string())));
+        assert_eq!(incr("b", 2.0, "nx").await, Ok(Some("2".to_string())));
+        assert_eq!(store.zscore(0, "z", "a").await, Ok(Some(5.5)));
+        assert_eq!(store.zscore(0, "z", "b").await, Ok(Some(2.0)));
+    }
+
+    #[tokio::test]
+    async fn zadd_incr_nan_leaves_the_set_unchanged() {
+        let store = store();
+        let incr = |increment| {
+            store.zadd_incr(0, "z".into(), "a".into(), increment, ZAddOptions::default())
+        };
+        assert_eq!(incr(f64::INFINITY).await, Ok(Some("inf".to_string())));
+        let nan = incr(f64::NEG_INFINITY).await;
+        assert_eq!(nan, Err("ERR resulting score is not a number (NaN)"));
+        assert_eq!(store.zscore(0, "z", "a").await, Ok(Some(f64::INFINITY)));
+        assert_eq!(store.zcard(0, "z").await, Ok(1));
+    }
+
+    /// Grava `bytes` na chave, bit a bit, como um cliente faria com `SETBIT`.
+    async fn set_bytes(store: &Store, key: &str, bytes: &[u8]) {
+        for (index, byte) in bytes.iter().enumerate() {
+            for bit in 0..8 {
+                let offset = (index * 8 + bit) as u64;
+                store.setbit(0, key.into(), offset, byte & (0x80 >> bit) != 0).await.unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn bit_range_limits() {
+        // Em bytes, a faixa vai do primeiro bit de `start` ao último de `end`.
+        assert_eq!(bit_range(3, 0, -1, false), Some((0, 23)));
+        assert_eq!(bit_range(3, 1, 1, false), Some((8, 15)));
+        assert_eq!(bit_range(3, -2, -1, false), Some((8, 23)));
+        assert_eq!(bit_range(3, -100, 100, false), Some((0, 23)));
+        assert_eq!(bit_range(3, 2, 1, false), None);
+        assert_eq!(bit_range(3, 3, 10, false), None);
+        assert_eq!(bit_range(0, 0, -1, false), None);
+        // Em bits, dentro de um mesmo byte e a partir do fim.
+        assert_eq!(bit_range(3, 2, 5, true), Some((2, 5)));
+        assert_eq!(bit_range(3, -3, -1, true), Some((21, 23)));
+        assert_eq!(bit_range(3, 5, 1000, true), Some((5, 23)));
+        assert_eq!(bit_range(3, -1000, -23, true), Some((0, 1)));
+        assert_eq!(bit_range(3, 24, 30, true), None);
+        assert_eq!(bit_range(1, i64::MIN, i64::MAX, true), Some((0, 7)));
+    }
+
+    #[tokio::test]
+    async fn bitcount_and_bitpos_match_a_bit_by_bit_reference() {
+        let store = store();
+        for bytes in [&[0xff, 0xf0, 0x0f][..], &[0x00, 0x81, 0x00, 0x10], &[0xff, 0xff]] {
+            set_bytes(&store, "k", bytes).await;
+            let bits: Vec<bool> =
+                (0..bytes.len() * 8).map(|i| bytes[i / 8] & (0x80 >> (i % 8)) != 0).collect();
+            for in_bits in [false, true] {
+                let unit = if in_bits { 1 } else { 8 };
+                let total = (bits.len() / unit) as i64;
+                for start in -total - 2..total + 2 {
+                    for end in -total - 2..total + 2 {
+                        let (from, to) = (
+                            if start < 0 { (total + start).max(0) } else { start },
+                            if end < 0 { (total + end).max(0) } else { end.min(total - 1) },
+                        );
+                        let positions = (from * unit as i64..(to + 1) * unit as i64)
+                            .map(|position| position as usize);
+                        let count = positions.clone().filter(|position| bits[*position]).count();
+                        let bitcount = store.bitcount(0, "k", Some((start, end)), in_bits).await;
+                        assert_eq!(bitcount, Ok(count as i64), "{bytes:?} {start} {end} {in_bits}");
+                        for bit in [false, true] {
+                            let position = positions.clone().find(|p| bits[*p] == bit);
+                            let bitpos = store.bitpos(0, "k", bit, start, Some(end), in_bits).await;
+                            let expected = position.map_or(-1, |position| position as i64);
+                            assert_eq!(bitpos, Ok(expected), "{bytes:?} {bit} {start} {end}");
+                        }
+                    }
+                }
+            }
+            store.delete(0, "k").await;
+        }
+    }
+
+    #[tokio::test]
+    async fn bitpos_of_zero_on_an_all_ones_string() {
+        let store = store();
+        set_bytes(&store, "k", &[0xff, 0xff, 0xff]).await;
+        // Sem `end`, a string é tratada como seguida de zeros.
+        assert_eq!(store.bitpos(0, "k", false, 0, None, false).await, Ok(24));
+        assert_eq!(store.bitpos(0, "k", false, 2, None, false).await, Ok(24));
+        assert_eq!(store.bitpos(0, "k", false, 5, None, true).await, Ok(24));
+        // Com `end`, a busca fica na faixa.
+        assert_eq!(store.bitpos(0, "k", false, 0, Some(-1), false).await, Ok(-1));
+        assert_eq!(store.bitpos(0, "k", false, 0, Some(23), true).await, Ok(-1));
+        // Uma chave inexistente é uma string vazia seguida de zeros.
+        assert_eq!(store.bitpos(0, "missing", false, 0, None, false).await, Ok(0));
+        assert_eq!(store.bitpos(0, "missing", true, 0, None, false).await, Ok(-1));
+    }
+
+    #[tokio::test]
+    async fn pfcount_of_a_hundred_thousand_elements() {
+        let store = store();
+        for batch in 0..100 {
+            let elements = (0..1000).map(|i| format!("member:{batch}:{i}")).collect();
+            store.pfadd(0, "hll".into(), elements).await.unwrap();
+        }
+        let count = store.pfcount(0, &strings(&["hll"])).await.unwrap();
+        // Três vezes o erro padrão de 0.81%.
+        assert!((count - 100_000).abs() < 2430, "estimativa {count}");
+
+        store.pfadd(0, "other".into(), strings(&["member:0:0", "extra"])).await.unwrap();
+        let union = store.pfcount(0, &strings(&["hll", "other", "missing"])).await.unwrap();
+        store.pfmerge(0, "merged".into(), strings(&["hll", "other"])).await.unwrap();
+        assert_eq!(store.pfcount(0, &strings(&["merged"])).await, Ok(union));
+    }
+
+    #[tokio::test]
+    async fn pfadd_replies_and_errors() {
+        let store = store();
+        assert_eq!(store.pfadd(0, "hll".into(), Vec::new()).await, Ok(1));
+        assert_eq!(store.pfadd(0, "hll".into(), Vec::new()).await, Ok(0));
+        assert_eq!(store.pfadd(0, "hll".into(), strings(&["a"])).await, Ok(1));
+        assert_eq!(store.pfadd(0, "hll".into(), strings(&["a"])).await, Ok(0));
+
+        store.sadd(0, "set".into(), strings(&["a"])).await.unwrap();
+        let plain = SetExpiry::Clear;
+        store.set(0, "s".into(), Value::String("x".into()), plain, SetCondition::Always).await;
+        assert_eq!(store.pfadd(0, "set".into(), strings(&["a"])).await, Err(WRONGTYPE));
+        assert_eq!(store.pfadd(0, "s".into(), strings(&["a"])).await, Err(hyperloglog::INVALID));
+        assert_eq!(store.pfcount(0, &strings(&["hll", "s"])).await, Err(hyperloglog::INVALID));
+        let merged = store.pfmerge(0, "hll".into(), strings(&["set"])).await;
+        assert_eq!(merged, Err(WRONGTYPE));
+    }
+
+    #[tokio::test]
+    async fn spop_with_huge_count_pops_everything() {
+        let store = store();
+        store.sadd(0, "s".into(), strings(&["a", "b", "c"])).await.unwrap();
+        let mut popped = store.spop(0, "s".into(), i64::MAX as usize).await.unwrap();
+        popped.sort();
+        assert_eq!(popped, strings(&["a", "b", "c"]));
+        // A task de escrita continua de pé.
+        assert_eq!(store.sadd(0, "s".into(), strings(&["d"])).await, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn hrandfield_with_huge_counts() {
+        let store = store();
+        let pairs = vec![("a".to_string(), "1".to_string()), ("b".to_string(), "2".to_string())];
+        store.hset(0, "h".into(), pairs.clone()).await.unwrap();
+        let mut picked = store.hrandfield(0, "h", i64::MAX).await.unwrap();
+        picked.sort();
+        assert_eq!(picked, pairs);
+        let repeated = store.hrandfield(0, "h", -5).await.unwrap();
+        assert_eq!(repeated.len(), 5);
+        assert!(repeated.iter().all(|pair| pairs.contains(pair)));
+        let huge = store.hrandfield(0, "h", -4_000_000_000_000_000_000).await;
+        assert_eq!(huge, Err("ERR count exceeds maximum allowed size (proto-max-bulk-len)"));
+    }
+
+    #[tokio::test]
+    async fn srandmember_with_huge_counts() {
+        let store = store();
+        store.sadd(0, "s".into(), strings(&["a", "b", "c"])).await.unwrap();
+        let mut picked = store.srandmember(0, "s", i64::MAX).await.unwrap();
+        picked.sort();
+        assert_eq!(picked, strings(&["a", "b", "c"]));
+        let repeated = store.srandmember(0, "s", -7).await.unwrap();
+        assert_eq!(repeated.len(), 7);
+        assert_eq!(store.scard(0, "s").await, Ok(3));
+        let huge = store.srandmember(0, "s", -4_000_000_000_000_000_000).await;
+        assert_eq!(huge, Err("ERR count exceeds maximum allowed size (proto-max-bulk-len)"));
+    }
+
+    #[tokio::test]
+    async fn zrandmember_with_huge_counts() {
+        let store = store();
+        let pairs = vec![(1.0, "a".to_string()), (2.0, "b".to_string())];
+        store.zadd(0, "z".into(), pairs, ZAddOptions::default()).await.unwrap();
+        let mut picked = store.zrandmember(0, "z", i64::MAX).await.unwrap();
+        picked.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(picked, vec![("a".to_string(), 1.0), ("b".to_string(), 2.0)]);
+        assert_eq!(store.zrandmember(0, "z", -3).await.unwrap().len(), 3);
+        // Um `count` negativo enorme é recusado antes de qualquer alocação.
+        let huge = store.zrandmember(0, "z", -4_000_000_000_000_000_000).await;
+        assert_eq!(huge, Err("ERR count exceeds maximum allowed size (proto-max-bulk-len)"));
+    }
+
+    /// Grava na chave o byte `0xff`, que não é UTF-8 válido.
+    async fn set_invalid_utf8(store: &Store, key: &str) {
+        for offset in 0..8 {
+            store.setbit(0, key.into(), offset, true).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn string_replies_keep_binary_values() {
+        let store = store();
+        set_invalid_utf8(&store, "k").await;
+        assert_eq!(store.getex(0, "k".into(), TtlUpdate::Persist).await, Ok(Some(vec![0xff])));
+        assert_eq!(store.getset(0, "k".into(), "a".into()).await, Ok(Some(vec![0xff])));
+        set_invalid_utf8(&store, "k").await;
+        let value = Value::String("a".into());
+        let previous =
+            store.set_get(0, "k".into(), value, SetExpiry::Clear, SetCondition::Always).await;
+        assert_eq!(previous, Ok(Some(vec![0xff])));
+        set_invalid_utf8(&store, "k").await;
+        assert_eq!(store.getdel(0, "k".into()).await, Ok(Some(vec![0xff])));
+    }
+
+    #[tokio::test]
+    async fn hash_reads_skip_expired_fields() {
+        let store = store();
+        let pairs = [("a", "1"), ("b", "2"), ("c", "3")];
+        let pairs = pairs.iter().map(|(f, v)| (f.to_string(), v.to_string())).collect();
+        store.hset(0, "h".into(), pairs).await.unwrap();
+        let soon = SystemTime::now() + Duration::from_millis(30);
+        let fields = strings(&["a", "c"]);
+        store.hexpire(0, "h".into(), fields, soon, ExpireCondition::default()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(40)).await;
+
+        // Leituras seguidas, antes e depois de a task de escrita remover os campos.
+        for _ in 0..2 {
+            assert_eq!(store.hlen(0, "h").await, Ok(1));
+            assert_eq!(store.hkeys(0, "h").await, Ok(strings(&["b"])));
+            assert_eq!(store.hvals(0, "h").await, Ok(strings(&["2"])));
+            let values = store.hmget(0, "h", &strings(&["a", "b", "c"])).await;
+            assert_eq!(values, Ok(vec![None, Some("2".to_string()), None]));
+            assert_eq!(store.hexists(0, "h", "a").await, Ok(false));
+            assert_eq!(store.hrandfield(0, "h", 10).await.unwrap().len(), 1);
+            let pairs = vec![("b".to_string(), "2".to_string())];
+            assert_eq!(store.hscan(0, "h", 0, 10, None).await, Ok((0, pairs)));
+        }
+    }
+
+    #[tokio::test]
+    async fn keyspace_reads_skip_hashes_with_every_field_expired() {
+        // Sem a task de escrita, nada é removido: só a checagem de leitura esconde o hash.
+        let (store, _background_task) = Store::new(16, 512 * 1024 * 1024);
+        let past = SystemTime::now() - Duration::from_secs(1);
+        let hash = HashMap::from([("f".to_string(), "v".to_string())]);
+        let field_expiries = HashMap::from([("f".to_string(), past)]);
+        let metadata = KeyMetadata { field_expiries, ..Default::default() };
+        store.databases[0]
+            .replace(
+                HashMap::from([
+                    ("h".to_string(), Value::Hash(hash.into())),
+                    ("k".to_string(), Value::String("v".into())),
+                ]),
+                HashMap::from([("h".to_string(), metadata)]),
+            )
+            .await;
+
+        assert_eq!(store.dbsize(0).await, 1);
+        assert_eq!(store.keys(0).await, strings(&["k"]));
+        assert_eq!(store.scan(0, 0, 10, None, None).await, (0, strings(&["k"])));
+        for _ in 0..10 {
+            assert_eq!(store.random_key(0).await.as_deref(), Some("k"));
+        }
+    }
+
+    #[tokio::test]
+    async fn dbsize_checks_due_index_entries_against_the_metadata() {
+        let store = store();
+        for key in ["persisted", "expired", "extended", "plain"] {
+            set_string(&store, key).await;
+        }
+        let soon = SystemTime::now() + Duration::from_millis(30);
+        for key in ["persisted", "expired", "extended"] {
+            store.expire(0, key.into(), soon, ExpireCondition::default()).await;
+        }
+        // As entradas antigas de `persisted` e `extended` continuam no índice.
+        store.persist(0, "persisted".into()).await;
+        let later = SystemTime::now() + Duration::from_secs(3600);
+        store.expire(0, "extended".into(), later, ExpireCondition::default()).await;
+        tokio::time::sleep(Duration::from_millis(40)).await;
+
+        assert_eq!(store.dbsize(0).await, 3);
+        assert_eq!(store.dbsize(0).await, 3);
+        // As entradas contadas voltaram ao índice, e a limpeza ainda acha a chave vencida.
+        store.clean_expired().await;
+        assert!(!store.databases[0].data.read().await.contains_key("expired"));
+        assert_eq!(store.databases[0].data.read().await.len(), 3);
+    }
+
+    #[test]
+    fn random_sample_caps_and_repeats() {
+        let items = [1, 2, 3];
+        let mut distinct = random_sample(items.iter(), items.len(), i64::MAX);
+        distinct.sort();
+        assert_eq!(distinct, vec![&1, &2, &3]);
+        assert_eq!(random_sample(items.iter(), items.len(), 2).len(), 2);
+        assert_eq!(random_sample(items.iter(), items.len(), 0), Vec::<&i32>::new());
+        let repeated = random_sample(items.iter(), items.len(), -10);
+        assert_eq!(repeated.len(), 10);
+        assert!(repeated.iter().all(|item| items.contains(item)));
+        assert!(random_sample(std::iter::empty::<i32>(), 0, -10).is_empty());
+    }
+
+    #[test]
+    fn random_sample_with_few_repeats_covers_every_position() {
+        let items: Vec<usize> = (0..100).collect();
+        let mut seen: HashSet<&usize> = HashSet::new();
+        for _ in 0..2000 {
+            let picked = random_sample(items.iter(), items.len(), -3);
+            assert_eq!(picked.len(), 3);
+            seen.extend(picked);
+        }
+        // Em 6000 sorteios, todas as 100 posições, inclusive as pontas, aparecem.
+        assert_eq!(seen.len(), 100);
+        assert_eq!(random_sample(items.iter(), items.len(), -1).len(), 1);
+    }
+}
@@ -0,0 +1,329 @@
+//! Conjunto ordenado dos comandos `Z*`: cada membro tem um score, guardado num mapa para as
+//! consultas por membro e num índice ordenado por `(score, membro)` para as consultas por
+//! posição ou faixa, que assim não precisam reordenar o conjunto.
+//!
+//! Só o mapa é persistido; o índice é reconstruído na carga.
+
+use crate::scan::ScanMap;
+use serde::{Deserialize, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+
+/// Score de um membro, com a ordem total do `f64`. Nunca é NaN, e o zero negativo é guardado
+/// como positivo, para que os dois ocupem a mesma posição no índice, como no Redis.
+#[derive(Debug, Clone, Copy)]
+pub struct Score(pub f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Limite de uma faixa de scores, como `5`, `(5` (exclusivo) ou `-inf`.
+#[derive(Debug, Clone, Copy)]
+pub struct ScoreBound {
+    pub value: f64,
+    pub exclusive: bool,
+}
+
+/// Limite de uma faixa de membros do `ZRANGEBYLEX`: `-` e `+` são os extremos, e um membro
+/// vem como `[membro` (inclusivo) ou `(membro` (exclusivo).
+#[derive(Debug, Clone)]
+pub enum LexBound {
+    Min,
+    Max,
+    Included(String),
+    Excluded(String),
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(try_from = "HashMap<String, String>")]
+pub struct ZSet {
+    scores: ScanMap<f64>,
+    index: BTreeSet<(Score, String)>,
+}
+
+impl ZSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Define o score do membro, reposicionando-o no índice, e retorna o score anterior.
+    pub fn insert(&mut self, member: String, score: f64) -> Option<f64> {
+        debug_assert!(!score.is_nan(), "scores NaN são rejeitados antes de chegar aqui");
+        // Somar zero transforma -0.0 em 0.0 e não altera nenhum outro valor.
+        let score = score + 0.0;
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            self.index.remove(&(Score(previous), member.clone()));
+        }
+        self.index.insert((Score(score), member));
+        previous
+    }
+
+    /// Posição do membro na ordem crescente de score, contando a partir de 0. O `BTreeSet` não
+    /// guarda o tamanho das subárvores, então a posição vem da contagem dos membros de um dos
+    /// lados do membro no índice. Os dois lados são percorridos juntos, e a contagem para no
+    /// primeiro que acabar: O(min(posição, tamanho - posição)), sem ordenar nada. No pior caso,
+    /// um membro no meio do índice, continua O(n).
+    pub fn rank(&self, member: &str) -> Option<usize> {
+        let key = (Score(self.score(member)?), member.to_string());
+        let mut before = self.index.range(..&key);
+        let mut after = self.index.range(&key..);
+        for steps in 0.. {
+            if before.next().is_none() {
+                return Some(steps);
+            }
+            if after.next().is_none() {
+                return Some(self.len() - steps);
+            }
+        }
+        unreachable!("um dos lados acaba antes")
+    }
+
+    /// Remove o membro e retorna o seu score.
+    pub fn remove(&mut self, member: &str) -> Option<f64> {
+        let (member, score) = self.scores.remove_entry(member)?;
+        self.index.remove(&(Score(score), member));
+        Some(score)
+    }
+
+    /// Remove e retorna o membro de menor score, ou o de maior com `max`.
+    pub fn pop(&mut self, max: bool) -> Option<(String, f64)> {
+        let (score, member) = if max { self.index.pop_last() } else { self.index.pop_first() }?;
+        self.scores.remove(&member);
+        Some((member, score.0))
+    }
+
+    /// Membros com score entre `min` e `max`, na ordem do índice. Só a faixa é percorrida.
+    pub fn range_by_score(
+        &self,
+        min: ScoreBound,
+        max: ScoreBound,
+    ) -> impl DoubleEndedIterator<Item = (&str, f64)> {
+        // Os limites viram chaves do índice: o membro vazio é o menor de cada score, e um limite
+        // exclusivo passa para o próximo `f64`, já que nenhum score cabe entre os dois.
+        let (min_value, max_value) = (min.value + 0.0, max.value + 0.0);
+        let start = if min.exclusive { min_value.next_up() } else { min_value };
+        let end = match max.exclusive {
+            true => Bound::Excluded((Score(max_value), String::new())),
+            false if max_value == f64::INFINITY => Bound::Unbounded,
+            false => Bound::Excluded((Score(max_value.next_up()), String::new())),
+        };
+        // O `BTreeSet::range` não aceita um início depois do fim. Nada vem depois de `inf`,
+        // então `(inf` também é uma faixa vazia.
+        let empty = (min.exclusive && min_value == f64::INFINITY)
+            || matches!(&end, Bound::Excluded((end, _)) if Score(start) >= *end);
+        let range = (!empty).then(|| {
+            self.index.range((Bound::Included((Score(start), String::new())), end))
+        });
+        range.into_iter().flatten().map(|(score, member)| (member.as_str(), score.0))
+    }
+
+    /// Membros entre `min` e `max` na ordem do texto, como o `ZRANGEBYLEX`. Como no Redis, todos
+    /// os membros devem ter o mesmo score; a faixa é buscada entre os que têm o score do
+    /// primeiro, e os demais ficam de fora.
+    pub fn range_by_lex(
+        &self,
+        min: LexBound,
+        max: LexBound,
+    ) -> impl DoubleEndedIterator<Item = (&str, f64)> {
+        let score = self.index.first().map_or(Score(0.0), |(score, _)| *score);
+        let key = |member: String| (score, member);
+        let start = match min {
+            LexBound::Min => Some(Bound::Included(key(String::new()))),
+            LexBound::Max => None,
+            LexBound::Included(member) => Some(Bound::Included(key(member))),
+            LexBound::Excluded(member) => Some(Bound::Excluded(key(member))),
+        };
+        let end = match max {
+            LexBound::Min => None,
+            LexBound::Max if score.0 == f64::INFINITY => Some(Bound::Unbounded),
+            LexBound::Max => Some(Bound::Excluded((Score(score.0.next_up()), String::new()))),
+            LexBound::Included(member) => Some(Bound::Included(key(member))),
+            LexBound::Excluded(member) => Some(Bound::Excluded(key(member))),
+        };
+        // O `BTreeSet::range` não aceita um início depois do fim, nem os dois exclusivos e
+        // iguais.
+        let range = start.zip(end).filter(|(start, end)| match (start, end) {
+            (Bound::Included(s), Bound::Included(e) | Bound::Excluded(e))
+            | (Bound::Excluded(s), Bound::Included(e)) => s <= e,
+            (Bound::Excluded(s), Bound::Excluded(e)) => s < e,
+            _ => true,
+        });
+        range
+            .map(|range| self.index.range(range))
+            .into_iter()
+            .flatten()
+            .map(|(score, member)| (member.as_str(), score.0))
+    }
+
+    /// Um lote do `ZSCAN`, retomado do cursor pelo índice por hash dos membros.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&str, f64)>) {
+        let (next, batch) = self.scores.scan(cursor, count);
+        (next, batch.into_iter().map(|(member, score)| (member, *score)).collect())
+    }
+
+    /// Membros em ordem crescente de score; empates ficam na ordem do texto do membro.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&str, f64)> + Clone {
+        self.index.iter().map(|(score, member)| (member.as_str(), score.0))
+    }
+}
+
+/// Os scores vão como texto porque o JSON do snapshot não representa `inf`. O `format_score`
+/// gera a menor representação que volta ao mesmo valor, então a carga é exata.
+impl Serialize for ZSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer
+            .collect_map(self.scores.iter().map(|(member, score)| (member, format_score(*score))))
+    }
+}
+
+impl TryFrom<HashMap<String, String>> for ZSet {
+    type Error = String;
+
+    fn try_from(scores: HashMap<String, String>) -> Result<Self, Self::Error> {
+        let mut zset = ZSet::default();
+        for (member, score) in scores {
+            match score.parse::<f64>() {
+                Ok(score) if !score.is_nan() => {
+                    zset.insert(member, score);
+                }
+                _ => return Err(format!("score inválido para o membro {:?}: {}", member, score)),
+            }
+        }
+        Ok(zset)
+    }
+}
+
+/// Formata um score como o Redis: inteiros sem casa decimal e os demais com a menor
+/// representação que volta ao mesmo `f64`, em notação científica só quando o expoente é
+/// grande (`1e+21`, `1.5e-7`), como o `fpconv_dtoa` usado pelo Redis.
+pub fn format_score(score: f64) -> String {
+    if score.is_infinite() {
+        return if score > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    // O Redis imprime como inteiro tudo o que cabe com folga num `long long`.
+    if score.fract() == 0.0 && score.abs() <= (i64::MAX / 2) as f64 {
+        return (score as i64).to_string();
+    }
+
+    // O `{:e}` do Rust já traz os dígitos mínimos: `d.ddde-x`.
+    let scientific = format!("{:e}", score.abs());
+    let (mantissa, exponent) = scientific.split_once('e').expect("formato do {:e}");
+    let exponent: i32 = exponent.parse().expect("expoente do {:e}");
+    let digits = mantissa.replace('.', "");
+    let len = digits.len() as i32;
+    // O valor é `digits * 10^shift`.
+    let shift = exponent - (len - 1);
+    let sign = if score < 0.0 { "-" } else { "" };
+
+    if shift >= 0 && exponent < len + 7 {
+        return format!("{}{}{}", sign, digits, "0".repeat(shift as usize));
+    }
+    if shift < 0 && (shift > -7 || exponent.abs() < 4) {
+        let point = len + shift;
+        return if point <= 0 {
+            format!("{}0.{}{}", sign, "0".repeat(-point as usize), digits)
+        } else {
+            let (integer, fraction) = digits.split_at(point as usize);
+            format!("{}{}.{}", sign, integer, fraction)
+        };
+    }
+    let (first, rest) = digits.split_at(1);
+    let fraction = if rest.is_empty() { String::new() } else { format!(".{}", rest) };
+    let exponent_sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}{}{}e{}{}", sign, first, fraction, exponent_sign, exponent.abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bound(value: f64, exclusive: bool) -> ScoreBound {
+        ScoreBound { value, exclusive }
+    }
+
+    /// Conjunto com os membros `m0`, `m1`, ... e score igual ao número de cada um.
+    fn numbered(len: usize) -> ZSet {
+        let mut zset = ZSet::default();
+        for i in 0..len {
+            zset.insert(format!("m{i}"), i as f64);
+        }
+        zset
+    }
+
+    #[test]
+    fn range_by_score_bounds() {
+        let zset = numbered(10);
+        let members = |min, max| -> Vec<&str> {
+            zset.range_by_score(min, max).map(|(member, _)| member).collect()
+        };
+        assert_eq!(members(bound(2.0, false), bound(4.0, false)), ["m2", "m3", "m4"]);
+        assert_eq!(members(bound(2.0, true), bound(4.0, true)), ["m3"]);
+        assert_eq!(members(bound(8.0, false), bound(f64::INFINITY, false)), ["m8", "m9"]);
+        assert!(members(bound(5.0, true), bound(5.0, false)).is_empty());
+        assert!(members(bound(f64::INFINITY, true), bound(f64::INFINITY, false)).is_empty());
+    }
+
+    #[test]
+    fn rank_on_a_hundred_thousand_members() {
+        let mut zset = numbered(100_000);
+        for i in [0, 1, 9_999, 50_000, 99_998, 99_999] {
+            assert_eq!(zset.rank(&format!("m{i}")), Some(i));
+        }
+        assert_eq!(zset.rank("missing"), None);
+        // Empates ficam na ordem do texto do membro.
+        zset.insert("m50000b".to_string(), 50_000.0);
+        zset.insert("m50000a".to_string(), 50_000.0);
+        assert_eq!(zset.rank("m50000"), Some(50_000));
+        assert_eq!(zset.rank("m50000a"), Some(50_001));
+        assert_eq!(zset.rank("m50000b"), Some(50_002));
+        assert_eq!(zset.rank("m99999"), Some(100_001));
+    }
+
+    #[test]
+    fn scan_resumes_from_the_cursor_after_removals() {
+        let mut zset = numbered(1_000);
+        let (mut cursor, first) = zset.scan(0, 100);
+        assert_eq!(first.len(), 100);
+        let mut seen: Vec<(String, f64)> =
+            first.into_iter().map(|(member, score)| (member.to_string(), score)).collect();
+        // Membros já retornados podem sair sem que a iteração perca os outros.
+        for (member, _) in &seen {
+            zset.remove(member);
+        }
+        while cursor != 0 {
+            let (next, batch) = zset.scan(cursor, 100);
+            assert!(batch.len() <= 100);
+            seen.extend(batch.into_iter().map(|(member, score)| (member.to_string(), score)));
+            cursor = next;
+        }
+        seen.sort_by(|a, b| a.1.total_cmp(&b.1));
+        assert_eq!(seen, (0..1_000).map(|i| (format!("m{i}"), i as f64)).collect::<Vec<_>>());
+    }
+}